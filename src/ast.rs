@@ -1,78 +1,1067 @@
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::ops::Index;
+use std::rc::Rc;
+
 use crate::scanner::{Token, TokenType};
+
+/// Identifies an expression within the [`Ast`] it was parsed into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+impl ExprId {
+    /// The position of the expression in its arena, for side tables indexed
+    /// by expression.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Where a local variable lives at runtime: the number of scopes between a
+/// use and the variable's declaration, and the variable's position among the
+/// ones declared in that scope.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+/// The region of source a node was parsed from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the node's first character.
+    pub start: usize,
+    /// Byte offset just past the node's last character.
+    pub end: usize,
+    /// The line and 1-based column the node starts at.
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// The span from the start of `first` to the end of `last`.
+    pub fn between(first: &Token, last: &Token) -> Self {
+        Span {
+            start: first.start,
+            end: last.end.max(first.start),
+            line: first.line,
+            column: first.column,
+        }
+    }
+}
+
+/// Every expression parsed from one source, stored side by side rather than
+/// boxed individually. Expressions refer to their subexpressions by
+/// [`ExprId`], and statements to their expressions the same way.
+#[derive(Default)]
+pub struct Ast<'a> {
+    expressions: Vec<Expression<'a>>,
+    /// Where each of `expressions` came from.
+    spans: Vec<Span>,
+}
+
+impl<'a> Ast<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add(&mut self, expr: Expression<'a>, span: Span) -> ExprId {
+        let id = u32::try_from(self.expressions.len()).expect("too many expressions");
+        self.expressions.push(expr);
+        self.spans.push(span);
+        ExprId(id)
+    }
+
+    /// The source an expression was parsed from.
+    pub fn span(&self, id: ExprId) -> Span {
+        self.spans[id.index()]
+    }
+
+    /// Swaps the expression `id` refers to for `expr`, returning the old one.
+    /// The new expression keeps the old one's span.
+    pub fn replace(&mut self, id: ExprId, expr: Expression<'a>) -> Expression<'a> {
+        std::mem::replace(&mut self.expressions[id.index()], expr)
+    }
+
+    /// Every expression's ID, in the order they were added. Expressions are
+    /// added after their subexpressions, so this visits operands first.
+    pub fn ids(&self) -> impl Iterator<Item = ExprId> {
+        (0..self.expressions.len() as u32).map(ExprId)
+    }
+
+    /// The leftmost token of an expression, which says where it starts.
+    pub fn first_token(&self, id: ExprId) -> &Token<'a> {
+        match &self[id] {
+            Expression::Assign { name, .. } | Expression::Variable { name, .. } => name,
+            Expression::Binary { l_expr, .. } | Expression::Logical { l_expr, .. } => {
+                self.first_token(*l_expr)
+            }
+            Expression::Call { callee, .. } => self.first_token(*callee),
+            Expression::Function { declaration } => &declaration.name,
+            Expression::Get { object, .. }
+            | Expression::Index { object, .. }
+            | Expression::Set { object, .. }
+            | Expression::SetIndex { object, .. } => self.first_token(*object),
+            Expression::Grouping { expr } => self.first_token(*expr),
+            Expression::List { bracket, .. } => bracket,
+            Expression::Named { name, .. } => name,
+            Expression::Spread { ellipsis, .. } => ellipsis,
+            Expression::Tuple { paren, elements } => match elements.first() {
+                Some(first) => self.first_token(*first),
+                None => paren,
+            },
+            Expression::Map { brace, .. } => brace,
+            Expression::Literal { token } => token,
+            Expression::Super { keyword, .. } | Expression::This { keyword, .. } => keyword,
+            Expression::Unary { operator, .. } => operator,
+        }
+    }
+
+    /// The token a statement starts at, which says where it is. Blocks and
+    /// `try` statements have none, because they're only there to hold other
+    /// statements.
+    pub fn statement_token<'s>(&'s self, stmt: &'s Stmt<'a>) -> Option<&'s Token<'a>> {
+        let token = match stmt {
+            Stmt::Block { .. } | Stmt::Try { .. } => return None,
+            Stmt::Break { keyword, .. }
+            | Stmt::Continue { keyword, .. }
+            | Stmt::Import { keyword, .. }
+            | Stmt::Return { keyword, .. }
+            | Stmt::Throw { keyword, .. } => keyword,
+            Stmt::Class { name, .. } | Stmt::ForIn { name, .. } | Stmt::Var { name, .. } => name,
+            Stmt::Destructure { paren, .. } => paren,
+            Stmt::Expression { expr, .. } | Stmt::Print { expr, .. } => self.first_token(*expr),
+            Stmt::Function { declaration, .. } => &declaration.name,
+            Stmt::If { condition, .. } | Stmt::While { condition, .. } => {
+                self.first_token(*condition)
+            }
+        };
+        Some(token)
+    }
+
+    pub fn len(&self) -> usize {
+        self.expressions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expressions.is_empty()
+    }
+}
+
+impl<'a> Index<ExprId> for Ast<'a> {
+    type Output = Expression<'a>;
+
+    fn index(&self, id: ExprId) -> &Expression<'a> {
+        &self.expressions[id.index()]
+    }
+}
+
+#[derive(Clone)]
 pub enum Expression<'a> {
+    Assign {
+        name: Token<'a>,
+        value: ExprId,
+        /// Where the variable is declared, filled in by the resolver. `None`
+        /// means the variable is global.
+        slot: Cell<Option<Slot>>,
+    },
     Binary {
-        l_expr: Box<Expression<'a>>,
+        l_expr: ExprId,
         operator: Token<'a>,
-        r_expr: Box<Expression<'a>>,
+        r_expr: ExprId,
+    },
+    Call {
+        callee: ExprId,
+        paren: Token<'a>,
+        arguments: Vec<ExprId>,
+    },
+    /// An anonymous function, `fun (a, b) { ... }`.
+    Function {
+        declaration: Rc<FunctionDeclaration<'a>>,
+    },
+    /// `object.name`, or `object?.name` when `optional`, which is nil
+    /// rather than an error if `object` is nil.
+    Get {
+        object: ExprId,
+        name: Token<'a>,
+        optional: bool,
     },
     Grouping {
-        expr: Box<Expression<'a>>,
+        expr: ExprId,
+    },
+    /// `object[index]`.
+    Index {
+        object: ExprId,
+        bracket: Token<'a>,
+        index: ExprId,
+    },
+    /// `[a, b, c]`.
+    List {
+        bracket: Token<'a>,
+        elements: Vec<ExprId>,
+    },
+    /// `{key: value, ...}`.
+    Map {
+        brace: Token<'a>,
+        entries: Vec<(ExprId, ExprId)>,
     },
     Literal {
         token: Token<'a>,
     },
+    Logical {
+        l_expr: ExprId,
+        operator: Token<'a>,
+        r_expr: ExprId,
+    },
+    Set {
+        object: ExprId,
+        name: Token<'a>,
+        value: ExprId,
+        /// For a compound assignment like `object.name += value`, the
+        /// operator that combines the old value with `value`. `object` is
+        /// still only evaluated once.
+        operator: Option<Token<'a>>,
+    },
+    /// `object[index] = value`.
+    SetIndex {
+        object: ExprId,
+        bracket: Token<'a>,
+        index: ExprId,
+        value: ExprId,
+        /// See `Expression::Set::operator`.
+        operator: Option<Token<'a>>,
+    },
+    Super {
+        keyword: Token<'a>,
+        method: Token<'a>,
+        /// See `Expression::Assign::slot`.
+        slot: Cell<Option<Slot>>,
+    },
+    This {
+        keyword: Token<'a>,
+        /// See `Expression::Assign::slot`.
+        slot: Cell<Option<Slot>>,
+    },
+    /// `name: value` as an argument, passed to the parameter called `name`.
+    Named {
+        name: Token<'a>,
+        value: ExprId,
+    },
+    /// `...list` as an argument, which passes each element of the list
+    /// as an argument of its own.
+    Spread {
+        ellipsis: Token<'a>,
+        expr: ExprId,
+    },
+    /// `(a, b)`, or the values after `return` in `return a, b;`, where
+    /// `paren` is the `return` keyword instead.
+    Tuple {
+        paren: Token<'a>,
+        elements: Vec<ExprId>,
+    },
     Unary {
         operator: Token<'a>,
-        r_expr: Box<Expression<'a>>,
+        r_expr: ExprId,
+    },
+    Variable {
+        name: Token<'a>,
+        /// See `Expression::Assign::slot`.
+        slot: Cell<Option<Slot>>,
     },
 }
 
-pub trait Visitor {
+/// A statement. Each variant's `span` is the source it was parsed from.
+pub enum Stmt<'a> {
+    Block {
+        statements: Vec<Stmt<'a>>,
+        span: Span,
+    },
+    Break {
+        keyword: Token<'a>,
+        span: Span,
+    },
+    Class {
+        name: Token<'a>,
+        superclass: Option<ExprId>,
+        /// Classes listed after `with`, whose methods are copied in.
+        mixins: Vec<ExprId>,
+        methods: Vec<Rc<FunctionDeclaration<'a>>>,
+        /// Methods declared with a leading `class`, called on the class
+        /// itself.
+        class_methods: Vec<Rc<FunctionDeclaration<'a>>>,
+        span: Span,
+    },
+    Continue {
+        keyword: Token<'a>,
+        span: Span,
+    },
+    /// `var (a, b) = tuple;`, which declares a variable for each element.
+    Destructure {
+        paren: Token<'a>,
+        names: Vec<Token<'a>>,
+        initializer: ExprId,
+        is_const: bool,
+        span: Span,
+    },
+    Expression {
+        expr: ExprId,
+        span: Span,
+    },
+    /// `for (name in iterable) body`.
+    ForIn {
+        name: Token<'a>,
+        keyword: Token<'a>,
+        iterable: ExprId,
+        body: Box<Stmt<'a>>,
+        span: Span,
+    },
+    Function {
+        declaration: Rc<FunctionDeclaration<'a>>,
+        span: Span,
+    },
+    If {
+        condition: ExprId,
+        then_branch: Box<Stmt<'a>>,
+        else_branch: Option<Box<Stmt<'a>>>,
+        span: Span,
+    },
+    /// `import "path";` or `import "path" as name;`.
+    Import {
+        keyword: Token<'a>,
+        path: Token<'a>,
+        alias: Option<Token<'a>>,
+        span: Span,
+    },
+    Print {
+        expr: ExprId,
+        span: Span,
+    },
+    Return {
+        keyword: Token<'a>,
+        value: Option<ExprId>,
+        span: Span,
+    },
+    Throw {
+        keyword: Token<'a>,
+        value: ExprId,
+        span: Span,
+    },
+    /// `try { body } catch (name) { handler }`.
+    Try {
+        body: Vec<Stmt<'a>>,
+        name: Token<'a>,
+        handler: Vec<Stmt<'a>>,
+        span: Span,
+    },
+    Var {
+        name: Token<'a>,
+        initializer: Option<ExprId>,
+        /// Declared with `const`, so it can never be assigned to again.
+        is_const: bool,
+        span: Span,
+    },
+    While {
+        condition: ExprId,
+        body: Box<Stmt<'a>>,
+        /// A `for` loop's increment clause, run after the body on every
+        /// iteration including ones cut short by `continue`.
+        increment: Option<ExprId>,
+        span: Span,
+    },
+}
+
+impl Stmt<'_> {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Block { span, .. }
+            | Stmt::Break { span, .. }
+            | Stmt::Class { span, .. }
+            | Stmt::Continue { span, .. }
+            | Stmt::Destructure { span, .. }
+            | Stmt::Expression { span, .. }
+            | Stmt::ForIn { span, .. }
+            | Stmt::Function { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::Import { span, .. }
+            | Stmt::Print { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::Throw { span, .. }
+            | Stmt::Try { span, .. }
+            | Stmt::Var { span, .. }
+            | Stmt::While { span, .. } => *span,
+        }
+    }
+}
+
+/// A function's signature and body, shared between the syntax tree and
+/// every runtime function value created from it.
+pub struct FunctionDeclaration<'a> {
+    /// The function's name, or for an anonymous function its `fun` keyword.
+    pub name: Token<'a>,
+    pub params: Vec<Token<'a>>,
+    /// Declared like `fun f(a, ...rest)`, so the last parameter is a list
+    /// of any arguments after the others.
+    pub variadic: bool,
+    pub body: Vec<Stmt<'a>>,
+    /// A method declared without a parameter list, which runs as soon as
+    /// it's accessed.
+    pub is_getter: bool,
+}
+
+impl FunctionDeclaration<'_> {
+    pub fn is_anonymous(&self) -> bool {
+        self.name.token_type == TokenType::Fun
+    }
+
+    /// The parameters as they're written, with `...` before a rest
+    /// parameter.
+    pub fn written_params(&self) -> Vec<String> {
+        let last = self.params.len().wrapping_sub(1);
+        self.params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| {
+                if self.variadic && i == last {
+                    format!("...{}", param.lexeme)
+                } else {
+                    param.lexeme.to_string()
+                }
+            })
+            .collect()
+    }
+}
+
+pub trait Visitor<'a> {
     type E;
-    fn visit_expression(&self, expr: &Expression) -> Self::E;
+    fn visit_expression(&self, expr: ExprId) -> Self::E;
+}
+
+/// Like [`Visitor`], for statements.
+pub trait StmtVisitor<'a> {
+    type S;
+    fn visit_statement(&self, stmt: &Stmt<'a>) -> Self::S;
+}
+
+/// A pass over whole programs that can change its own state as it goes,
+/// unlike [`Visitor`]. By default each method walks into the node's
+/// children with the matching `walk_*` function, so a pass only overrides
+/// the nodes it cares about, and calls `walk_*` itself to keep descending
+/// below them.
+pub trait VisitorMut<'a> {
+    fn visit_expr(&mut self, ast: &Ast<'a>, expr: ExprId) {
+        walk_expr(self, ast, expr);
+    }
+
+    fn visit_stmt(&mut self, ast: &Ast<'a>, stmt: &Stmt<'a>) {
+        walk_stmt(self, ast, stmt);
+    }
+
+    /// Visits a named function, method or anonymous function.
+    fn visit_function(&mut self, ast: &Ast<'a>, declaration: &FunctionDeclaration<'a>) {
+        walk_function(self, ast, declaration);
+    }
+}
+
+/// Visits each subexpression of `expr`, in source order.
+pub fn walk_expr<'a, V>(visitor: &mut V, ast: &Ast<'a>, expr: ExprId)
+where
+    V: VisitorMut<'a> + ?Sized,
+{
+    match &ast[expr] {
+        Expression::Assign { value, .. }
+        | Expression::Named { value, .. }
+        | Expression::Grouping { expr: value }
+        | Expression::Spread { expr: value, .. }
+        | Expression::Get { object: value, .. }
+        | Expression::Unary { r_expr: value, .. } => visitor.visit_expr(ast, *value),
+        Expression::Binary { l_expr, r_expr, .. } | Expression::Logical { l_expr, r_expr, .. } => {
+            visitor.visit_expr(ast, *l_expr);
+            visitor.visit_expr(ast, *r_expr);
+        }
+        Expression::Call {
+            callee, arguments, ..
+        } => {
+            visitor.visit_expr(ast, *callee);
+            for argument in arguments {
+                visitor.visit_expr(ast, *argument);
+            }
+        }
+        Expression::Function { declaration } => visitor.visit_function(ast, declaration),
+        Expression::Index { object, index, .. } => {
+            visitor.visit_expr(ast, *object);
+            visitor.visit_expr(ast, *index);
+        }
+        Expression::List { elements, .. } | Expression::Tuple { elements, .. } => {
+            for element in elements {
+                visitor.visit_expr(ast, *element);
+            }
+        }
+        Expression::Map { entries, .. } => {
+            for (key, value) in entries {
+                visitor.visit_expr(ast, *key);
+                visitor.visit_expr(ast, *value);
+            }
+        }
+        Expression::Set { object, value, .. } => {
+            visitor.visit_expr(ast, *object);
+            visitor.visit_expr(ast, *value);
+        }
+        Expression::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            visitor.visit_expr(ast, *object);
+            visitor.visit_expr(ast, *index);
+            visitor.visit_expr(ast, *value);
+        }
+        Expression::Literal { .. }
+        | Expression::Super { .. }
+        | Expression::This { .. }
+        | Expression::Variable { .. } => {}
+    }
 }
 
-pub struct AstPrinter;
+/// Visits the statements, expressions and functions directly inside `stmt`,
+/// in source order.
+pub fn walk_stmt<'a, V>(visitor: &mut V, ast: &Ast<'a>, stmt: &Stmt<'a>)
+where
+    V: VisitorMut<'a> + ?Sized,
+{
+    match stmt {
+        Stmt::Block { statements, .. } => {
+            for statement in statements {
+                visitor.visit_stmt(ast, statement);
+            }
+        }
+        Stmt::Class {
+            superclass,
+            mixins,
+            methods,
+            class_methods,
+            ..
+        } => {
+            for class in superclass.iter().chain(mixins) {
+                visitor.visit_expr(ast, *class);
+            }
+            for method in methods.iter().chain(class_methods) {
+                visitor.visit_function(ast, method);
+            }
+        }
+        Stmt::Destructure { initializer, .. } => visitor.visit_expr(ast, *initializer),
+        Stmt::Expression { expr, .. }
+        | Stmt::Print { expr, .. }
+        | Stmt::Throw { value: expr, .. } => visitor.visit_expr(ast, *expr),
+        Stmt::ForIn { iterable, body, .. } => {
+            visitor.visit_expr(ast, *iterable);
+            visitor.visit_stmt(ast, body);
+        }
+        Stmt::Function { declaration, .. } => visitor.visit_function(ast, declaration),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr(ast, *condition);
+            visitor.visit_stmt(ast, then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(ast, else_branch);
+            }
+        }
+        Stmt::Return { value, .. }
+        | Stmt::Var {
+            initializer: value, ..
+        } => {
+            if let Some(value) = value {
+                visitor.visit_expr(ast, *value);
+            }
+        }
+        Stmt::Try { body, handler, .. } => {
+            for statement in body.iter().chain(handler) {
+                visitor.visit_stmt(ast, statement);
+            }
+        }
+        Stmt::While {
+            condition,
+            body,
+            increment,
+            ..
+        } => {
+            visitor.visit_expr(ast, *condition);
+            visitor.visit_stmt(ast, body);
+            if let Some(increment) = increment {
+                visitor.visit_expr(ast, *increment);
+            }
+        }
+        Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Import { .. } => {}
+    }
+}
 
-impl AstPrinter {
-    pub fn print(&self, expr: &Expression) -> String {
+/// Visits each statement in the body of `declaration`.
+pub fn walk_function<'a, V>(visitor: &mut V, ast: &Ast<'a>, declaration: &FunctionDeclaration<'a>)
+where
+    V: VisitorMut<'a> + ?Sized,
+{
+    for statement in &declaration.body {
+        visitor.visit_stmt(ast, statement);
+    }
+}
+
+pub struct AstPrinter<'r, 'a> {
+    ast: &'r Ast<'a>,
+}
+
+impl<'r, 'a> AstPrinter<'r, 'a> {
+    pub fn new(ast: &'r Ast<'a>) -> Self {
+        AstPrinter { ast }
+    }
+
+    pub fn print(&self, expr: ExprId) -> String {
         self.visit_expression(expr)
     }
+
+    pub fn print_statement(&self, stmt: &Stmt<'a>) -> String {
+        self.visit_statement(stmt)
+    }
+
+    /// Prints each of a program's statements on a line of its own.
+    pub fn print_program(&self, statements: &[Stmt<'a>]) -> String {
+        statements
+            .iter()
+            .map(|statement| format!("{}\n", self.visit_statement(statement)))
+            .collect()
+    }
+}
+
+impl<'a> StmtVisitor<'a> for AstPrinter<'_, 'a> {
+    type S = String;
+    fn visit_statement(&self, stmt: &Stmt<'a>) -> Self::S {
+        let statements = |statements: &[Stmt<'a>]| {
+            statements
+                .iter()
+                .map(|s| format!(" {}", self.visit_statement(s)))
+                .collect::<String>()
+        };
+        let optional = |expr: &Option<ExprId>| {
+            expr.map(|e| format!(" {}", self.visit_expression(e)))
+                .unwrap_or_default()
+        };
+        let function = |declaration: &FunctionDeclaration<'a>| {
+            format!(
+                "(Fun {} ({}){})",
+                declaration.name.lexeme,
+                declaration.written_params().join(" "),
+                statements(&declaration.body)
+            )
+        };
+
+        match stmt {
+            Stmt::Block {
+                statements: body, ..
+            } => format!("(Block{})", statements(body)),
+            Stmt::Break { .. } => "(Break)".to_string(),
+            Stmt::Class {
+                name,
+                superclass,
+                mixins,
+                methods,
+                class_methods,
+                ..
+            } => format!(
+                "(Class {}{}{}{}{})",
+                name.lexeme,
+                superclass
+                    .map(|s| format!(" < {}", self.visit_expression(s)))
+                    .unwrap_or_default(),
+                mixins
+                    .iter()
+                    .map(|m| format!(" with {}", self.visit_expression(*m)))
+                    .collect::<String>(),
+                methods
+                    .iter()
+                    .map(|m| format!(" {}", function(m)))
+                    .collect::<String>(),
+                class_methods
+                    .iter()
+                    .map(|m| format!(" (Class {})", function(m)))
+                    .collect::<String>()
+            ),
+            Stmt::Continue { .. } => "(Continue)".to_string(),
+            Stmt::Expression { expr, .. } => {
+                format!("(Expression {})", self.visit_expression(*expr))
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => format!(
+                "(ForIn {} {} {})",
+                name.lexeme,
+                self.visit_expression(*iterable),
+                self.visit_statement(body)
+            ),
+            Stmt::Function { declaration, .. } => function(declaration),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => format!(
+                "(If {} {}{})",
+                self.visit_expression(*condition),
+                self.visit_statement(then_branch),
+                else_branch
+                    .as_ref()
+                    .map(|s| format!(" {}", self.visit_statement(s)))
+                    .unwrap_or_default()
+            ),
+            Stmt::Import { path, alias, .. } => format!(
+                "(Import {}{})",
+                path.lexeme,
+                alias
+                    .as_ref()
+                    .map(|a| format!(" as {}", a.lexeme))
+                    .unwrap_or_default()
+            ),
+            Stmt::Print { expr, .. } => format!("(Print {})", self.visit_expression(*expr)),
+            Stmt::Return { value, .. } => format!("(Return{})", optional(value)),
+            Stmt::Throw { value, .. } => format!("(Throw {})", self.visit_expression(*value)),
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => format!(
+                "(Try (Block{}) {} (Block{}))",
+                statements(body),
+                name.lexeme,
+                statements(handler)
+            ),
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+                ..
+            } => format!(
+                "({} {}{})",
+                if *is_const { "Const" } else { "Var" },
+                name.lexeme,
+                optional(initializer)
+            ),
+            Stmt::Destructure {
+                names,
+                initializer,
+                is_const,
+                ..
+            } => format!(
+                "({} ({}) {})",
+                if *is_const { "Const" } else { "Var" },
+                names.iter().map(|n| n.lexeme).collect::<Vec<_>>().join(" "),
+                self.visit_expression(*initializer)
+            ),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => format!(
+                "(While {} {}{})",
+                self.visit_expression(*condition),
+                self.visit_statement(body),
+                optional(increment)
+            ),
+        }
+    }
 }
 
-impl Visitor for AstPrinter {
+impl<'a> Visitor<'a> for AstPrinter<'_, 'a> {
     type E = String;
-    fn visit_expression(&self, e: &Expression) -> Self::E {
-        match e {
+    fn visit_expression(&self, e: ExprId) -> Self::E {
+        match &self.ast[e] {
+            Expression::Assign { name, value, .. } => {
+                format!("(Assign {} {})", name.lexeme, self.visit_expression(*value))
+            }
             Expression::Binary {
                 l_expr,
                 operator,
                 r_expr,
             } => format!(
-                "(Binary {:?} {} {})",
+                "(Binary {} {} {})",
+                operator.lexeme,
+                self.visit_expression(*l_expr),
+                self.visit_expression(*r_expr)
+            ),
+            Expression::Call {
+                callee, arguments, ..
+            } => format!(
+                "(Call {}{})",
+                self.visit_expression(*callee),
+                arguments
+                    .iter()
+                    .map(|a| format!(" {}", self.visit_expression(*a)))
+                    .collect::<String>()
+            ),
+            Expression::Function { declaration } => format!(
+                "(Function{})",
+                declaration
+                    .written_params()
+                    .iter()
+                    .map(|p| format!(" {}", p))
+                    .collect::<String>()
+            ),
+            Expression::Get {
+                object,
+                name,
+                optional,
+            } => format!(
+                "({} {} {})",
+                if *optional { "OptionalGet" } else { "Get" },
+                self.visit_expression(*object),
+                name.lexeme
+            ),
+            Expression::Grouping { expr } => format!("(Grouping {})", self.visit_expression(*expr)),
+            Expression::Index { object, index, .. } => format!(
+                "(Index {} {})",
+                self.visit_expression(*object),
+                self.visit_expression(*index)
+            ),
+            Expression::List { elements, .. } => format!(
+                "(List{})",
+                elements
+                    .iter()
+                    .map(|e| format!(" {}", self.visit_expression(*e)))
+                    .collect::<String>()
+            ),
+            Expression::Literal { token } => format!("(Literal {})", literal_text(token)),
+            Expression::Named { name, value } => {
+                format!("(Named {} {})", name.lexeme, self.visit_expression(*value))
+            }
+            Expression::Spread { expr, .. } => {
+                format!("(Spread {})", self.visit_expression(*expr))
+            }
+            Expression::Tuple { elements, .. } => format!(
+                "(Tuple{})",
+                elements
+                    .iter()
+                    .map(|e| format!(" {}", self.visit_expression(*e)))
+                    .collect::<String>()
+            ),
+            Expression::Logical {
+                l_expr,
                 operator,
-                self.visit_expression(l_expr),
-                self.visit_expression(r_expr)
+                r_expr,
+            } => format!(
+                "(Logical {} {} {})",
+                operator.lexeme,
+                self.visit_expression(*l_expr),
+                self.visit_expression(*r_expr)
+            ),
+            Expression::Set {
+                object,
+                name,
+                value,
+                operator,
+            } => format!(
+                "(Set {} {}{} {})",
+                self.visit_expression(*object),
+                name.lexeme,
+                compound(operator),
+                self.visit_expression(*value)
             ),
-            Expression::Grouping { expr } => format!("(Grouping {})", self.visit_expression(expr)),
-            Expression::Literal { token } => format!("(Literal {:?})", token),
+            Expression::Map { entries, .. } => format!(
+                "(Map{})",
+                entries
+                    .iter()
+                    .map(|(k, v)| format!(
+                        " {} {}",
+                        self.visit_expression(*k),
+                        self.visit_expression(*v)
+                    ))
+                    .collect::<String>()
+            ),
+            Expression::SetIndex {
+                object,
+                index,
+                value,
+                operator,
+                ..
+            } => format!(
+                "(SetIndex {} {}{} {})",
+                self.visit_expression(*object),
+                self.visit_expression(*index),
+                compound(operator),
+                self.visit_expression(*value)
+            ),
+            Expression::Super { method, .. } => format!("(Super {})", method.lexeme),
+            Expression::This { .. } => "(This)".to_string(),
             Expression::Unary { operator, r_expr } => {
-                format!("(Unary {:?} {})", operator, self.visit_expression(r_expr))
+                format!(
+                    "(Unary {} {})",
+                    operator.lexeme,
+                    self.visit_expression(*r_expr)
+                )
             }
+            Expression::Variable { name, .. } => format!("(Variable {})", name.lexeme),
         }
     }
 }
 
+/// ` +=` after the target of a compound assignment with `+`, or nothing
+/// for a plain one.
+fn compound(operator: &Option<Token>) -> String {
+    match operator {
+        Some(operator) => format!(" {}=", operator.lexeme),
+        None => String::new(),
+    }
+}
+
+/// A literal's value as it would be written in Lox. The optimizer makes
+/// literals out of other tokens, so the lexeme can't be used.
+pub(crate) fn literal_text(token: &Token) -> String {
+    match &token.token_type {
+        TokenType::Number { number } => number.to_string(),
+        TokenType::StringLiteral { literal } => format!("\"{}\"", literal),
+        TokenType::True => "true".to_string(),
+        TokenType::False => "false".to_string(),
+        _ => "nil".to_string(),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn test_ast() {
-        let expr = Expression::Binary {
-            l_expr: Box::new(Expression::Unary {
+        let mut ast = Ast::new();
+        let number = ast.add(
+            Expression::Literal {
+                token: Token::new(TokenType::Number { number: 123_f64 }, "123", 1),
+            },
+            Span::default(),
+        );
+        let negated = ast.add(
+            Expression::Unary {
                 operator: Token::new(TokenType::Minus, "-", 1),
-                r_expr: Box::new(Expression::Literal {
-                    token: Token::new(TokenType::Number { number: 123_f64 }, "123", 1),
-                }),
-            }),
-            operator: Token::new(TokenType::Star, "*", 1),
-            r_expr: Box::new(Expression::Grouping {
-                expr: Box::new(Expression::Literal {
-                    token: Token::new(TokenType::Number { number: 45.67 }, "45.67", 1),
-                }),
-            }),
-        };
-        println!("{}", AstPrinter {}.print(&expr));
+                r_expr: number,
+            },
+            Span::default(),
+        );
+        let number = ast.add(
+            Expression::Literal {
+                token: Token::new(TokenType::Number { number: 45.67 }, "45.67", 1),
+            },
+            Span::default(),
+        );
+        let grouping = ast.add(Expression::Grouping { expr: number }, Span::default());
+        let expr = ast.add(
+            Expression::Binary {
+                l_expr: negated,
+                operator: Token::new(TokenType::Star, "*", 1),
+                r_expr: grouping,
+            },
+            Span::default(),
+        );
+        println!("{}", AstPrinter::new(&ast).print(expr));
+    }
+
+    #[test]
+    fn test_print_statement() {
+        let source = "fun f(a, b) { if (a) return b; else { var c = 1; } }\nwhile (true) break;";
+        let mut scanner = crate::scanner::Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let parser = crate::parser::Parser::new(tokens);
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        let printer = AstPrinter::new(&ast);
+        let printed: Vec<String> = statements
+            .iter()
+            .map(|s| printer.print_statement(s))
+            .collect();
+        assert_eq!(
+            printed,
+            [
+                "(Fun f (a b) (If (Variable a) (Return (Variable b)) (Block (Var c (Literal 1)))))",
+                "(While (Literal true) (Break))",
+            ]
+        );
+        assert_eq!(
+            printer.print_program(&statements),
+            printed.join("\n") + "\n"
+        );
+    }
+
+    #[test]
+    fn test_print_program() {
+        let source = "var a;\nclass B < A { m(x) { return super.m(x); } }\n\
+            for (var i = 0; i < 2; i = i + 1) print i;\n{ a = B(); a.m(1); }";
+        let mut scanner = crate::scanner::Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let parser = crate::parser::Parser::new(tokens);
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        assert_eq!(
+            AstPrinter::new(&ast).print_program(&statements),
+            "(Var a)\n\
+            (Class B < (Variable A) (Fun m (x) (Return (Call (Super m) (Variable x)))))\n\
+            (Block (Var i (Literal 0)) (While (Binary < (Variable i) (Literal 2)) \
+            (Print (Variable i)) (Assign i (Binary + (Variable i) (Literal 1)))))\n\
+            (Block (Expression (Assign a (Call (Variable B)))) \
+            (Expression (Call (Get (Variable a) m) (Literal 1))))\n"
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let source = "print -a * (b + 1);\nfor (;;) { f(x)[0]; }";
+        let mut scanner = crate::scanner::Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let parser = crate::parser::Parser::new(tokens);
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        let text = |span: Span| &source[span.start..span.end];
+
+        assert_eq!(text(statements[0].span()), "print -a * (b + 1);");
+        assert_eq!(text(statements[1].span()), "for (;;) { f(x)[0]; }");
+        assert_eq!(statements[1].span().line, 2);
+        let spans: Vec<&str> = ast.ids().map(|id| text(ast.span(id))).collect();
+        assert!(spans.contains(&"-a * (b + 1)"));
+        assert!(spans.contains(&"(b + 1)"));
+        assert!(spans.contains(&"f(x)[0]"));
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        /// Records the variables read, skipping the bodies of functions.
+        #[derive(Default)]
+        struct Reads {
+            names: Vec<String>,
+        }
+
+        impl<'a> VisitorMut<'a> for Reads {
+            fn visit_expr(&mut self, ast: &Ast<'a>, expr: ExprId) {
+                if let Expression::Variable { name, .. } = &ast[expr] {
+                    self.names.push(name.lexeme.to_string());
+                }
+                walk_expr(self, ast, expr);
+            }
+
+            fn visit_function(&mut self, _: &Ast<'a>, _: &FunctionDeclaration<'a>) {}
+        }
+
+        let source =
+            "var a = b + [c, {d: e}]; fun f() { print g; }\nwhile (h) { i.j = k(l, ...m); }";
+        let mut scanner = crate::scanner::Scanner::new(source);
+        let parser = crate::parser::Parser::new(scanner.scan_tokens().unwrap());
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        let mut reads = Reads::default();
+        for statement in &statements {
+            reads.visit_stmt(&ast, statement);
+        }
+        assert_eq!(reads.names, ["b", "c", "d", "e", "h", "i", "k", "l", "m"]);
     }
 }
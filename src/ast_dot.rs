@@ -0,0 +1,404 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::Write;
+
+use crate::ast::{literal_text, Ast, ExprId, Expression, FunctionDeclaration, Stmt, Visitor};
+use crate::scanner::Token;
+
+/// Draws a parsed program as a Graphviz graph, one box per node of the tree
+/// with edges labelled by the child's role. Render it with
+/// `dot -Tsvg ast.dot > ast.svg`.
+pub struct AstDotPrinter<'r, 'a> {
+    ast: &'r Ast<'a>,
+    out: RefCell<String>,
+    next_node: Cell<usize>,
+}
+
+impl<'r, 'a> AstDotPrinter<'r, 'a> {
+    pub fn new(ast: &'r Ast<'a>) -> Self {
+        AstDotPrinter {
+            ast,
+            out: RefCell::new(String::new()),
+            next_node: Cell::new(0),
+        }
+    }
+
+    /// The graph of a program's statements, hanging off a root node.
+    pub fn print(&self, statements: &[Stmt]) -> String {
+        self.out.replace(String::from(
+            "digraph ast {\n  node [shape=box, fontname=\"monospace\"];\n",
+        ));
+        self.next_node.set(0);
+
+        let root = self.node("Program");
+        self.statements(root, "", statements);
+
+        let mut out = self.out.take();
+        out.push_str("}\n");
+        out
+    }
+
+    /// Adds a node and returns its ID.
+    fn node(&self, label: &str) -> usize {
+        let id = self.next_node.get();
+        self.next_node.set(id + 1);
+        let _ = writeln!(
+            self.out.borrow_mut(),
+            "  n{} [label=\"{}\"];",
+            id,
+            escape(label)
+        );
+        id
+    }
+
+    fn edge(&self, from: usize, to: usize, label: &str) {
+        let mut out = self.out.borrow_mut();
+        if label.is_empty() {
+            let _ = writeln!(out, "  n{} -> n{};", from, to);
+        } else {
+            let _ = writeln!(out, "  n{} -> n{} [label=\"{}\"];", from, to, escape(label));
+        }
+    }
+
+    fn child(&self, parent: usize, label: &str, expr: ExprId) {
+        let child = self.visit_expression(expr);
+        self.edge(parent, child, label);
+    }
+
+    fn children(&self, parent: usize, label: &str, exprs: &[ExprId]) {
+        for (i, expr) in exprs.iter().enumerate() {
+            self.child(parent, &format!("{}{}", label, i), *expr);
+        }
+    }
+
+    fn statements(&self, parent: usize, label: &str, statements: &[Stmt]) {
+        for (i, statement) in statements.iter().enumerate() {
+            let child = self.statement(statement);
+            self.edge(parent, child, &format!("{}{}", label, i));
+        }
+    }
+
+    fn function(&self, kind: &str, declaration: &FunctionDeclaration) -> usize {
+        let name = if declaration.is_anonymous() {
+            String::new()
+        } else {
+            format!(" {}", declaration.name.lexeme)
+        };
+        let params = declaration.written_params();
+        let node = self.node(&format!("{}{}({})", kind, name, params.join(", ")));
+        self.statements(node, "", &declaration.body);
+        node
+    }
+
+    fn statement(&self, stmt: &Stmt) -> usize {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                let node = self.node("Block");
+                self.statements(node, "", statements);
+                node
+            }
+            Stmt::Break { .. } => self.node("Break"),
+            Stmt::Class {
+                name,
+                superclass,
+                mixins,
+                methods,
+                class_methods,
+                ..
+            } => {
+                let node = self.node(&format!("Class {}", name.lexeme));
+                if let Some(superclass) = superclass {
+                    self.child(node, "superclass", *superclass);
+                }
+                self.children(node, "mixin ", mixins);
+                for method in methods {
+                    let child = self.function("Method", method);
+                    self.edge(node, child, "");
+                }
+                for method in class_methods {
+                    let child = self.function("Class method", method);
+                    self.edge(node, child, "");
+                }
+                node
+            }
+            Stmt::Continue { .. } => self.node("Continue"),
+            Stmt::Expression { expr, .. } => {
+                let node = self.node("Expression");
+                self.child(node, "", *expr);
+                node
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                let node = self.node(&format!("For {} in", name.lexeme));
+                self.child(node, "iterable", *iterable);
+                let body = self.statement(body);
+                self.edge(node, body, "body");
+                node
+            }
+            Stmt::Function { declaration, .. } => self.function("Function", declaration),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let node = self.node("If");
+                self.child(node, "condition", *condition);
+                let then_branch = self.statement(then_branch);
+                self.edge(node, then_branch, "then");
+                if let Some(else_branch) = else_branch {
+                    let else_branch = self.statement(else_branch);
+                    self.edge(node, else_branch, "else");
+                }
+                node
+            }
+            Stmt::Import { path, alias, .. } => match alias {
+                Some(alias) => self.node(&format!("Import {} as {}", path.lexeme, alias.lexeme)),
+                None => self.node(&format!("Import {}", path.lexeme)),
+            },
+            Stmt::Print { expr, .. } => {
+                let node = self.node("Print");
+                self.child(node, "", *expr);
+                node
+            }
+            Stmt::Return { value, .. } => {
+                let node = self.node("Return");
+                if let Some(value) = value {
+                    self.child(node, "", *value);
+                }
+                node
+            }
+            Stmt::Throw { value, .. } => {
+                let node = self.node("Throw");
+                self.child(node, "", *value);
+                node
+            }
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                let node = self.node(&format!("Try, catch ({})", name.lexeme));
+                self.statements(node, "try ", body);
+                self.statements(node, "catch ", handler);
+                node
+            }
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+                ..
+            } => {
+                let keyword = if *is_const { "Const" } else { "Var" };
+                let node = self.node(&format!("{} {}", keyword, name.lexeme));
+                if let Some(initializer) = initializer {
+                    self.child(node, "", *initializer);
+                }
+                node
+            }
+            Stmt::Destructure {
+                names,
+                initializer,
+                is_const,
+                ..
+            } => {
+                let keyword = if *is_const { "Const" } else { "Var" };
+                let names: Vec<&str> = names.iter().map(|n| n.lexeme).collect();
+                let node = self.node(&format!("{} ({})", keyword, names.join(", ")));
+                self.child(node, "", *initializer);
+                node
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                let node = self.node("While");
+                self.child(node, "condition", *condition);
+                let body = self.statement(body);
+                self.edge(node, body, "body");
+                if let Some(increment) = increment {
+                    self.child(node, "increment", *increment);
+                }
+                node
+            }
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for AstDotPrinter<'_, 'a> {
+    /// The ID of the node drawn for the expression.
+    type E = usize;
+    fn visit_expression(&self, e: ExprId) -> Self::E {
+        match &self.ast[e] {
+            Expression::Assign { name, value, .. } => {
+                let node = self.node(&format!("Assign {}", name.lexeme));
+                self.child(node, "", *value);
+                node
+            }
+            Expression::Binary {
+                l_expr,
+                operator,
+                r_expr,
+            }
+            | Expression::Logical {
+                l_expr,
+                operator,
+                r_expr,
+            } => {
+                let node = self.node(operator.lexeme);
+                self.child(node, "left", *l_expr);
+                self.child(node, "right", *r_expr);
+                node
+            }
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                let node = self.node("Call");
+                self.child(node, "callee", *callee);
+                self.children(node, "arg ", arguments);
+                node
+            }
+            Expression::Function { declaration } => self.function("Function", declaration),
+            Expression::Get {
+                object,
+                name,
+                optional,
+            } => {
+                let dot = if *optional { "?." } else { "." };
+                let node = self.node(&format!("{}{}", dot, name.lexeme));
+                self.child(node, "object", *object);
+                node
+            }
+            Expression::Grouping { expr } => {
+                let node = self.node("( )");
+                self.child(node, "", *expr);
+                node
+            }
+            Expression::Index { object, index, .. } => {
+                let node = self.node("[ ]");
+                self.child(node, "object", *object);
+                self.child(node, "index", *index);
+                node
+            }
+            Expression::List { elements, .. } => {
+                let node = self.node("List");
+                self.children(node, "", elements);
+                node
+            }
+            Expression::Named { name, value } => {
+                let node = self.node(&format!("{}:", name.lexeme));
+                self.child(node, "", *value);
+                node
+            }
+            Expression::Spread { expr, .. } => {
+                let node = self.node("...");
+                self.child(node, "", *expr);
+                node
+            }
+            Expression::Tuple { elements, .. } => {
+                let node = self.node("Tuple");
+                self.children(node, "", elements);
+                node
+            }
+            Expression::Map { entries, .. } => {
+                let node = self.node("Map");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    self.child(node, &format!("key {}", i), *key);
+                    self.child(node, &format!("value {}", i), *value);
+                }
+                node
+            }
+            Expression::Literal { token } => self.node(&literal_text(token)),
+            Expression::Set {
+                object,
+                name,
+                value,
+                operator,
+            } => {
+                let node = self.node(&format!(".{} {}", name.lexeme, assignment(operator)));
+                self.child(node, "object", *object);
+                self.child(node, "value", *value);
+                node
+            }
+            Expression::SetIndex {
+                object,
+                index,
+                value,
+                operator,
+                ..
+            } => {
+                let node = self.node(&format!("[ ] {}", assignment(operator)));
+                self.child(node, "object", *object);
+                self.child(node, "index", *index);
+                self.child(node, "value", *value);
+                node
+            }
+            Expression::Super { method, .. } => self.node(&format!("super.{}", method.lexeme)),
+            Expression::This { .. } => self.node("this"),
+            Expression::Unary { operator, r_expr } => {
+                let node = self.node(operator.lexeme);
+                self.child(node, "", *r_expr);
+                node
+            }
+            Expression::Variable { name, .. } => self.node(name.lexeme),
+        }
+    }
+}
+
+/// `=`, or `+=` for a compound assignment with `+`.
+fn assignment(operator: &Option<Token>) -> String {
+    match operator {
+        Some(operator) => format!("{}=", operator.lexeme),
+        None => "=".to_string(),
+    }
+}
+
+/// `label` with the characters that would end or break a quoted DOT string
+/// escaped.
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_print_dot() {
+        let mut scanner = Scanner::new("print -1 + \"a\\\"\";");
+        let tokens = scanner.scan_tokens().unwrap();
+        let parser = Parser::new(tokens);
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        assert_eq!(
+            AstDotPrinter::new(&ast).print(&statements),
+            concat!(
+                "digraph ast {\n",
+                "  node [shape=box, fontname=\"monospace\"];\n",
+                "  n0 [label=\"Program\"];\n",
+                "  n1 [label=\"Print\"];\n",
+                "  n2 [label=\"+\"];\n",
+                "  n3 [label=\"-\"];\n",
+                "  n4 [label=\"1\"];\n",
+                "  n3 -> n4;\n",
+                "  n2 -> n3 [label=\"left\"];\n",
+                "  n5 [label=\"\\\"a\\\"\\\"\"];\n",
+                "  n2 -> n5 [label=\"right\"];\n",
+                "  n1 -> n2;\n",
+                "  n0 -> n1 [label=\"0\"];\n",
+                "}\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use crate::ast::{Ast, ExprId, Expression, FunctionDeclaration, Stmt, Visitor};
+use crate::json::{self, Object};
+use crate::scanner::{Token, TokenType};
+
+/// Writes a parsed program out as JSON, for tools that want the syntax tree
+/// without parsing Lox themselves.
+///
+/// Every node is an object whose `type` is the name of its variant. Nodes
+/// built around a token also say which `line` it's on. Names and operators
+/// are written as their source text, and literals as the JSON value they
+/// stand for.
+pub struct AstJsonPrinter<'r, 'a> {
+    ast: &'r Ast<'a>,
+}
+
+impl<'r, 'a> AstJsonPrinter<'r, 'a> {
+    pub fn new(ast: &'r Ast<'a>) -> Self {
+        AstJsonPrinter { ast }
+    }
+
+    /// A program's statements as a JSON array.
+    pub fn print(&self, statements: &[Stmt]) -> String {
+        self.statements(statements)
+    }
+
+    fn statements(&self, statements: &[Stmt]) -> String {
+        json::array(statements.iter().map(|s| self.statement(s)))
+    }
+
+    fn expressions(&self, expressions: &[ExprId]) -> String {
+        json::array(expressions.iter().map(|e| self.visit_expression(*e)))
+    }
+
+    fn optional(&self, expr: Option<ExprId>) -> String {
+        expr.map_or_else(|| "null".to_string(), |e| self.visit_expression(e))
+    }
+
+    fn node(kind: &str, token: Option<&Token>) -> Object {
+        let node = Object::new().field("type", json::string(kind));
+        match token {
+            Some(token) => node.field("line", token.line.to_string()),
+            None => node,
+        }
+    }
+
+    fn lexeme(token: &Token) -> String {
+        json::string(token.lexeme)
+    }
+
+    fn optional_lexeme(token: Option<&Token>) -> String {
+        token.map_or_else(|| "null".to_string(), Self::lexeme)
+    }
+
+    fn function(&self, kind: &str, declaration: &FunctionDeclaration) -> Object {
+        let node = Self::node(kind, Some(&declaration.name));
+        let node = if declaration.is_anonymous() {
+            node
+        } else {
+            node.field("name", Self::lexeme(&declaration.name))
+        };
+        node.field(
+            "params",
+            json::array(declaration.params.iter().map(Self::lexeme)),
+        )
+        .field("variadic", declaration.variadic.to_string())
+        .field("body", self.statements(&declaration.body))
+        .field("getter", declaration.is_getter.to_string())
+    }
+
+    fn statement(&self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                Self::node("Block", None).field("statements", self.statements(statements))
+            }
+            Stmt::Break { keyword, .. } => Self::node("Break", Some(keyword)),
+            Stmt::Class {
+                name,
+                superclass,
+                mixins,
+                methods,
+                class_methods,
+                ..
+            } => Self::node("Class", Some(name))
+                .field("name", Self::lexeme(name))
+                .field("superclass", self.optional(*superclass))
+                .field("mixins", self.expressions(mixins))
+                .field(
+                    "methods",
+                    json::array(methods.iter().map(|m| self.function("Method", m).build())),
+                )
+                .field(
+                    "class_methods",
+                    json::array(
+                        class_methods
+                            .iter()
+                            .map(|m| self.function("Method", m).build()),
+                    ),
+                ),
+            Stmt::Continue { keyword, .. } => Self::node("Continue", Some(keyword)),
+            Stmt::Expression { expr, .. } => {
+                Self::node("Expression", None).field("expression", self.visit_expression(*expr))
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => Self::node("ForIn", Some(name))
+                .field("name", Self::lexeme(name))
+                .field("iterable", self.visit_expression(*iterable))
+                .field("body", self.statement(body)),
+            Stmt::Function { declaration, .. } => self.function("Function", declaration),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => Self::node("If", None)
+                .field("condition", self.visit_expression(*condition))
+                .field("then", self.statement(then_branch))
+                .field(
+                    "else",
+                    else_branch
+                        .as_ref()
+                        .map_or_else(|| "null".to_string(), |s| self.statement(s)),
+                ),
+            Stmt::Import {
+                keyword,
+                path,
+                alias,
+                ..
+            } => Self::node("Import", Some(keyword))
+                .field("path", Self::lexeme(path))
+                .field(
+                    "alias",
+                    alias
+                        .as_ref()
+                        .map_or_else(|| "null".to_string(), Self::lexeme),
+                ),
+            Stmt::Print { expr, .. } => {
+                Self::node("Print", None).field("expression", self.visit_expression(*expr))
+            }
+            Stmt::Return { keyword, value, .. } => {
+                Self::node("Return", Some(keyword)).field("value", self.optional(*value))
+            }
+            Stmt::Throw { keyword, value, .. } => {
+                Self::node("Throw", Some(keyword)).field("value", self.visit_expression(*value))
+            }
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => Self::node("Try", None)
+                .field("body", self.statements(body))
+                .field("name", Self::lexeme(name))
+                .field("handler", self.statements(handler)),
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+                ..
+            } => Self::node("Var", Some(name))
+                .field("name", Self::lexeme(name))
+                .field("initializer", self.optional(*initializer))
+                .field("const", is_const.to_string()),
+            Stmt::Destructure {
+                paren,
+                names,
+                initializer,
+                is_const,
+                ..
+            } => Self::node("Destructure", Some(paren))
+                .field(
+                    "names",
+                    json::array(names.iter().map(|name| Self::lexeme(name))),
+                )
+                .field("initializer", self.visit_expression(*initializer))
+                .field("const", is_const.to_string()),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => Self::node("While", None)
+                .field("condition", self.visit_expression(*condition))
+                .field("body", self.statement(body))
+                .field("increment", self.optional(*increment)),
+        }
+        .build()
+    }
+}
+
+impl<'a> Visitor<'a> for AstJsonPrinter<'_, 'a> {
+    type E = String;
+    fn visit_expression(&self, e: ExprId) -> Self::E {
+        match &self.ast[e] {
+            Expression::Assign { name, value, .. } => Self::node("Assign", Some(name))
+                .field("name", Self::lexeme(name))
+                .field("value", self.visit_expression(*value)),
+            Expression::Binary {
+                l_expr,
+                operator,
+                r_expr,
+            } => Self::node("Binary", Some(operator))
+                .field("operator", Self::lexeme(operator))
+                .field("left", self.visit_expression(*l_expr))
+                .field("right", self.visit_expression(*r_expr)),
+            Expression::Call {
+                callee,
+                paren,
+                arguments,
+            } => Self::node("Call", Some(paren))
+                .field("callee", self.visit_expression(*callee))
+                .field("arguments", self.expressions(arguments)),
+            Expression::Function { declaration } => self.function("Function", declaration),
+            Expression::Get {
+                object,
+                name,
+                optional,
+            } => Self::node("Get", Some(name))
+                .field("object", self.visit_expression(*object))
+                .field("name", Self::lexeme(name))
+                .field("optional", optional.to_string()),
+            Expression::Grouping { expr } => {
+                Self::node("Grouping", None).field("expression", self.visit_expression(*expr))
+            }
+            Expression::Index {
+                object,
+                bracket,
+                index,
+            } => Self::node("Index", Some(bracket))
+                .field("object", self.visit_expression(*object))
+                .field("index", self.visit_expression(*index)),
+            Expression::List { bracket, elements } => {
+                Self::node("List", Some(bracket)).field("elements", self.expressions(elements))
+            }
+            Expression::Named { name, value } => Self::node("Named", Some(name))
+                .field("name", Self::lexeme(name))
+                .field("value", self.visit_expression(*value)),
+            Expression::Spread { ellipsis, expr } => Self::node("Spread", Some(ellipsis))
+                .field("expression", self.visit_expression(*expr)),
+            Expression::Tuple { paren, elements } => {
+                Self::node("Tuple", Some(paren)).field("elements", self.expressions(elements))
+            }
+            Expression::Map { brace, entries } => Self::node("Map", Some(brace)).field(
+                "entries",
+                json::array(entries.iter().map(|(key, value)| {
+                    Object::new()
+                        .field("key", self.visit_expression(*key))
+                        .field("value", self.visit_expression(*value))
+                        .build()
+                })),
+            ),
+            Expression::Literal { token } => {
+                let value = match &token.token_type {
+                    TokenType::Number { number } => json::number(*number),
+                    TokenType::StringLiteral { literal } => json::string(literal),
+                    TokenType::True => "true".to_string(),
+                    TokenType::False => "false".to_string(),
+                    _ => "null".to_string(),
+                };
+                Self::node("Literal", Some(token)).field("value", value)
+            }
+            Expression::Logical {
+                l_expr,
+                operator,
+                r_expr,
+            } => Self::node("Logical", Some(operator))
+                .field("operator", Self::lexeme(operator))
+                .field("left", self.visit_expression(*l_expr))
+                .field("right", self.visit_expression(*r_expr)),
+            Expression::Set {
+                object,
+                name,
+                value,
+                operator,
+            } => Self::node("Set", Some(name))
+                .field("object", self.visit_expression(*object))
+                .field("name", Self::lexeme(name))
+                .field("operator", Self::optional_lexeme(operator.as_ref()))
+                .field("value", self.visit_expression(*value)),
+            Expression::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+                operator,
+            } => Self::node("SetIndex", Some(bracket))
+                .field("object", self.visit_expression(*object))
+                .field("index", self.visit_expression(*index))
+                .field("operator", Self::optional_lexeme(operator.as_ref()))
+                .field("value", self.visit_expression(*value)),
+            Expression::Super {
+                keyword, method, ..
+            } => Self::node("Super", Some(keyword)).field("method", Self::lexeme(method)),
+            Expression::This { keyword, .. } => Self::node("This", Some(keyword)),
+            Expression::Unary { operator, r_expr } => Self::node("Unary", Some(operator))
+                .field("operator", Self::lexeme(operator))
+                .field("operand", self.visit_expression(*r_expr)),
+            Expression::Variable { name, .. } => {
+                Self::node("Variable", Some(name)).field("name", Self::lexeme(name))
+            }
+        }
+        .build()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_print_json() {
+        let mut scanner = Scanner::new("var a = -1 + \"x\";\nprint a;");
+        let tokens = scanner.scan_tokens().unwrap();
+        let parser = Parser::new(tokens);
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        assert_eq!(
+            AstJsonPrinter::new(&ast).print(&statements),
+            concat!(
+                r#"[{"type":"Var","line":1,"name":"a","initializer":"#,
+                r#"{"type":"Binary","line":1,"operator":"+","#,
+                r#""left":{"type":"Unary","line":1,"operator":"-","#,
+                r#""operand":{"type":"Literal","line":1,"value":1}},"#,
+                r#""right":{"type":"Literal","line":1,"value":"x"}},"const":false},"#,
+                r#"{"type":"Print","expression":{"type":"Variable","line":2,"name":"a"}}]"#
+            )
+        );
+    }
+}
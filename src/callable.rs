@@ -0,0 +1,342 @@
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+use crate::ast::{Ast, FunctionDeclaration};
+use crate::class::LoxInstance;
+use crate::diagnostic::codes;
+use crate::environment::Environment;
+use crate::interpreter::{self, Flow, Interpreter, RuntimeError, RuntimeResult, Types};
+use crate::scanner::Token;
+use crate::symbol::Symbol;
+
+pub trait LoxCallable<'a> {
+    /// How many arguments the callable takes or, if it's variadic, the
+    /// fewest it takes.
+    fn arity(&self) -> usize;
+    /// Whether the callable takes any number of arguments past its arity.
+    fn is_variadic(&self) -> bool {
+        false
+    }
+    /// The names of the parameters, for matching up named arguments, if
+    /// they're known.
+    fn parameter_names(&self) -> Option<Vec<&'a str>> {
+        None
+    }
+    /// Calls the function. `paren` is the closing parenthesis of the call,
+    /// which errors raised by the call are reported at.
+    fn call(
+        &self,
+        interpreter: &Interpreter<'a>,
+        paren: &Token,
+        arguments: Vec<Types<'a>>,
+    ) -> RuntimeResult<Types<'a>>;
+}
+
+pub struct LoxFunction<'a> {
+    declaration: Rc<FunctionDeclaration<'a>>,
+    /// The arena holding the expressions in the function's body.
+    ast: Rc<Ast<'a>>,
+    /// The scope the function was declared in, kept alive for as long as the
+    /// function is so that it can keep reading and writing captured variables.
+    closure: Rc<RefCell<Environment<'a>>>,
+    /// Whether this is a class's `init` method, which always returns `this`.
+    is_initializer: bool,
+}
+
+impl<'a> LoxFunction<'a> {
+    pub fn new(
+        declaration: Rc<FunctionDeclaration<'a>>,
+        ast: Rc<Ast<'a>>,
+        closure: Rc<RefCell<Environment<'a>>>,
+        is_initializer: bool,
+    ) -> Self {
+        LoxFunction {
+            declaration,
+            ast,
+            closure,
+            is_initializer,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.declaration.name.lexeme
+    }
+
+    /// The name shown for this function in stack traces.
+    pub fn trace_name(&self) -> Option<&'a str> {
+        if self.declaration.is_anonymous() {
+            None
+        } else {
+            Some(self.declaration.name.lexeme)
+        }
+    }
+
+    pub fn is_getter(&self) -> bool {
+        self.declaration.is_getter
+    }
+
+    pub(crate) fn closure(&self) -> &Rc<RefCell<Environment<'a>>> {
+        &self.closure
+    }
+
+    /// Creates a copy of this method whose closure has `this` bound to
+    /// `instance`.
+    pub fn bind(&self, instance: Rc<LoxInstance<'a>>) -> LoxFunction<'a> {
+        self.bind_this(Types::Instance(instance))
+    }
+
+    /// Like `bind`, but `this` can be any value, such as the class a class
+    /// method is called on.
+    pub fn bind_this(&self, this: Types<'a>) -> LoxFunction<'a> {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+        environment.define(Symbol::THIS, this);
+        LoxFunction::new(
+            Rc::clone(&self.declaration),
+            Rc::clone(&self.ast),
+            environment.into_shared(),
+            self.is_initializer,
+        )
+    }
+}
+
+impl<'a> LoxCallable<'a> for LoxFunction<'a> {
+    fn arity(&self) -> usize {
+        self.declaration.params.len() - self.declaration.variadic as usize
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.declaration.variadic
+    }
+
+    fn parameter_names(&self) -> Option<Vec<&'a str>> {
+        Some(self.declaration.params.iter().map(|p| p.lexeme).collect())
+    }
+
+    fn call(
+        &self,
+        interpreter: &Interpreter<'a>,
+        paren: &Token,
+        arguments: Vec<Types<'a>>,
+    ) -> RuntimeResult<Types<'a>> {
+        interpreter.enter_call(self, paren)?;
+        let result = self.run(interpreter, arguments);
+        interpreter.exit_call();
+        result
+    }
+}
+
+impl<'a> LoxFunction<'a> {
+    fn run(
+        &self,
+        interpreter: &Interpreter<'a>,
+        mut arguments: Vec<Types<'a>>,
+    ) -> RuntimeResult<Types<'a>> {
+        // Tail calls are made by looping rather than recursing, with the
+        // callee replacing this function.
+        let mut tail_call: Option<Rc<LoxFunction<'a>>> = None;
+        loop {
+            let function = tail_call.as_deref().unwrap_or(self);
+            let hook = interpreter.hook();
+            if let Some(hook) = &hook {
+                hook.on_call(
+                    interpreter,
+                    function.trace_name().unwrap_or("<fn>"),
+                    &arguments,
+                );
+            }
+
+            let mut environment = Environment::with_enclosing(Rc::clone(&function.closure));
+            let params = &function.declaration.params;
+            // Parameters are defined in order, so the rest parameter goes last.
+            let rest = if function.declaration.variadic {
+                let rest = arguments.split_off(function.arity().min(arguments.len()));
+                Some(Types::List(Rc::new(RefCell::new(rest))))
+            } else {
+                None
+            };
+            for (param, argument) in params.iter().zip(arguments.into_iter().chain(rest)) {
+                environment.define(param.symbol, argument);
+            }
+
+            let environment = environment.into_shared();
+            let flow = interpreter.with_ast(&function.ast, || {
+                interpreter.execute_block(&function.declaration.body, environment)
+            });
+            let name = function.trace_name().unwrap_or("<fn>");
+            let flow = match flow {
+                Ok(flow) => flow,
+                Err(e) => {
+                    if let Some(hook) = &hook {
+                        hook.on_return(interpreter, name, None);
+                    }
+                    return Err(e);
+                }
+            };
+
+            let value = if function.is_initializer {
+                function
+                    .closure
+                    .borrow()
+                    .get_own(Symbol::THIS)
+                    .unwrap_or(Types::Nil)
+            } else {
+                match flow {
+                    Flow::Return(value) => value,
+                    Flow::TailCall {
+                        function,
+                        arguments: next_arguments,
+                        ..
+                    } => {
+                        if let Some(hook) = &hook {
+                            hook.on_return(interpreter, name, None);
+                        }
+                        interpreter.replace_call(&function);
+                        tail_call = Some(function);
+                        arguments = next_arguments;
+                        continue;
+                    }
+                    Flow::Next | Flow::Break | Flow::Continue => Types::Nil,
+                }
+            };
+
+            if let Some(hook) = &hook {
+                hook.on_return(interpreter, name, Some(&value));
+            }
+            return Ok(value);
+        }
+    }
+}
+
+impl Debug for LoxFunction<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.declaration.is_anonymous() {
+            return write!(f, "<fn>");
+        }
+
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+/// What a native function hands back: its result, or a message to raise as a
+/// runtime error.
+pub type NativeResult<'a> = Result<Types<'a>, String>;
+
+/// Why a built-in native function didn't return a value.
+pub(crate) enum NativeFailure {
+    /// A runtime error with this code and message.
+    Error(&'static str, String),
+    /// The program should stop with this exit code.
+    Exit(i32),
+}
+
+impl NativeFailure {
+    /// The usual error for a native, with no more specific code.
+    pub(crate) fn message(message: &str) -> Self {
+        NativeFailure::Error(codes::NATIVE_FUNCTION, message.to_string())
+    }
+}
+
+type NativeFn<'a> = dyn Fn(&Interpreter<'a>, &[Types<'a>]) -> Result<Types<'a>, NativeFailure> + 'a;
+
+/// A function implemented in Rust rather than in Lox.
+pub struct NativeFunction<'a> {
+    name: String,
+    arity: usize,
+    function: Box<NativeFn<'a>>,
+}
+
+impl<'a> NativeFunction<'a> {
+    pub fn new<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&[Types<'a>]) -> NativeResult<'a> + 'a,
+    {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(move |_, arguments| {
+                function(arguments).map_err(|message| NativeFailure::message(&message))
+            }),
+        }
+    }
+
+    /// A native that can fail in more ways than the ones from
+    /// [`NativeFunction::new`].
+    pub(crate) fn builtin<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&[Types<'a>]) -> Result<Types<'a>, NativeFailure> + 'a,
+    {
+        Self::with_interpreter(name, arity, move |_, arguments| function(arguments))
+    }
+
+    /// A built-in native that needs the interpreter, like `write`.
+    pub(crate) fn with_interpreter<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&Interpreter<'a>, &[Types<'a>]) -> Result<Types<'a>, NativeFailure> + 'a,
+    {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<'a> LoxCallable<'a> for NativeFunction<'a> {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &Interpreter<'a>,
+        paren: &Token,
+        arguments: Vec<Types<'a>>,
+    ) -> RuntimeResult<Types<'a>> {
+        interpreter
+            .call_native(&self.name, &arguments, || {
+                (self.function)(interpreter, &arguments)
+            })
+            .map_err(|failure| match failure {
+                NativeFailure::Error(code, message) => RuntimeError::new(paren, code, &message),
+                NativeFailure::Exit(code) => RuntimeError::exit(paren, code),
+            })
+    }
+}
+
+impl Debug for NativeFunction<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// `exit(code)`, which stops the program with the given exit code.
+pub(crate) fn exit<'a>(arguments: &[Types<'a>]) -> Result<Types<'a>, NativeFailure> {
+    match arguments[0] {
+        // Only the low byte of a process's exit code survives, so anything
+        // else would report something other than what was asked for.
+        Types::Number(code) if code.fract() == 0.0 && (0.0..=255.0).contains(&code) => {
+            Err(NativeFailure::Exit(code as i32))
+        }
+        _ => Err(NativeFailure::message(
+            "Exit code must be an integer from 0 to 255.",
+        )),
+    }
+}
+
+/// `assert(condition, message)`, which fails with `message` if `condition`
+/// is false or nil.
+pub(crate) fn assert<'a>(arguments: &[Types<'a>]) -> Result<Types<'a>, NativeFailure> {
+    if interpreter::is_truthy(&arguments[0]) {
+        Ok(Types::Nil)
+    } else {
+        Err(NativeFailure::Error(
+            codes::ASSERTION_FAILED,
+            format!("Assertion failed: {}", arguments[1]),
+        ))
+    }
+}
@@ -0,0 +1,195 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+use crate::callable::{LoxCallable, LoxFunction};
+use crate::diagnostic::codes;
+use crate::gc::Object;
+use crate::interpreter::{Interpreter, RuntimeError, RuntimeResult, Types};
+use crate::scanner::Token;
+
+pub struct LoxClass<'a> {
+    name: String,
+    superclass: Option<Rc<LoxClass<'a>>>,
+    methods: HashMap<String, Rc<LoxFunction<'a>>>,
+    class_methods: HashMap<String, Rc<LoxFunction<'a>>>,
+}
+
+impl<'a> LoxClass<'a> {
+    pub fn new(
+        name: &str,
+        superclass: Option<Rc<LoxClass<'a>>>,
+        methods: HashMap<String, Rc<LoxFunction<'a>>>,
+        class_methods: HashMap<String, Rc<LoxFunction<'a>>>,
+    ) -> Self {
+        LoxClass {
+            name: name.to_string(),
+            superclass,
+            methods,
+            class_methods,
+        }
+    }
+
+    /// Looks `name` up as a class method, falling back to the superclass
+    /// chain, and binds it so `this` is `class`.
+    pub fn get(class: &Rc<LoxClass<'a>>, name: &Token) -> RuntimeResult<Types<'a>> {
+        let mut current = Some(class);
+        while let Some(searched) = current {
+            if let Some(method) = searched.class_methods.get(name.lexeme) {
+                let bound = method.bind_this(Types::Class(Rc::clone(class)));
+                return Ok(Types::Function(Rc::new(bound)));
+            }
+            current = searched.superclass.as_ref();
+        }
+
+        Err(RuntimeError::new(
+            name,
+            codes::UNDEFINED_PROPERTY,
+            &format!("Undefined property '{}'.", name.lexeme),
+        ))
+    }
+
+    /// Every method instances of this class respond to, including
+    /// inherited ones.
+    pub fn all_methods(&self) -> HashMap<String, Rc<LoxFunction<'a>>> {
+        let mut methods = match &self.superclass {
+            Some(superclass) => superclass.all_methods(),
+            None => HashMap::new(),
+        };
+        methods.extend(
+            self.methods
+                .iter()
+                .map(|(name, method)| (name.clone(), Rc::clone(method))),
+        );
+        methods
+    }
+
+    pub(crate) fn references(&self, out: &mut Vec<Object<'a>>) {
+        out.extend(self.superclass.iter().cloned().map(Object::Class));
+        out.extend(
+            self.methods
+                .values()
+                .chain(self.class_methods.values())
+                .cloned()
+                .map(Object::Function),
+        );
+    }
+
+    /// Finds `name` on this class, falling back to the superclass chain.
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction<'a>>> {
+        match (self.methods.get(name), &self.superclass) {
+            (Some(method), _) => Some(Rc::clone(method)),
+            (None, Some(superclass)) => superclass.find_method(name),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Classes are called like functions to create new instances of themselves.
+/// Unlike the other callables this needs the `Rc` the class lives in, since
+/// every instance keeps a handle back to its class.
+impl<'a> LoxCallable<'a> for Rc<LoxClass<'a>> {
+    fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.find_method("init")
+            .is_some_and(|init| init.is_variadic())
+    }
+
+    fn parameter_names(&self) -> Option<Vec<&'a str>> {
+        match self.find_method("init") {
+            Some(init) => init.parameter_names(),
+            None => Some(vec![]),
+        }
+    }
+
+    fn call(
+        &self,
+        interpreter: &Interpreter<'a>,
+        paren: &Token,
+        arguments: Vec<Types<'a>>,
+    ) -> RuntimeResult<Types<'a>> {
+        let instance = Rc::new(LoxInstance::new(Rc::clone(self)));
+        interpreter.heap().track_instance(&instance);
+        if let Some(init) = self.find_method("init") {
+            init.bind(Rc::clone(&instance))
+                .call(interpreter, paren, arguments)?;
+        }
+
+        Ok(Types::Instance(instance))
+    }
+}
+
+impl Debug for LoxClass<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct LoxInstance<'a> {
+    class: Rc<LoxClass<'a>>,
+    fields: RefCell<HashMap<String, Types<'a>>>,
+}
+
+impl<'a> LoxInstance<'a> {
+    pub fn new(class: Rc<LoxClass<'a>>) -> Self {
+        LoxInstance {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Looks `name` up as a field first and then as a method, binding any
+    /// method found to `instance` so `this` works inside it.
+    pub fn get(instance: &Rc<LoxInstance<'a>>, name: &Token) -> RuntimeResult<Types<'a>> {
+        if let Some(value) = instance.fields.borrow().get(name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        match instance.class.find_method(name.lexeme) {
+            Some(method) => Ok(Types::Function(Rc::new(method.bind(Rc::clone(instance))))),
+            None => Err(RuntimeError::new(
+                name,
+                codes::UNDEFINED_PROPERTY,
+                &format!("Undefined property '{}'.", name.lexeme),
+            )),
+        }
+    }
+
+    pub fn set(&self, name: &Token, value: Types<'a>) {
+        self.fields
+            .borrow_mut()
+            .insert(name.lexeme.to_string(), value);
+    }
+
+    /// Returns false if the fields are being changed and can't be read.
+    pub(crate) fn references(&self, out: &mut Vec<Object<'a>>) -> bool {
+        out.push(Object::Class(Rc::clone(&self.class)));
+        match self.fields.try_borrow() {
+            Ok(fields) => {
+                out.extend(fields.values().filter_map(Object::of));
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Removes every field, for when the collector finds this instance is
+    /// garbage.
+    pub(crate) fn clear(&self) -> Vec<Types<'a>> {
+        self.fields
+            .borrow_mut()
+            .drain()
+            .map(|(_, value)| value)
+            .collect()
+    }
+}
+
+impl Debug for LoxInstance<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
@@ -0,0 +1,38 @@
+//! Monotonic time, for measuring how long code takes to run.
+
+use std::time::{Duration, Instant};
+
+/// A source of time that never goes backwards.
+pub trait Clock {
+    /// How long it's been since some fixed point, like the clock's creation.
+    fn elapsed(&self) -> Duration;
+}
+
+/// The system's monotonic clock. On wasm32-unknown-unknown, where there's no
+/// clock and asking for one panics, time stands still instead.
+pub struct MonotonicClock {
+    start: Option<Instant>,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        let start = if cfg!(target_arch = "wasm32") {
+            None
+        } else {
+            Some(Instant::now())
+        };
+        MonotonicClock { start }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn elapsed(&self) -> Duration {
+        self.start.map_or(Duration::ZERO, |start| start.elapsed())
+    }
+}
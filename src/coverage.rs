@@ -0,0 +1,182 @@
+//! Records which lines of a program ran, for finding code its tests miss.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use anyhow::Result;
+
+use crate::ast::{walk_stmt, Ast, Stmt, VisitorMut};
+use crate::interpreter::{ExecutionHook, Interpreter};
+use crate::parser::Parser;
+use crate::scanner::{Scanner, Token};
+
+/// An [`ExecutionHook`] which records the lines of one source that had a
+/// statement run on them. Code from other sources, like imported modules,
+/// is ignored.
+pub struct Coverage<'a> {
+    source: &'a str,
+    /// Every line with a statement on it.
+    lines: BTreeSet<usize>,
+    covered: RefCell<BTreeSet<usize>>,
+}
+
+impl<'a> Coverage<'a> {
+    /// Prepares to record coverage of `source`, failing if it can't be
+    /// parsed.
+    pub fn new(source: &'a str) -> Result<Self> {
+        let mut scanner = Scanner::new(source);
+        let parser = Parser::new(scanner.scan_tokens()?);
+        let statements = parser.parse()?;
+        let ast = parser.into_ast();
+
+        let mut lines = StatementLines {
+            lines: BTreeSet::new(),
+        };
+        for statement in &statements {
+            lines.visit_stmt(&ast, statement);
+        }
+
+        Ok(Coverage {
+            source,
+            lines: lines.lines,
+            covered: RefCell::new(BTreeSet::new()),
+        })
+    }
+
+    /// The lines with statements on them which ran.
+    pub fn covered(&self) -> BTreeSet<usize> {
+        self.covered.borrow().clone()
+    }
+
+    /// The lines with statements on them which never ran.
+    pub fn uncovered(&self) -> BTreeSet<usize> {
+        self.lines
+            .difference(&self.covered.borrow())
+            .copied()
+            .collect()
+    }
+
+    /// A summary for people, naming the source `name`, with the ranges of
+    /// lines that never ran.
+    pub fn report(&self, name: &str) -> String {
+        let covered = self.covered.borrow().len();
+        let mut report = format!(
+            "{}: {} of {} lines covered ({:.1}%)\n",
+            name,
+            covered,
+            self.lines.len(),
+            percentage(covered, self.lines.len())
+        );
+
+        let uncovered = self.uncovered();
+        if !uncovered.is_empty() {
+            let _ = writeln!(report, "not run: {}", ranges(&uncovered));
+        }
+        report
+    }
+
+    /// The coverage in the lcov tracefile format, for the source file at
+    /// `path`.
+    pub fn lcov(&self, path: &str) -> String {
+        let covered = self.covered.borrow();
+        let mut lcov = format!("SF:{}\n", path);
+        for line in &self.lines {
+            let hits = if covered.contains(line) { 1 } else { 0 };
+            let _ = writeln!(lcov, "DA:{},{}", line, hits);
+        }
+        let _ = writeln!(lcov, "LH:{}", covered.len());
+        let _ = writeln!(lcov, "LF:{}", self.lines.len());
+        lcov.push_str("end_of_record\n");
+        lcov
+    }
+}
+
+impl<'a> ExecutionHook<'a> for Coverage<'a> {
+    fn before_statement(&self, _interpreter: &Interpreter<'a>, token: &Token<'a>) {
+        if token.is_from(self.source) && self.lines.contains(&token.line) {
+            self.covered.borrow_mut().insert(token.line);
+        }
+    }
+}
+
+/// Collects the line of every statement, however deeply it's nested.
+struct StatementLines {
+    lines: BTreeSet<usize>,
+}
+
+impl<'a> VisitorMut<'a> for StatementLines {
+    fn visit_stmt(&mut self, ast: &Ast<'a>, stmt: &Stmt<'a>) {
+        if let Some(token) = ast.statement_token(stmt) {
+            self.lines.insert(token.line);
+        }
+        walk_stmt(self, ast, stmt);
+    }
+}
+
+fn percentage(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        100.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+/// Lines as a list of ranges, like `3, 7-9`.
+fn ranges(lines: &BTreeSet<usize>) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for &line in lines {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Lox;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_coverage() {
+        let source = "fun f(x) {\n  if (x) {\n    return 1;\n  }\n  return 2;\n}\nprint f(false);\nvar g = fun () {\n  print 3;\n};\n";
+        let coverage = Rc::new(Coverage::new(source).unwrap());
+        let lox = Lox::new();
+        lox.interpreter().set_hook(coverage.clone());
+        lox.run(source).unwrap();
+
+        assert_eq!(
+            coverage.covered(),
+            [1, 2, 5, 7, 8].iter().copied().collect()
+        );
+        assert_eq!(coverage.uncovered(), [3, 9].iter().copied().collect());
+        assert_eq!(
+            coverage.report("f.lox"),
+            "f.lox: 5 of 7 lines covered (71.4%)\nnot run: 3, 9\n"
+        );
+        assert_eq!(
+            coverage.lcov("f.lox"),
+            "SF:f.lox\nDA:1,1\nDA:2,1\nDA:3,0\nDA:5,1\nDA:7,1\nDA:8,1\nDA:9,0\nLH:5\nLF:7\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let lines = [1, 3, 4, 5, 8, 9].iter().copied().collect();
+        assert_eq!(ranges(&lines), "1, 3-5, 8-9");
+    }
+}
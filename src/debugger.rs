@@ -0,0 +1,202 @@
+use rlox_treewalk::interpreter::{ExecutionHook, Interpreter};
+use rlox_treewalk::parser::{Parser, ReplInput};
+use rlox_treewalk::scanner::{Scanner, Token};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::rc::Rc;
+
+const HELP: &str = "\
+break <line>  stop when the given line is reached (b)
+step          run to the next line, going into calls (s)
+next          run to the next line, stepping over calls (n)
+continue      run to the next breakpoint (c)
+print <expr>  show the value of an expression (p)
+quit          stop the program (q)";
+
+/// Something typed at the debugger's prompt.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Break(usize),
+    Step,
+    Next,
+    Continue,
+    Print(String),
+    Quit,
+    Help,
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(at) => (&line[..at], line[at..].trim()),
+            None => (line, ""),
+        };
+
+        let command = match name {
+            "break" | "b" => match rest.parse() {
+                Ok(line) if line > 0 => Command::Break(line),
+                _ => return Err("Expect a line number after 'break'.".to_string()),
+            },
+            "step" | "s" => Command::Step,
+            "next" | "n" => Command::Next,
+            "continue" | "c" => Command::Continue,
+            "print" | "p" if rest.is_empty() => {
+                return Err("Expect an expression after 'print'.".to_string())
+            }
+            "print" | "p" => Command::Print(rest.to_string()),
+            "quit" | "q" => Command::Quit,
+            "help" | "h" => Command::Help,
+            _ => return Err(format!("Unknown command '{}'. Try 'help'.", name)),
+        };
+
+        // Commands other than `break` and `print` don't take anything.
+        match command {
+            Command::Break(_) | Command::Print(_) => Ok(command),
+            _ if !rest.is_empty() => Err(format!("'{}' doesn't take an argument.", name)),
+            _ => Ok(command),
+        }
+    }
+}
+
+/// How far to run before pausing again.
+#[derive(Clone, Copy)]
+enum Mode {
+    /// Pause at the next line, wherever it is.
+    Step,
+    /// Pause at the next line with at most this many calls in progress.
+    Next(usize),
+    /// Pause only at breakpoints.
+    Continue,
+    /// Never pause again, because there's nobody left to type commands.
+    Detached,
+}
+
+/// Pauses a running script at breakpoints and between steps, and takes
+/// commands from stdin while it's paused.
+pub struct Debugger {
+    source: &'static str,
+    breakpoints: RefCell<HashSet<usize>>,
+    mode: Cell<Mode>,
+    /// The line and call depth of the last statement, so that several
+    /// statements on one line are stepped over together.
+    last: Cell<Option<(usize, usize)>>,
+}
+
+impl Debugger {
+    /// A debugger for `source` which pauses before its first line.
+    pub fn new(source: &'static str) -> Self {
+        Debugger {
+            source,
+            breakpoints: RefCell::new(HashSet::new()),
+            mode: Cell::new(Mode::Step),
+            last: Cell::new(None),
+        }
+    }
+
+    fn should_pause(&self, line: usize, depth: usize) -> bool {
+        if self.last.replace(Some((line, depth))) == Some((line, depth)) {
+            return false;
+        }
+
+        match self.mode.get() {
+            Mode::Step => true,
+            Mode::Next(max_depth) => depth <= max_depth,
+            Mode::Continue => self.breakpoints.borrow().contains(&line),
+            Mode::Detached => false,
+        }
+    }
+
+    /// Takes commands until one of them resumes the script.
+    fn pause(&self, interpreter: &Interpreter<'static>, token: &Token) {
+        println!("{}", crate::describe_line(self.source, token));
+
+        let stdin = io::stdin();
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+
+            let mut input = String::new();
+            match stdin.lock().read_line(&mut input) {
+                Ok(0) | Err(_) => {
+                    println!();
+                    self.mode.set(Mode::Detached);
+                    return;
+                }
+                Ok(_) if input.trim().is_empty() => continue,
+                Ok(_) => {}
+            }
+
+            match Command::parse(&input) {
+                Ok(Command::Break(line)) => {
+                    self.breakpoints.borrow_mut().insert(line);
+                    println!("Breakpoint at line {}.", line);
+                }
+                Ok(Command::Step) => return self.mode.set(Mode::Step),
+                Ok(Command::Next) => {
+                    return self.mode.set(Mode::Next(interpreter.call_depth()));
+                }
+                Ok(Command::Continue) => return self.mode.set(Mode::Continue),
+                Ok(Command::Print(expr)) => print_expression(interpreter, expr),
+                Ok(Command::Quit) => process::exit(0),
+                Ok(Command::Help) => println!("{}", HELP),
+                Err(message) => println!("{}", message),
+            }
+        }
+    }
+}
+
+impl ExecutionHook<'static> for Debugger {
+    fn before_statement(&self, interpreter: &Interpreter<'static>, token: &Token<'static>) {
+        if self.should_pause(token.line, interpreter.call_depth()) {
+            self.pause(interpreter, token);
+        }
+    }
+}
+
+/// Evaluates `source` where the script is paused and prints its value.
+fn print_expression(interpreter: &Interpreter<'static>, source: String) {
+    // Leaked because values like functions can point back into it.
+    let source: &'static str = Box::leak(source.into_boxed_str());
+    let mut scanner = Scanner::new(source);
+    let tokens = match scanner.scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(e) => return println!("{}", e),
+    };
+
+    let parser = Parser::new(tokens);
+    let expr = match parser.parse_repl() {
+        Ok(ReplInput::Expression(expr)) => expr,
+        Ok(ReplInput::Statements(_)) => return println!("Expect an expression."),
+        Err(errors) => return println!("{}", errors),
+    };
+
+    match interpreter.evaluate_in_scope(&Rc::new(parser.into_ast()), expr) {
+        Ok(value) => println!("{}", value),
+        Err(e) => println!("{}", e.message),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::parse("break 12\n"), Ok(Command::Break(12)));
+        assert_eq!(Command::parse("b 3"), Ok(Command::Break(3)));
+        assert_eq!(Command::parse("  s "), Ok(Command::Step));
+        assert_eq!(Command::parse("next"), Ok(Command::Next));
+        assert_eq!(Command::parse("c"), Ok(Command::Continue));
+        assert_eq!(
+            Command::parse("print a + b.c"),
+            Ok(Command::Print("a + b.c".to_string()))
+        );
+        assert!(Command::parse("break").is_err());
+        assert!(Command::parse("break x").is_err());
+        assert!(Command::parse("step 2").is_err());
+        assert!(Command::parse("jump 4").is_err());
+    }
+}
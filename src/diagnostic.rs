@@ -0,0 +1,387 @@
+use std::fmt::{self, Display, Formatter, Write};
+use std::io::{self, IsTerminal};
+
+use thiserror::Error;
+
+use crate::interpreter::RuntimeError;
+use crate::json;
+use crate::parser::ParserErrors;
+use crate::resolver::{ResolverError, ResolverErrors};
+use crate::scanner::ScannerErrors;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Stable identifiers for every kind of diagnostic, so tools can recognize
+/// one however its message happens to be worded. A number is never reused
+/// for a different kind of problem.
+pub mod codes {
+    // Scanning.
+    pub const UNEXPECTED_CHARACTER: &str = "E0001";
+    pub const UNTERMINATED_STRING: &str = "E0002";
+    pub const UNTERMINATED_BLOCK_COMMENT: &str = "E0003";
+    pub const INVALID_ESCAPE: &str = "E0004";
+    pub const INVALID_NUMBER: &str = "E0005";
+
+    // Parsing.
+    pub const EXPECTED_EXPRESSION: &str = "E0100";
+    pub const EXPECTED_TOKEN: &str = "E0101";
+    pub const INVALID_ASSIGNMENT_TARGET: &str = "E0102";
+    pub const TOO_MANY_PARAMETERS: &str = "E0103";
+    pub const TOO_MANY_ARGUMENTS: &str = "E0104";
+    pub const TOO_DEEPLY_NESTED: &str = "E0105";
+    pub const POSITIONAL_AFTER_NAMED: &str = "E0106";
+
+    // Resolving.
+    pub const READ_IN_OWN_INITIALIZER: &str = "E0200";
+    pub const ALREADY_DECLARED: &str = "E0201";
+    pub const TOP_LEVEL_RETURN: &str = "E0202";
+    pub const RETURN_FROM_INITIALIZER: &str = "E0203";
+    pub const THIS_OUTSIDE_CLASS: &str = "E0204";
+    pub const SUPER_OUTSIDE_CLASS: &str = "E0205";
+    pub const SUPER_WITHOUT_SUPERCLASS: &str = "E0206";
+    pub const INHERIT_FROM_SELF: &str = "E0207";
+    pub const MIX_IN_SELF: &str = "E0208";
+    pub const DUPLICATE_MIXIN: &str = "E0209";
+    pub const BREAK_OUTSIDE_LOOP: &str = "E0210";
+    pub const CONTINUE_OUTSIDE_LOOP: &str = "E0211";
+    pub const ASSIGN_TO_CONSTANT: &str = "E0212";
+
+    // Running.
+    pub const UNDEFINED_VARIABLE: &str = "E0300";
+    pub const OPERAND_TYPE: &str = "E0301";
+    pub const SHIFT_OUT_OF_RANGE: &str = "E0302";
+    pub const NOT_CALLABLE: &str = "E0303";
+    pub const WRONG_ARGUMENT_COUNT: &str = "E0304";
+    pub const NOT_AN_INSTANCE: &str = "E0305";
+    pub const UNDEFINED_PROPERTY: &str = "E0306";
+    pub const INVALID_SUPERCLASS: &str = "E0307";
+    pub const INVALID_MIXIN: &str = "E0308";
+    pub const NOT_INDEXABLE: &str = "E0309";
+    pub const INVALID_INDEX: &str = "E0310";
+    pub const INDEX_OUT_OF_BOUNDS: &str = "E0311";
+    pub const UNDEFINED_KEY: &str = "E0312";
+    pub const NOT_ITERABLE: &str = "E0313";
+    pub const UNCAUGHT_EXCEPTION: &str = "E0314";
+    pub const STACK_OVERFLOW: &str = "E0315";
+    pub const MODULE_NOT_FOUND: &str = "E0316";
+    pub const MODULE_FAILED: &str = "E0317";
+    pub const IMPORT_CYCLE: &str = "E0318";
+    pub const INVALID_MODULE_PATH: &str = "E0319";
+    pub const UNDEFINED_MEMBER: &str = "E0320";
+    pub const NATIVE_FUNCTION: &str = "E0321";
+    /// Not a problem: the program called `exit`.
+    pub const EXIT: &str = "E0322";
+    pub const ASSERTION_FAILED: &str = "E0323";
+    pub const DIVISION_BY_ZERO: &str = "E0324";
+    pub const CANNOT_DESTRUCTURE: &str = "E0325";
+    pub const INVALID_NAMED_ARGUMENT: &str = "E0326";
+    /// Something earlier passes should have made impossible.
+    pub const INTERNAL: &str = "E0399";
+
+    // Warnings.
+    pub const UNREACHABLE_CODE: &str = "W0001";
+    pub const UNUSED_LOCAL: &str = "W0002";
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// How diagnostics are printed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// For people, with a snippet of the source.
+    Human,
+    /// One JSON object per line, for editors and other tools.
+    Json,
+}
+
+/// An error or warning about some part of a program, ready to be shown
+/// alongside the source it points into.
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// One of [`codes`].
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    /// Byte offset of the offending text in its source.
+    pub start: usize,
+    /// Byte offset just past the offending text. Equal to `start` when the
+    /// problem is a missing token rather than a wrong one.
+    pub end: usize,
+    /// Lines shown after the source snippet, like a stack trace.
+    pub notes: Vec<String>,
+    /// Which REPL input, counting from 1, the diagnostic is about, if it's
+    /// about one. Its line, column and offsets are then within that input.
+    pub input: Option<usize>,
+}
+
+impl Diagnostic {
+    /// Formats the diagnostic as a header, the source line it points at with
+    /// the offending text underlined, and any notes. The snippet is left out
+    /// if the span doesn't fit in `source`, as for code from another file.
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+        let (label, style) = match self.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+        };
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}{}",
+            paint(style, &format!("{}[{}]", label, self.code)),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+
+        match self.snippet(source) {
+            Some((line_number, text, offset, width)) => {
+                let gutter = " ".repeat(line_number.to_string().len());
+                let _ = writeln!(
+                    out,
+                    "{}{} [{}]",
+                    gutter,
+                    paint(BLUE, "-->"),
+                    self.location()
+                );
+                let _ = writeln!(out, "{} {}", gutter, paint(BLUE, "|"));
+                let _ = writeln!(
+                    out,
+                    "{} {}",
+                    paint(BLUE, &format!("{} |", line_number)),
+                    text
+                );
+                // Tabs are kept so the carets line up however they're shown.
+                let padding: String = text
+                    .chars()
+                    .take(offset)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let _ = writeln!(
+                    out,
+                    "{} {} {}{}",
+                    gutter,
+                    paint(BLUE, "|"),
+                    padding,
+                    paint(style, &"^".repeat(width))
+                );
+            }
+            None => {
+                let _ = writeln!(out, "{} [{}]", paint(BLUE, "-->"), self.location());
+            }
+        }
+
+        for note in &self.notes {
+            let _ = writeln!(out, "{}", note);
+        }
+
+        out
+    }
+
+    /// Where the diagnostic points, like `line 2, column 7`, or
+    /// `repl:3, line 1, column 5` for the third REPL input.
+    fn location(&self) -> String {
+        let position = format!("line {}, column {}", self.line, self.column);
+        match self.input {
+            Some(input) => format!("repl:{}, {}", input, position),
+            None => position,
+        }
+    }
+
+    /// Formats the diagnostic as a single-line JSON object. A diagnostic
+    /// about a REPL input says which in an `input` field.
+    pub fn to_json(&self) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let span = json::Object::new()
+            .field("start", self.start.to_string())
+            .field("end", self.end.to_string())
+            .build();
+        let object = json::Object::new()
+            .field("code", json::string(self.code))
+            .field("severity", json::string(severity))
+            .field("message", json::string(&self.message))
+            .field("line", self.line.to_string())
+            .field("column", self.column.to_string())
+            .field("span", span)
+            .field(
+                "notes",
+                json::array(self.notes.iter().map(|note| json::string(note))),
+            );
+        match self.input {
+            Some(input) => object.field("input", input.to_string()).build(),
+            None => object.build(),
+        }
+    }
+
+    /// The 1-based number and text of the line the span starts on, with the
+    /// character offset and width of the span within it.
+    fn snippet<'s>(&self, source: &'s str) -> Option<(usize, &'s str, usize, usize)> {
+        if self.start > self.end || !source.is_char_boundary(self.end) {
+            return None;
+        }
+
+        let start = self.start;
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line_number = source[..start].matches('\n').count() + 1;
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        let offset = source[line_start..start].chars().count();
+        let end = self.end.max(start).min(line_start + text.len());
+        let width = source[start.min(end)..end].chars().count().max(1);
+        Some((line_number, text, offset, width))
+    }
+}
+
+/// Every error found in a program before running it, from scanning, parsing
+/// and resolving in that order. Each pass runs on whatever the ones before
+/// it could make sense of, so the errors are all reported at once.
+#[derive(Error, Debug, Default)]
+pub struct StaticErrors(pub Vec<anyhow::Error>);
+
+impl Display for StaticErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for error in &self.0 {
+            write!(f, "{}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The diagnostics describing an error from running a program, or `None`
+/// if it isn't one of the interpreter's own errors.
+pub fn from_error(error: &anyhow::Error) -> Option<Vec<Diagnostic>> {
+    if let Some(errors) = error.downcast_ref::<StaticErrors>() {
+        errors
+            .0
+            .iter()
+            .map(from_error)
+            .collect::<Option<Vec<_>>>()
+            .map(|diagnostics| diagnostics.into_iter().flatten().collect())
+    } else if let Some(errors) = error.downcast_ref::<ScannerErrors>() {
+        Some(errors.0.iter().map(|e| e.diagnostic()).collect())
+    } else if let Some(errors) = error.downcast_ref::<ParserErrors>() {
+        Some(errors.0.iter().map(|e| e.diagnostic()).collect())
+    } else if let Some(error) = error.downcast_ref::<ResolverError>() {
+        Some(vec![error.diagnostic()])
+    } else if let Some(errors) = error.downcast_ref::<ResolverErrors>() {
+        Some(errors.0.iter().map(|e| e.diagnostic()).collect())
+    } else {
+        error
+            .downcast_ref::<RuntimeError>()
+            .map(|error| vec![error.diagnostic()])
+    }
+}
+
+/// A diagnostic formatted as `format`, ending with a newline. Human-readable
+/// ones are in color if `color` is set.
+pub fn display(diagnostic: &Diagnostic, source: &str, format: Format, color: bool) -> String {
+    match format {
+        Format::Human => diagnostic.render(source, color),
+        Format::Json => format!("{}\n", diagnostic.to_json()),
+    }
+}
+
+/// Prints a diagnostic to stderr. Human-readable ones are in color if
+/// stderr is a terminal.
+pub fn emit(diagnostic: &Diagnostic, source: &str, format: Format) {
+    eprint!(
+        "{}",
+        display(diagnostic, source, format, io::stderr().is_terminal())
+    );
+}
+
+/// Prints an error from running `source` to stderr, as diagnostics if it's
+/// one of the interpreter's own errors.
+pub fn report(error: &anyhow::Error, source: &str, format: Format) {
+    match from_error(error) {
+        Some(diagnostics) => diagnostics.iter().for_each(|d| emit(d, source, format)),
+        None => eprintln!("{}", error),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn diagnostic(start: usize, end: usize) -> Diagnostic {
+        Diagnostic {
+            code: codes::INTERNAL,
+            severity: Severity::Error,
+            message: "Something's wrong.".to_string(),
+            line: 2,
+            column: 7,
+            start,
+            end,
+            notes: vec![],
+            input: None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "var a = 1;\nprint a +;\n";
+        assert_eq!(
+            diagnostic(19, 20).render(source, false),
+            "error[E0399]: Something's wrong.\n \
+             --> [line 2, column 7]\n  \
+             |\n\
+             2 | print a +;\n  \
+             |         ^\n"
+        );
+
+        // Missing tokens are pointed at with a single caret.
+        assert!(diagnostic(20, 20)
+            .render(source, false)
+            .ends_with("2 | print a +;\n  |          ^\n"));
+
+        // Spans that don't belong to the source are shown without a snippet.
+        assert_eq!(
+            diagnostic(17, 99).render(source, false),
+            "error[E0399]: Something's wrong.\n--> [line 2, column 7]\n"
+        );
+
+        let mut repl = diagnostic(19, 20);
+        repl.input = Some(3);
+        assert!(repl
+            .render(source, false)
+            .contains("--> [repl:3, line 2, column 7]\n"));
+
+        assert!(diagnostic(17, 18)
+            .render(source, true)
+            .starts_with("\x1b[1;31merror[E0399]\x1b[0m"));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut diagnostic = diagnostic(19, 20);
+        diagnostic.message = "Say \"hi\"\n".to_string();
+        assert_eq!(
+            diagnostic.to_json(),
+            r#"{"code":"E0399","severity":"error","message":"Say \"hi\"\n","line":2,"column":7,"span":{"start":19,"end":20},"notes":[]}"#
+        );
+        diagnostic.input = Some(3);
+        assert!(diagnostic.to_json().ends_with(r#""notes":[],"input":3}"#));
+    }
+}
@@ -0,0 +1,225 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ast::Slot;
+use crate::diagnostic::codes;
+use crate::gc::{Heap, Object};
+use crate::interpreter::{RuntimeError, RuntimeResult, Types};
+use crate::scanner::Token;
+use crate::symbol::{FxHashMap, FxHashSet, Symbol};
+
+#[derive(Default)]
+pub struct Environment<'a> {
+    /// The variables of the global scope, which the resolver doesn't track
+    /// and so are looked up by their interned names.
+    values: FxHashMap<Symbol, Types<'a>>,
+    /// Names in `values` declared with `const`. Assignments to local
+    /// constants are rejected by the resolver instead.
+    constants: FxHashSet<Symbol>,
+    /// The variables of a local scope, in the order they were declared,
+    /// which is the index the resolver gave each of them.
+    slots: Vec<Types<'a>>,
+    /// The name of each of `slots`, for looking locals up by name.
+    names: Vec<Symbol>,
+    enclosing: Option<Rc<RefCell<Environment<'a>>>>,
+    /// Shared by every scope in the same interpreter.
+    heap: Rc<Heap<'a>>,
+}
+
+impl<'a> Environment<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment<'a>>>) -> Self {
+        let heap = Rc::clone(&enclosing.borrow().heap);
+        Environment {
+            values: Default::default(),
+            constants: Default::default(),
+            slots: vec![],
+            names: vec![],
+            enclosing: Some(enclosing),
+            heap,
+        }
+    }
+
+    /// Moves this scope into an `Rc` that closures can share, registering it
+    /// with the cycle collector.
+    pub fn into_shared(self) -> Rc<RefCell<Self>> {
+        let heap = Rc::clone(&self.heap);
+        let environment = Rc::new(RefCell::new(self));
+        heap.track_environment(&environment);
+        environment
+    }
+
+    pub(crate) fn heap(&self) -> &Rc<Heap<'a>> {
+        &self.heap
+    }
+
+    pub(crate) fn references(&self, out: &mut Vec<Object<'a>>) {
+        out.extend(
+            self.values
+                .values()
+                .chain(&self.slots)
+                .filter_map(Object::of),
+        );
+        if let Some(enclosing) = &self.enclosing {
+            out.push(Object::Environment(Rc::clone(enclosing)));
+        }
+    }
+
+    /// Removes every variable, for when the collector finds this scope is
+    /// garbage.
+    pub(crate) fn clear(&mut self) -> Vec<Types<'a>> {
+        self.constants.clear();
+        self.names.clear();
+        self.values
+            .drain()
+            .map(|(_, value)| value)
+            .chain(self.slots.drain(..))
+            .collect()
+    }
+
+    fn is_global(&self) -> bool {
+        self.enclosing.is_none()
+    }
+
+    /// Adds a variable to this scope. In a local scope it takes the next
+    /// slot, so locals have to be defined in the order they're declared.
+    pub fn define(&mut self, name: Symbol, value: Types<'a>) {
+        if self.is_global() {
+            self.constants.remove(&name);
+            self.values.insert(name, value);
+        } else {
+            self.slots.push(value);
+            self.names.push(name);
+        }
+    }
+
+    /// Defines `name` like [`Environment::define`], but rejects any later
+    /// assignment to it.
+    pub fn define_const(&mut self, name: Symbol, value: Types<'a>) {
+        self.define(name, value);
+        if self.is_global() {
+            self.constants.insert(name);
+        }
+    }
+
+    pub fn get(&self, name: &Token) -> RuntimeResult<Types<'a>> {
+        if self.is_global() {
+            return self
+                .values
+                .get(&name.symbol)
+                .cloned()
+                .ok_or_else(|| Self::undefined(name));
+        }
+
+        match (self.get_own(name.symbol), &self.enclosing) {
+            (Some(value), _) => Ok(value),
+            (None, Some(enclosing)) => enclosing.borrow().get(name),
+            (None, None) => Err(Self::undefined(name)),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Types<'a>) -> RuntimeResult<()> {
+        if self.is_global() {
+            return match self.values.get_mut(&name.symbol) {
+                Some(_) if self.constants.contains(&name.symbol) => Err(Self::constant(name)),
+                Some(v) => {
+                    *v = value;
+                    Ok(())
+                }
+                None => Err(Self::undefined(name)),
+            };
+        }
+
+        match (self.position(name.symbol), &self.enclosing) {
+            (Some(index), _) => {
+                self.slots[index] = value;
+                Ok(())
+            }
+            (None, Some(enclosing)) => enclosing.borrow_mut().assign(name, value),
+            (None, None) => Err(Self::undefined(name)),
+        }
+    }
+
+    /// The slot of the local `name` in this scope.
+    fn position(&self, name: Symbol) -> Option<usize> {
+        self.names.iter().rposition(|&local| local == name)
+    }
+
+    /// Reads `name` from this scope only, without looking at enclosing ones.
+    pub fn get_own(&self, name: Symbol) -> Option<Types<'a>> {
+        if self.is_global() {
+            self.values.get(&name).cloned()
+        } else {
+            self.position(name).map(|index| self.slots[index].clone())
+        }
+    }
+
+    /// Every variable defined directly in this scope.
+    pub fn own_values(&self) -> Vec<(Symbol, Types<'a>)> {
+        self.values
+            .iter()
+            .chain(self.names.iter().zip(&self.slots))
+            .map(|(&name, value)| (name, value.clone()))
+            .collect()
+    }
+
+    /// Reads the local in `slot`, as computed by the resolver. `name` is only
+    /// used to report errors.
+    pub fn get_at(&self, slot: Slot, name: &Token) -> RuntimeResult<Types<'a>> {
+        match (slot.depth, &self.enclosing) {
+            (0, _) => self
+                .slots
+                .get(slot.index)
+                .cloned()
+                .ok_or_else(|| Self::undefined(name)),
+            (depth, Some(enclosing)) => enclosing.borrow().get_at(
+                Slot {
+                    depth: depth - 1,
+                    ..slot
+                },
+                name,
+            ),
+            (_, None) => Err(Self::undefined(name)),
+        }
+    }
+
+    pub fn assign_at(&mut self, slot: Slot, name: &Token, value: Types<'a>) -> RuntimeResult<()> {
+        match (slot.depth, &self.enclosing) {
+            (0, _) => match self.slots.get_mut(slot.index) {
+                Some(v) => {
+                    *v = value;
+                    Ok(())
+                }
+                None => Err(Self::undefined(name)),
+            },
+            (depth, Some(enclosing)) => enclosing.borrow_mut().assign_at(
+                Slot {
+                    depth: depth - 1,
+                    ..slot
+                },
+                name,
+                value,
+            ),
+            (_, None) => Err(Self::undefined(name)),
+        }
+    }
+
+    fn undefined(name: &Token) -> RuntimeError {
+        RuntimeError::new(
+            name,
+            codes::UNDEFINED_VARIABLE,
+            &format!("Undefined variable '{}'.", name.lexeme),
+        )
+    }
+
+    fn constant(name: &Token) -> RuntimeError {
+        RuntimeError::new(
+            name,
+            codes::ASSIGN_TO_CONSTANT,
+            &format!("Can't assign to constant '{}'.", name.lexeme),
+        )
+    }
+}
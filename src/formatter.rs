@@ -0,0 +1,798 @@
+//! Rewrites Lox source in one canonical layout: two-space indentation, one
+//! statement per line, spaces around binary operators, and no doubled
+//! parentheses. Comments and single blank lines between statements are kept.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::ast::{Ast, ExprId, Expression, FunctionDeclaration, Stmt, Visitor};
+use crate::parser::Parser;
+use crate::scanner::{Comment, Scanner, Token, TokenType};
+
+const INDENT: &str = "  ";
+
+/// `source` formatted, or the errors that stop it from being parsed.
+pub fn format(source: &str) -> Result<String> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens()?.to_vec();
+    let parser = Parser::new(&tokens);
+    let statements = parser.parse()?;
+    let ast = parser.into_ast();
+    Ok(Formatter::new(source, &ast, &tokens, scanner.comments()).program(&statements))
+}
+
+/// The lines that differ between `old` and `new`, in the style of a unified
+/// diff without context lines.
+pub fn diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        out.push_str(&format!("@@ -{} +{} @@\n", i + 1, j + 1));
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                break;
+            }
+            if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+                out.push_str(&format!("-{}\n", old[i]));
+                i += 1;
+            } else {
+                out.push_str(&format!("+{}\n", new[j]));
+                j += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Walks the syntax tree printing source for it, and keeps track of how far
+/// through the token stream it has got so that comments, which aren't in
+/// the tree, can be put back where they were.
+struct Formatter<'r, 'a> {
+    source: &'a str,
+    ast: &'r Ast<'a>,
+    tokens: &'r [Token<'a>],
+    comments: &'r [Comment<'a>],
+    /// Index of the first comment not yet written out.
+    next_comment: Cell<usize>,
+    /// Byte offset just past the last token written out.
+    cursor: Cell<usize>,
+    /// The line of the last token or comment written out.
+    line: Cell<usize>,
+    indent: Cell<usize>,
+    /// The index of each `{` token's matching `}`.
+    closing: HashMap<usize, usize>,
+}
+
+impl<'r, 'a> Formatter<'r, 'a> {
+    fn new(
+        source: &'a str,
+        ast: &'r Ast<'a>,
+        tokens: &'r [Token<'a>],
+        comments: &'r [Comment<'a>],
+    ) -> Self {
+        let mut closing = HashMap::new();
+        let mut open = vec![];
+        for (i, token) in tokens.iter().enumerate() {
+            match token.token_type {
+                TokenType::LeftBrace => open.push(i),
+                TokenType::RightBrace => {
+                    if let Some(start) = open.pop() {
+                        closing.insert(start, i);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Formatter {
+            source,
+            ast,
+            tokens,
+            comments,
+            next_comment: Cell::new(0),
+            cursor: Cell::new(0),
+            line: Cell::new(1),
+            indent: Cell::new(0),
+            closing,
+        }
+    }
+
+    fn program(&self, statements: &[Stmt]) -> String {
+        self.lines(statements, usize::MAX, |s| self.statement(s))
+    }
+
+    /// Records that everything up to the end of `token` has been written.
+    /// Tokens the parser made up, which have no place in the source, are
+    /// ignored.
+    fn mark(&self, token: &Token) {
+        if token.end > self.cursor.get() {
+            self.cursor.set(token.end);
+            self.line.set(token.line);
+        }
+    }
+
+    /// The index of the first token that hasn't been written yet.
+    fn next_index(&self) -> usize {
+        let cursor = self.cursor.get();
+        self.tokens.partition_point(|t| t.start < cursor)
+    }
+
+    /// The index of the next unwritten token of the given type.
+    fn find(&self, token_type: &TokenType) -> Option<usize> {
+        (self.next_index()..self.tokens.len()).find(|&i| &self.tokens[i].token_type == token_type)
+    }
+
+    /// Marks the next unwritten token of the given type, for punctuation
+    /// the tree doesn't keep, like a statement's `;`.
+    fn mark_next(&self, token_type: &TokenType) {
+        if let Some(i) = self.find(token_type) {
+            self.mark(&self.tokens[i]);
+        }
+    }
+
+    /// Whether the next of `keyword`, `while` or `{` to come is `keyword`.
+    /// Loops are desugared by the parser, so this is how a `for` loop is
+    /// told apart from the `while` or block it became.
+    fn next_keyword_is(&self, keyword: &TokenType) -> bool {
+        self.tokens[self.next_index()..]
+            .iter()
+            .find(|t| {
+                matches!(
+                    t.token_type,
+                    TokenType::For | TokenType::While | TokenType::LeftBrace
+                )
+            })
+            .is_some_and(|t| &t.token_type == keyword)
+    }
+
+    fn indentation(&self) -> String {
+        INDENT.repeat(self.indent.get())
+    }
+
+    /// Writes out, one per line, the comments that start before `offset`.
+    fn comments_before(&self, out: &mut String, offset: usize) {
+        while let Some(comment) = self
+            .comments
+            .get(self.next_comment.get())
+            .filter(|c| c.start < offset)
+        {
+            if !out.is_empty() && comment.line > self.line.get() + 1 {
+                out.push('\n');
+            }
+            out.push_str(&self.indentation());
+            out.push_str(comment.text);
+            out.push('\n');
+            self.wrote_comment(comment);
+        }
+    }
+
+    /// The comments that follow the last token written on the same line,
+    /// and come before the next one.
+    fn trailing_comments(&self) -> String {
+        let next = self
+            .tokens
+            .get(self.next_index())
+            .map_or(usize::MAX, |t| t.start);
+        let mut out = String::new();
+        while let Some(comment) = self
+            .comments
+            .get(self.next_comment.get())
+            .filter(|c| c.line == self.line.get() && c.start < next)
+        {
+            out.push(' ');
+            out.push_str(comment.text);
+            self.wrote_comment(comment);
+        }
+        out
+    }
+
+    fn wrote_comment(&self, comment: &Comment) {
+        self.next_comment.set(self.next_comment.get() + 1);
+        self.line
+            .set(comment.line + comment.text.matches('\n').count());
+    }
+
+    /// Writes each item on its own lines at the current indentation, along
+    /// with the comments before `end`. A blank line between items in the
+    /// source is kept.
+    fn lines<T>(&self, items: &[T], end: usize, item: impl Fn(&T) -> String) -> String {
+        let mut out = String::new();
+        for it in items {
+            if let Some(next) = self.tokens.get(self.next_index()) {
+                self.comments_before(&mut out, next.start);
+                if !out.is_empty() && next.line > self.line.get() + 1 {
+                    out.push('\n');
+                }
+            }
+
+            out.push_str(&self.indentation());
+            out.push_str(&item(it));
+            out.push_str(&self.trailing_comments());
+            out.push('\n');
+        }
+        self.comments_before(&mut out, end);
+        out
+    }
+
+    /// Items between the next `{` and its `}`, indented one level further.
+    fn braced<T>(&self, items: &[T], item: impl Fn(&T) -> String) -> String {
+        let open = self.find(&TokenType::LeftBrace);
+        let close = open
+            .and_then(|i| self.closing.get(&i))
+            .map(|&i| &self.tokens[i]);
+        if let Some(open) = open {
+            self.mark(&self.tokens[open]);
+        }
+
+        let mut out = String::from("{");
+        out.push_str(&self.trailing_comments());
+        self.indent.set(self.indent.get() + 1);
+        let body = self.lines(items, close.map_or(usize::MAX, |t| t.start), item);
+        self.indent.set(self.indent.get() - 1);
+        if let Some(close) = close {
+            self.mark(close);
+        }
+
+        if body.is_empty() && out.len() == 1 {
+            out.push('}');
+        } else {
+            out.push('\n');
+            out.push_str(&body);
+            out.push_str(&self.indentation());
+            out.push('}');
+        }
+        out
+    }
+
+    fn block(&self, statements: &[Stmt]) -> String {
+        self.braced(statements, |s| self.statement(s))
+    }
+
+    /// A statement written after some other code on the same line, like
+    /// the body of an `if`.
+    fn body(&self, stmt: &Stmt) -> String {
+        format!(" {}", self.statement(stmt))
+    }
+
+    fn name(&self, token: &Token<'a>) -> &'a str {
+        self.mark(token);
+        token.lexeme
+    }
+
+    /// Names separated by commas, the last written as a rest parameter if
+    /// `variadic`.
+    fn parameters(&self, params: &[Token<'a>], variadic: bool) -> String {
+        let mut params: Vec<String> = params.iter().map(|p| self.name(p).to_string()).collect();
+        if let (true, Some(last)) = (variadic, params.last_mut()) {
+            last.insert_str(0, "...");
+        }
+        self.mark_next(&TokenType::RightParen);
+        params.join(", ")
+    }
+
+    fn function(&self, declaration: &FunctionDeclaration<'a>) -> String {
+        let name = self.name(&declaration.name);
+        if declaration.is_getter {
+            return format!("{} {}", name, self.block(&declaration.body));
+        }
+
+        let params = self.parameters(&declaration.params, declaration.variadic);
+        format!("{}({}) {}", name, params, self.block(&declaration.body))
+    }
+
+    fn statement(&self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Block { statements, .. } => match statements.as_slice() {
+                [initializer, Stmt::While {
+                    condition,
+                    body,
+                    increment,
+                    ..
+                }] if self.next_keyword_is(&TokenType::For) => {
+                    self.for_loop(Some(initializer), *condition, *increment, body)
+                }
+                _ => self.block(statements),
+            },
+            Stmt::Break { keyword, .. } | Stmt::Continue { keyword, .. } => {
+                let keyword = self.name(keyword);
+                self.mark_next(&TokenType::Semicolon);
+                format!("{};", keyword)
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                mixins,
+                methods,
+                class_methods,
+                ..
+            } => {
+                let mut out = format!("class {}", self.name(name));
+                if let Some(superclass) = superclass {
+                    out.push_str(&format!(" < {}", self.visit_expression(*superclass)));
+                }
+                if !mixins.is_empty() {
+                    let mixins: Vec<String> =
+                        mixins.iter().map(|m| self.visit_expression(*m)).collect();
+                    out.push_str(&format!(" with {}", mixins.join(", ")));
+                }
+
+                let mut all: Vec<(bool, &FunctionDeclaration)> = methods
+                    .iter()
+                    .map(|m| (false, m.as_ref()))
+                    .chain(class_methods.iter().map(|m| (true, m.as_ref())))
+                    .collect();
+                all.sort_by_key(|(_, m)| m.name.start);
+                let body = self.braced(&all, |(is_class, method)| {
+                    let method = self.function(method);
+                    if *is_class {
+                        format!("class {}", method)
+                    } else {
+                        method
+                    }
+                });
+                format!("{} {}", out, body)
+            }
+            Stmt::Expression { expr, .. } => {
+                let expr = self.visit_expression(*expr);
+                self.mark_next(&TokenType::Semicolon);
+                format!("{};", expr)
+            }
+            Stmt::ForIn {
+                name,
+                keyword,
+                iterable,
+                body,
+                ..
+            } => {
+                let name = self.name(name);
+                self.mark(keyword);
+                let iterable = self.visit_expression(*iterable);
+                self.mark_next(&TokenType::RightParen);
+                format!("for ({} in {}){}", name, iterable, self.body(body))
+            }
+            Stmt::Function { declaration, .. } => format!("fun {}", self.function(declaration)),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let condition = self.visit_expression(*condition);
+                self.mark_next(&TokenType::RightParen);
+                let mut out = format!("if ({}){}", condition, self.body(then_branch));
+                if let Some(else_branch) = else_branch {
+                    if let Stmt::Block { .. } = **then_branch {
+                        out.push(' ');
+                    } else {
+                        out.push('\n');
+                        out.push_str(&self.indentation());
+                    }
+                    out.push_str(&format!("else{}", self.body(else_branch)));
+                }
+                out
+            }
+            Stmt::Import {
+                keyword,
+                path,
+                alias,
+                ..
+            } => {
+                self.mark(keyword);
+                let mut out = format!("import {}", self.name(path));
+                if let Some(alias) = alias {
+                    out.push_str(&format!(" as {}", self.name(alias)));
+                }
+                self.mark_next(&TokenType::Semicolon);
+                out + ";"
+            }
+            Stmt::Print { expr, .. } => {
+                let expr = self.visit_expression(*expr);
+                self.mark_next(&TokenType::Semicolon);
+                format!("print {};", expr)
+            }
+            Stmt::Return { keyword, value, .. } => {
+                self.mark(keyword);
+                let out = match value {
+                    Some(value) => format!("return {};", self.visit_expression(*value)),
+                    None => "return;".to_string(),
+                };
+                self.mark_next(&TokenType::Semicolon);
+                out
+            }
+            Stmt::Throw { keyword, value, .. } => {
+                self.mark(keyword);
+                let value = self.visit_expression(*value);
+                self.mark_next(&TokenType::Semicolon);
+                format!("throw {};", value)
+            }
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                let body = self.block(body);
+                let name = self.name(name);
+                self.mark_next(&TokenType::RightParen);
+                format!("try {} catch ({}) {}", body, name, self.block(handler))
+            }
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+                ..
+            } => {
+                let keyword = if *is_const { "const" } else { "var" };
+                let mut out = format!("{} {}", keyword, self.name(name));
+                if let Some(initializer) = initializer {
+                    out.push_str(&format!(" = {}", self.visit_expression(*initializer)));
+                }
+                self.mark_next(&TokenType::Semicolon);
+                out + ";"
+            }
+            Stmt::Destructure {
+                paren,
+                names,
+                initializer,
+                is_const,
+                ..
+            } => {
+                let keyword = if *is_const { "const" } else { "var" };
+                self.mark(paren);
+                let names = self.parameters(names, false);
+                let initializer = self.visit_expression(*initializer);
+                self.mark_next(&TokenType::Semicolon);
+                format!("{} ({}) = {};", keyword, names, initializer)
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                if self.next_keyword_is(&TokenType::For) {
+                    return self.for_loop(None, *condition, *increment, body);
+                }
+
+                let condition = self.visit_expression(*condition);
+                self.mark_next(&TokenType::RightParen);
+                format!("while ({}){}", condition, self.body(body))
+            }
+        }
+    }
+
+    /// A `for` loop put back together from the `while` loop it was
+    /// desugared into.
+    fn for_loop(
+        &self,
+        initializer: Option<&Stmt>,
+        condition: ExprId,
+        increment: Option<ExprId>,
+        body: &Stmt,
+    ) -> String {
+        let mut out = String::from("for (");
+        match initializer {
+            Some(initializer) => out.push_str(&self.statement(initializer)),
+            None => {
+                self.mark_next(&TokenType::Semicolon);
+                out.push(';');
+            }
+        }
+
+        // A missing condition is filled in by the parser with a `true` that
+        // isn't in the source.
+        match &self.ast[condition] {
+            Expression::Literal { token } if token.end == 0 => {}
+            _ => {
+                out.push(' ');
+                out.push_str(&self.visit_expression(condition));
+            }
+        }
+        self.mark_next(&TokenType::Semicolon);
+        out.push(';');
+
+        if let Some(increment) = increment {
+            out.push(' ');
+            out.push_str(&self.visit_expression(increment));
+        }
+        self.mark_next(&TokenType::RightParen);
+        out.push(')');
+        out.push_str(&self.body(body));
+        out
+    }
+
+    /// If `value` is what the parser made of the right-hand side of a
+    /// compound assignment like `a += b`, the operator and `b`.
+    fn compound(&self, value: ExprId) -> Option<(&'r Token<'a>, ExprId)> {
+        match &self.ast[value] {
+            Expression::Binary {
+                operator, r_expr, ..
+            } if matches!(
+                operator.token_type,
+                TokenType::Plus | TokenType::Minus | TokenType::Star | TokenType::Slash
+            ) && self.source[operator.end..].starts_with('=') =>
+            {
+                Some((operator, *r_expr))
+            }
+            _ => None,
+        }
+    }
+
+    /// ` = value`, or for a compound assignment ` += b`.
+    fn assigned(&self, value: ExprId) -> String {
+        match self.compound(value) {
+            Some((operator, value)) => self.assigned_with(Some(operator), value),
+            None => self.assigned_with(None, value),
+        }
+    }
+
+    /// ` = value`, or ` += value` if `operator` is `+`.
+    fn assigned_with(&self, operator: Option<&Token>, value: ExprId) -> String {
+        match operator {
+            Some(operator) => {
+                self.mark(operator);
+                format!(" {}= {}", operator.lexeme, self.visit_expression(value))
+            }
+            None => format!(" = {}", self.visit_expression(value)),
+        }
+    }
+
+    fn expressions(&self, expressions: &[ExprId]) -> String {
+        let expressions: Vec<String> = expressions
+            .iter()
+            .map(|e| self.visit_expression(*e))
+            .collect();
+        expressions.join(", ")
+    }
+}
+
+impl<'r, 'a> Visitor<'a> for Formatter<'r, 'a> {
+    type E = String;
+    fn visit_expression(&self, e: ExprId) -> Self::E {
+        match &self.ast[e] {
+            Expression::Assign { name, value, .. } => {
+                format!("{}{}", self.name(name), self.assigned(*value))
+            }
+            Expression::Binary {
+                l_expr,
+                operator,
+                r_expr,
+            }
+            | Expression::Logical {
+                l_expr,
+                operator,
+                r_expr,
+            } => {
+                let left = self.visit_expression(*l_expr);
+                let operator = self.name(operator);
+                let right = self.visit_expression(*r_expr);
+                if operator == "," {
+                    format!("{}, {}", left, right)
+                } else {
+                    format!("{} {} {}", left, operator, right)
+                }
+            }
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                let callee = self.visit_expression(*callee);
+                let arguments = self.expressions(arguments);
+                self.mark_next(&TokenType::RightParen);
+                format!("{}({})", callee, arguments)
+            }
+            Expression::Function { declaration } => {
+                self.mark(&declaration.name);
+                let params = self.parameters(&declaration.params, declaration.variadic);
+                match declaration.body.as_slice() {
+                    [Stmt::Return {
+                        keyword,
+                        value: Some(value),
+                        ..
+                    }] if keyword.token_type == TokenType::Arrow => {
+                        self.mark(keyword);
+                        format!("fun ({}) => {}", params, self.visit_expression(*value))
+                    }
+                    body => format!("fun ({}) {}", params, self.block(body)),
+                }
+            }
+            Expression::Get {
+                object,
+                name,
+                optional,
+            } => {
+                let dot = if *optional { "?." } else { "." };
+                format!(
+                    "{}{}{}",
+                    self.visit_expression(*object),
+                    dot,
+                    self.name(name)
+                )
+            }
+            Expression::Grouping { expr } => {
+                // `((a))` is written `(a)`.
+                let mut inner = *expr;
+                let mut depth = 1;
+                while let Expression::Grouping { expr } = &self.ast[inner] {
+                    inner = *expr;
+                    depth += 1;
+                }
+                let inner = self.visit_expression(inner);
+                for _ in 0..depth {
+                    self.mark_next(&TokenType::RightParen);
+                }
+                format!("({})", inner)
+            }
+            Expression::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.visit_expression(*object);
+                self.mark(bracket);
+                let index = self.visit_expression(*index);
+                self.mark_next(&TokenType::RightBracket);
+                format!("{}[{}]", object, index)
+            }
+            Expression::List { bracket, elements } => {
+                self.mark(bracket);
+                let elements = self.expressions(elements);
+                self.mark_next(&TokenType::RightBracket);
+                format!("[{}]", elements)
+            }
+            Expression::Named { name, value } => {
+                format!("{}: {}", self.name(name), self.visit_expression(*value))
+            }
+            Expression::Spread { ellipsis, expr } => {
+                self.mark(ellipsis);
+                format!("...{}", self.visit_expression(*expr))
+            }
+            Expression::Tuple { paren, elements } if paren.token_type == TokenType::Return => {
+                self.expressions(elements)
+            }
+            Expression::Tuple { paren, elements } => {
+                self.mark(paren);
+                let mut out = self.expressions(elements);
+                if elements.len() == 1 {
+                    out.push(',');
+                }
+                self.mark_next(&TokenType::RightParen);
+                format!("({})", out)
+            }
+            Expression::Map { brace, entries } => {
+                let open = self.next_index();
+                self.mark(brace);
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = self.visit_expression(*key);
+                        format!("{}: {}", key, self.visit_expression(*value))
+                    })
+                    .collect();
+                if let Some(&close) = self.closing.get(&open) {
+                    self.mark(&self.tokens[close]);
+                }
+                format!("{{{}}}", entries.join(", "))
+            }
+            Expression::Literal { token } => self.name(token).to_string(),
+            Expression::Set {
+                object,
+                name,
+                value,
+                operator,
+            } => {
+                let object = self.visit_expression(*object);
+                let name = self.name(name);
+                let assigned = self.assigned_with(operator.as_ref(), *value);
+                format!("{}.{}{}", object, name, assigned)
+            }
+            Expression::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+                operator,
+            } => {
+                let object = self.visit_expression(*object);
+                self.mark(bracket);
+                let index = self.visit_expression(*index);
+                self.mark_next(&TokenType::RightBracket);
+                let assigned = self.assigned_with(operator.as_ref(), *value);
+                format!("{}[{}]{}", object, index, assigned)
+            }
+            Expression::Super {
+                keyword, method, ..
+            } => {
+                self.mark(keyword);
+                format!("super.{}", self.name(method))
+            }
+            Expression::This { keyword, .. } => self.name(keyword).to_string(),
+            Expression::Unary { operator, r_expr } => {
+                let operator = self.name(operator);
+                format!("{}{}", operator, self.visit_expression(*r_expr))
+            }
+            Expression::Variable { name, .. } => self.name(name).to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let source = "// Counts.\nvar  a=((1+2))*3;// three\n\n\n\
+                      for(var i=0;i<a;i+=1){if(i==1)print i;else{print -i;}}\n\
+                      class B<A with M{init(x){this.x=x;}class make(){return B(1);}size{return 1;}\n/* end */}\n\
+                      var f=fun(x)=>x and !x;\nwhile(true){break;}\nfor(;;){}\n";
+        assert_eq!(
+            format(source).unwrap(),
+            "// Counts.\n\
+             var a = (1 + 2) * 3; // three\n\
+             \n\
+             for (var i = 0; i < a; i += 1) {\n  \
+               if (i == 1) print i;\n  \
+               else {\n    \
+                 print -i;\n  \
+               }\n\
+             }\n\
+             class B < A with M {\n  \
+               init(x) {\n    \
+                 this.x = x;\n  \
+               }\n  \
+               class make() {\n    \
+                 return B(1);\n  \
+               }\n  \
+               size {\n    \
+                 return 1;\n  \
+               }\n  \
+               /* end */\n\
+             }\n\
+             var f = fun (x) => x and !x;\n\
+             while (true) {\n  \
+               break;\n\
+             }\n\
+             for (;;) {}\n"
+        );
+    }
+
+    #[test]
+    fn test_format_is_idempotent() {
+        let source = "var m = {\"a\": [1, 2][0], b: nil};\n\
+                      try {\n  throw m;\n} catch (e) {\n  print e[\"a\"];\n}\n";
+        assert_eq!(format(source).unwrap(), source);
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nc\n"), "");
+        assert_eq!(
+            diff("a\nb\nc\n", "a\nx\nc\nd\n"),
+            "@@ -2 +2 @@\n-b\n+x\n@@ -4 +4 @@\n+d\n"
+        );
+    }
+}
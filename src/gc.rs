@@ -0,0 +1,272 @@
+//! Frees values that only keep each other alive, like a function declared
+//! in a block that refers to itself, which reference counting never drops.
+
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::callable::LoxFunction;
+use crate::class::{LoxClass, LoxInstance};
+use crate::environment::Environment;
+use crate::interpreter::Types;
+use crate::map::LoxMap;
+use crate::module::LoxModule;
+
+/// How many objects are tracked before the first collection.
+const MIN_THRESHOLD: usize = 1024;
+
+/// Every environment and instance an interpreter has created, which is where
+/// cycles start: other values only join one through these.
+#[derive(Default)]
+pub(crate) struct Heap<'a> {
+    environments: RefCell<Vec<Weak<RefCell<Environment<'a>>>>>,
+    instances: RefCell<Vec<Weak<LoxInstance<'a>>>>,
+    /// How many tracked objects there may be before looking for garbage.
+    threshold: Cell<usize>,
+}
+
+impl<'a> Heap<'a> {
+    pub fn track_environment(&self, environment: &Rc<RefCell<Environment<'a>>>) {
+        self.environments
+            .borrow_mut()
+            .push(Rc::downgrade(environment));
+        self.maybe_collect();
+    }
+
+    pub fn track_instance(&self, instance: &Rc<LoxInstance<'a>>) {
+        self.instances.borrow_mut().push(Rc::downgrade(instance));
+        self.maybe_collect();
+    }
+
+    /// Collects once the number of tracked objects has doubled since the
+    /// last collection, so the work done stays in proportion to allocation.
+    fn maybe_collect(&self) {
+        let tracked = self.environments.borrow().len() + self.instances.borrow().len();
+        if tracked >= self.threshold.get().max(MIN_THRESHOLD) {
+            self.collect();
+        }
+    }
+
+    /// Breaks every cycle of values that nothing outside the cycle can reach,
+    /// returning how many objects were freed.
+    ///
+    /// Nothing records which values the running program holds on to, so the
+    /// roots are found by counting instead: an object with more strong
+    /// references than the objects traced here account for is held from
+    /// somewhere else, like a variable on the Rust stack, and is live.
+    pub fn collect(&self) -> usize {
+        let mut graph = Graph::default();
+        for environment in self.environments.borrow().iter() {
+            if let Some(environment) = environment.upgrade() {
+                graph.add(Object::Environment(environment));
+            }
+        }
+        for instance in self.instances.borrow().iter() {
+            if let Some(instance) = instance.upgrade() {
+                graph.add(Object::Instance(instance));
+            }
+        }
+        graph.trace();
+
+        let garbage = graph.garbage();
+        let freed = garbage.len();
+        // Dropped only once nothing is borrowed, since dropping the last
+        // reference to a value can free others.
+        let mut released = vec![];
+        for object in garbage {
+            object.clear(&mut released);
+        }
+        drop(released);
+        drop(graph);
+
+        self.environments
+            .borrow_mut()
+            .retain(|environment| environment.strong_count() > 0);
+        self.instances
+            .borrow_mut()
+            .retain(|instance| instance.strong_count() > 0);
+        let tracked = self.environments.borrow().len() + self.instances.borrow().len();
+        self.threshold.set(tracked * 2);
+        freed
+    }
+}
+
+/// A reference-counted value that can hold references to others.
+#[derive(Clone)]
+pub(crate) enum Object<'a> {
+    Environment(Rc<RefCell<Environment<'a>>>),
+    Function(Rc<LoxFunction<'a>>),
+    Class(Rc<LoxClass<'a>>),
+    Instance(Rc<LoxInstance<'a>>),
+    List(Rc<RefCell<Vec<Types<'a>>>>),
+    Map(Rc<RefCell<LoxMap<'a>>>),
+    Tuple(Rc<Vec<Types<'a>>>),
+    Module(Rc<LoxModule<'a>>),
+}
+
+impl<'a> Object<'a> {
+    /// The object `value` refers to, if it refers to one that can be part of
+    /// a cycle.
+    pub fn of(value: &Types<'a>) -> Option<Self> {
+        Some(match value {
+            Types::Function(function) => Object::Function(Rc::clone(function)),
+            Types::Class(class) => Object::Class(Rc::clone(class)),
+            Types::Instance(instance) => Object::Instance(Rc::clone(instance)),
+            Types::List(list) => Object::List(Rc::clone(list)),
+            Types::Map(map) => Object::Map(Rc::clone(map)),
+            Types::Tuple(tuple) => Object::Tuple(Rc::clone(tuple)),
+            Types::Module(module) => Object::Module(Rc::clone(module)),
+            Types::Number(_)
+            | Types::ReturnString(_)
+            | Types::Boolean(_)
+            | Types::Nil
+            | Types::NativeFunction(_) => return None,
+        })
+    }
+
+    fn id(&self) -> usize {
+        match self {
+            Object::Environment(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Function(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Class(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Instance(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::List(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Map(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Tuple(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Object::Module(rc) => Rc::as_ptr(rc) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(rc) => Rc::strong_count(rc),
+            Object::Function(rc) => Rc::strong_count(rc),
+            Object::Class(rc) => Rc::strong_count(rc),
+            Object::Instance(rc) => Rc::strong_count(rc),
+            Object::List(rc) => Rc::strong_count(rc),
+            Object::Map(rc) => Rc::strong_count(rc),
+            Object::Tuple(rc) => Rc::strong_count(rc),
+            Object::Module(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    /// Adds the objects this one holds strong references to to `out`, or
+    /// returns false if they can't be seen because it's being changed.
+    fn references(&self, out: &mut Vec<Object<'a>>) -> bool {
+        let values = |values: &mut dyn Iterator<Item = &Types<'a>>, out: &mut Vec<Object<'a>>| {
+            out.extend(values.filter_map(Object::of))
+        };
+        match self {
+            Object::Environment(environment) => match environment.try_borrow() {
+                Ok(environment) => environment.references(out),
+                Err(_) => return false,
+            },
+            Object::Function(function) => {
+                out.push(Object::Environment(Rc::clone(function.closure())))
+            }
+            Object::Class(class) => class.references(out),
+            Object::Instance(instance) => return instance.references(out),
+            Object::List(list) => match list.try_borrow() {
+                Ok(list) => values(&mut list.iter(), out),
+                Err(_) => return false,
+            },
+            Object::Map(map) => match map.try_borrow() {
+                Ok(map) => values(&mut map.iter().map(|(_, value)| value), out),
+                Err(_) => return false,
+            },
+            Object::Tuple(tuple) => values(&mut tuple.iter(), out),
+            Object::Module(module) => {
+                out.push(Object::Environment(Rc::clone(module.environment())))
+            }
+        }
+        true
+    }
+
+    /// Empties a garbage object, moving what it held to `released`.
+    fn clear(&self, released: &mut Vec<Types<'a>>) {
+        match self {
+            Object::Environment(environment) => {
+                released.extend(environment.borrow_mut().clear());
+            }
+            Object::Instance(instance) => released.extend(instance.clear()),
+            Object::List(list) => released.append(&mut list.borrow_mut()),
+            Object::Map(map) => {
+                let map = std::mem::take(&mut *map.borrow_mut());
+                released.extend(map.iter().map(|(_, value)| value.clone()));
+            }
+            // These can't change once they're made, but the cycles through
+            // them also go through one of the objects above.
+            Object::Function(_) | Object::Class(_) | Object::Tuple(_) | Object::Module(_) => {}
+        }
+    }
+}
+
+/// The objects reachable from the tracked ones, with a strong reference to
+/// each held here.
+#[derive(Default)]
+struct Graph<'a> {
+    objects: HashMap<usize, Object<'a>>,
+    /// What each object references, by id.
+    edges: HashMap<usize, Vec<usize>>,
+    /// Objects whose references couldn't be traced, which have to be live.
+    pinned: Vec<usize>,
+    pending: Vec<Object<'a>>,
+}
+
+impl<'a> Graph<'a> {
+    fn add(&mut self, object: Object<'a>) {
+        if let Entry::Vacant(entry) = self.objects.entry(object.id()) {
+            entry.insert(object.clone());
+            self.pending.push(object);
+        }
+    }
+
+    fn trace(&mut self) {
+        while let Some(object) = self.pending.pop() {
+            let mut references = vec![];
+            if !object.references(&mut references) {
+                self.pinned.push(object.id());
+            }
+            self.edges
+                .insert(object.id(), references.iter().map(Object::id).collect());
+            for reference in references {
+                self.add(reference);
+            }
+        }
+    }
+
+    /// The objects that can't be reached from outside the graph.
+    fn garbage(&self) -> Vec<Object<'a>> {
+        let mut internal: HashMap<usize, usize> = HashMap::new();
+        for targets in self.edges.values() {
+            for &target in targets {
+                *internal.entry(target).or_default() += 1;
+            }
+        }
+
+        // One strong reference to each object is the graph's own.
+        let mut live: Vec<usize> = self
+            .objects
+            .iter()
+            .filter(|(id, object)| {
+                object.strong_count() - 1 > internal.get(id).copied().unwrap_or(0)
+            })
+            .map(|(&id, _)| id)
+            .chain(self.pinned.iter().copied())
+            .collect();
+
+        let mut reached = HashSet::new();
+        while let Some(id) = live.pop() {
+            if reached.insert(id) {
+                live.extend(self.edges.get(&id).into_iter().flatten().copied());
+            }
+        }
+
+        self.objects
+            .iter()
+            .filter(|(id, _)| !reached.contains(id))
+            .map(|(_, object)| object.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,146 @@
+//! Coloring Lox source for a terminal, as it's typed into the REPL.
+
+use crate::scanner::{Scanner, TokenType};
+
+const RESET: &str = "\x1b[0m";
+const KEYWORD: &str = "\x1b[1;35m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const COMMENT: &str = "\x1b[90m";
+/// For a closing bracket with nothing to close.
+const UNMATCHED: &str = "\x1b[2m";
+
+/// `source` with keywords, strings, numbers and comments colored, and
+/// closing brackets that don't match an opening one dimmed. Unfinished
+/// source is colored as far as it can be scanned, and a string that hasn't
+/// been closed yet is colored to the end.
+pub fn highlight(source: &str) -> String {
+    let mut scanner = Scanner::new(source);
+    let scanned = scanner.scan_tokens().is_ok();
+
+    // The byte span and color of each piece of source that has one.
+    let mut spans: Vec<(usize, usize, &str)> = vec![];
+    let mut open = vec![];
+    for token in scanner.tokens() {
+        let color = match token.token_type {
+            TokenType::StringLiteral { .. } => Some(STRING),
+            TokenType::Number { .. } => Some(NUMBER),
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => {
+                open.push(token.token_type.clone());
+                None
+            }
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => {
+                let opener = match token.token_type {
+                    TokenType::RightParen => TokenType::LeftParen,
+                    TokenType::RightBrace => TokenType::LeftBrace,
+                    _ => TokenType::LeftBracket,
+                };
+                if open.last() == Some(&opener) {
+                    open.pop();
+                    None
+                } else {
+                    Some(UNMATCHED)
+                }
+            }
+            ref t if is_keyword(t) => Some(KEYWORD),
+            _ => None,
+        };
+        if let Some(color) = color {
+            spans.push((token.start, token.end, color));
+        }
+    }
+    for comment in scanner.comments() {
+        spans.push((comment.start, comment.end, COMMENT));
+    }
+
+    // Scanning stopped at an error. If it's a string or comment that
+    // hasn't ended yet, color the rest as one.
+    if !scanned {
+        let end = scanner
+            .tokens()
+            .iter()
+            .filter(|t| !matches!(t.token_type, TokenType::Error | TokenType::Eof))
+            .map(|t| t.end)
+            .chain(scanner.comments().iter().map(|c| c.end))
+            .max()
+            .unwrap_or(0);
+        let rest = &source[end..];
+        let start = end + (rest.len() - rest.trim_start().len());
+        let rest = &source[start..];
+        if rest.starts_with('"') {
+            spans.push((start, source.len(), STRING));
+        } else if rest.starts_with("/*") {
+            spans.push((start, source.len(), COMMENT));
+        }
+    }
+
+    spans.sort_by_key(|&(start, _, _)| start);
+    let mut out = String::new();
+    let mut written = 0;
+    for (start, end, color) in spans {
+        if start < written {
+            continue;
+        }
+        out.push_str(&source[written..start]);
+        out.push_str(color);
+        out.push_str(&source[start..end]);
+        out.push_str(RESET);
+        written = end;
+    }
+    out.push_str(&source[written..]);
+    out
+}
+
+fn is_keyword(token_type: &TokenType) -> bool {
+    use TokenType::*;
+    matches!(
+        token_type,
+        And | As
+            | Break
+            | Catch
+            | Class
+            | Const
+            | Continue
+            | Else
+            | False
+            | Fun
+            | For
+            | If
+            | Import
+            | In
+            | Nil
+            | Or
+            | Print
+            | Return
+            | Super
+            | This
+            | Throw
+            | True
+            | Try
+            | Var
+            | While
+            | With
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            highlight("var a = 1; // one"),
+            "\x1b[1;35mvar\x1b[0m a = \x1b[33m1\x1b[0m; \x1b[90m// one\x1b[0m"
+        );
+        assert_eq!(
+            highlight("print (\"é\"));"),
+            "\x1b[1;35mprint\x1b[0m (\x1b[32m\"é\"\x1b[0m)\x1b[2m)\x1b[0m;"
+        );
+        assert_eq!(
+            highlight("print \"abc"),
+            "\x1b[1;35mprint\x1b[0m \x1b[32m\"abc\x1b[0m"
+        );
+        assert_eq!(highlight("x /* a"), "x \x1b[90m/* a\x1b[0m");
+    }
+}
@@ -0,0 +1,267 @@
+//! Everything an interpreter needs from the world outside it, so that a
+//! program can be run somewhere other than a terminal, or the same way
+//! every time.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where a program's output goes, where its input comes from, and what time
+/// it thinks it is.
+pub trait Host {
+    /// Writes text the program printed.
+    fn write_stdout(&self, text: &str);
+
+    /// Writes diagnostics, like warnings about the program.
+    fn write_stderr(&self, text: &str);
+
+    /// Whether text written with [`Host::write_stderr`] is shown somewhere
+    /// that understands color codes.
+    fn stderr_color(&self) -> bool {
+        false
+    }
+
+    /// The next line of input without its line ending, or `None` at the end.
+    fn read_line(&self) -> Option<String>;
+
+    /// Seconds since the Unix epoch, or `None` if there's no clock.
+    fn now(&self) -> Option<f64>;
+
+    /// Where `random` and `randomInt` start from.
+    fn random_seed(&self) -> u64;
+
+    /// Whether `import` can read modules from the file system.
+    fn imports(&self) -> bool {
+        true
+    }
+}
+
+/// The process's own streams and the system clock.
+#[derive(Default)]
+pub struct OsHost;
+
+impl Host for OsHost {
+    fn write_stdout(&self, text: &str) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(text.as_bytes());
+        // Text without a newline, like a prompt, should still show.
+        let _ = stdout.flush();
+    }
+
+    fn write_stderr(&self, text: &str) {
+        let _ = io::stderr().write_all(text.as_bytes());
+    }
+
+    fn stderr_color(&self) -> bool {
+        io::stderr().is_terminal()
+    }
+
+    fn read_line(&self) -> Option<String> {
+        read_line(&mut io::stdin().lock())
+    }
+
+    fn now(&self) -> Option<f64> {
+        // Asking for the time panics on wasm32-unknown-unknown, which has no
+        // clock to ask.
+        if cfg!(target_arch = "wasm32") {
+            return None;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Some(now.as_secs_f64())
+    }
+
+    /// The time, so each run is different.
+    fn random_seed(&self) -> u64 {
+        if cfg!(target_arch = "wasm32") {
+            return 0;
+        }
+
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64)
+    }
+}
+
+/// The process's host, but with what the program prints going to `output`
+/// and what it reads coming from `input`, for the streams that are given.
+pub struct StreamHost<'a> {
+    output: Option<RefCell<Box<dyn Write + 'a>>>,
+    input: Option<RefCell<Box<dyn BufRead + 'a>>>,
+}
+
+impl<'a> StreamHost<'a> {
+    pub fn new(output: Option<Box<dyn Write + 'a>>, input: Option<Box<dyn BufRead + 'a>>) -> Self {
+        StreamHost {
+            output: output.map(RefCell::new),
+            input: input.map(RefCell::new),
+        }
+    }
+}
+
+impl Host for StreamHost<'_> {
+    fn write_stdout(&self, text: &str) {
+        match &self.output {
+            Some(output) => {
+                let mut output = output.borrow_mut();
+                let _ = output.write_all(text.as_bytes());
+                let _ = output.flush();
+            }
+            None => OsHost.write_stdout(text),
+        }
+    }
+
+    fn write_stderr(&self, text: &str) {
+        OsHost.write_stderr(text);
+    }
+
+    fn stderr_color(&self) -> bool {
+        OsHost.stderr_color()
+    }
+
+    fn read_line(&self) -> Option<String> {
+        match &self.input {
+            Some(input) => read_line(&mut *input.borrow_mut()),
+            None => OsHost.read_line(),
+        }
+    }
+
+    fn now(&self) -> Option<f64> {
+        OsHost.now()
+    }
+
+    fn random_seed(&self) -> u64 {
+        OsHost.random_seed()
+    }
+}
+
+/// The next line of `input` without its line ending, or `None` at the end.
+fn read_line(input: &mut dyn BufRead) -> Option<String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            let len = line.trim_end_matches(&['\n', '\r'][..]).len();
+            line.truncate(len);
+            Some(line)
+        }
+    }
+}
+
+/// A host that runs a program the same way every time: its input is given
+/// up front, what it writes is kept to be looked at, and the clock and
+/// random seed only change when told to.
+#[derive(Default)]
+pub struct CaptureHost {
+    input: RefCell<VecDeque<String>>,
+    stdout: RefCell<String>,
+    stderr: RefCell<String>,
+    time: Cell<f64>,
+    seed: Cell<u64>,
+    no_imports: Cell<bool>,
+}
+
+impl CaptureHost {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds the lines of `input` to what the program reads.
+    pub fn push_input(&self, input: &str) {
+        self.input
+            .borrow_mut()
+            .extend(input.lines().map(str::to_string));
+    }
+
+    pub fn set_time(&self, seconds: f64) {
+        self.time.set(seconds);
+    }
+
+    /// Sets the seed for interpreters created with this host from now on.
+    pub fn set_seed(&self, seed: u64) {
+        self.seed.set(seed);
+    }
+
+    /// Makes `import` fail without looking at the file system.
+    pub fn deny_imports(&self) {
+        self.no_imports.set(true);
+    }
+
+    /// Everything printed since the last call.
+    pub fn take_stdout(&self) -> String {
+        self.stdout.take()
+    }
+
+    /// Every diagnostic written since the last call.
+    pub fn take_stderr(&self) -> String {
+        self.stderr.take()
+    }
+}
+
+impl Host for CaptureHost {
+    fn write_stdout(&self, text: &str) {
+        self.stdout.borrow_mut().push_str(text);
+    }
+
+    fn write_stderr(&self, text: &str) {
+        self.stderr.borrow_mut().push_str(text);
+    }
+
+    fn read_line(&self) -> Option<String> {
+        self.input.borrow_mut().pop_front()
+    }
+
+    fn now(&self) -> Option<f64> {
+        Some(self.time.get())
+    }
+
+    fn random_seed(&self) -> u64 {
+        self.seed.get()
+    }
+
+    fn imports(&self) -> bool {
+        !self.no_imports.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::Lox;
+
+    #[test]
+    fn test_capture_host() {
+        let run = |source| {
+            let host = Rc::new(CaptureHost::new());
+            host.push_input("Ada\nGrace\n");
+            host.set_time(1.5);
+            host.set_seed(7);
+            let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+            lox.run(source).unwrap();
+            (host.take_stdout(), host.take_stderr())
+        };
+
+        let (stdout, stderr) = run(
+            "print readLine(); write(readLine()); print readLine(); print clock();\n\
+             { var unused = 1; }",
+        );
+        assert_eq!(stdout, "Ada\nGracenil\n1.5\n");
+        assert!(stderr.contains("local variable 'unused' is never used."));
+
+        // The same seed gives the same numbers.
+        let random = "print random(); print randomInt(1, 100);";
+        assert_eq!(run(random), run(random));
+
+        let host = Rc::new(CaptureHost::new());
+        host.deny_imports();
+        let lox = Lox::with_interpreter(Interpreter::with_host(host));
+        let error = lox.run("import \"Cargo.toml\";").err().unwrap();
+        assert!(error.to_string().contains("imports are turned off"));
+    }
+}
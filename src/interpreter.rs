@@ -1,110 +1,1886 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{env, fs};
 
-use crate::ast::{Expression, Visitor};
-use crate::scanner::TokenType;
+use thiserror::Error;
+
+use crate::ast::{
+    Ast, ExprId, Expression, FunctionDeclaration, Slot, Span, Stmt, StmtVisitor, Visitor,
+};
+use crate::callable::{
+    self, LoxCallable, LoxFunction, NativeFailure, NativeFunction, NativeResult,
+};
+use crate::class::{LoxClass, LoxInstance};
+use crate::diagnostic::{self, codes, Diagnostic, Format, Severity};
+use crate::environment::Environment;
+use crate::gc::Heap;
+use crate::host::{Host, OsHost, StreamHost};
+use crate::map::{LoxMap, MapKey};
+use crate::module::LoxModule;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::random::Rng;
+use crate::resolver::{Resolver, Warning};
+use crate::scanner::{Scanner, Token, TokenType};
+use crate::strings;
+use crate::symbol::Symbol;
 
 #[derive(Clone, Debug)]
-pub enum Types {
+pub enum Types<'a> {
     Number(f64),
-    ReturnString(String),
+    /// Behind one more pointer than `Rc<str>` would be, so that this is
+    /// a thin pointer and every value fits in 16 bytes.
+    ReturnString(Rc<String>),
     Boolean(bool),
     Nil,
+    Function(Rc<LoxFunction<'a>>),
+    NativeFunction(Rc<NativeFunction<'a>>),
+    Class(Rc<LoxClass<'a>>),
+    Instance(Rc<LoxInstance<'a>>),
+    List(Rc<RefCell<Vec<Types<'a>>>>),
+    /// A fixed group of values, like several returned at once.
+    Tuple(Rc<Vec<Types<'a>>>),
+    Map(Rc<RefCell<LoxMap<'a>>>),
+    Module(Rc<LoxModule<'a>>),
+}
+
+impl Types<'_> {
+    /// What kind of value this is, as `type(value)` tells Lox code.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Types::Number(_) => "number",
+            Types::ReturnString(_) => "string",
+            Types::Boolean(_) => "boolean",
+            Types::Nil => "nil",
+            Types::Function(_) | Types::NativeFunction(_) => "function",
+            Types::Class(_) => "class",
+            Types::Instance(_) => "instance",
+            Types::List(_) => "list",
+            Types::Tuple(_) => "tuple",
+            Types::Map(_) => "map",
+            Types::Module(_) => "module",
+        }
+    }
+}
+
+// Conversions from Rust values, for native functions to build their results.
+
+impl From<f64> for Types<'_> {
+    fn from(n: f64) -> Self {
+        Types::Number(n)
+    }
+}
+
+impl From<bool> for Types<'_> {
+    fn from(b: bool) -> Self {
+        Types::Boolean(b)
+    }
+}
+
+impl From<String> for Types<'_> {
+    fn from(s: String) -> Self {
+        Types::ReturnString(s.into())
+    }
+}
+
+impl From<&str> for Types<'_> {
+    fn from(s: &str) -> Self {
+        Types::ReturnString(Rc::new(s.to_string()))
+    }
+}
+
+impl From<()> for Types<'_> {
+    fn from(_: ()) -> Self {
+        Types::Nil
+    }
+}
+
+/// A number as Lox prints it, which is how jlox does: whole numbers have no
+/// decimal point, and the special values are `NaN`, `Infinity` and
+/// `-Infinity`. Negative zero keeps its sign.
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else {
+        n.to_string()
+    }
 }
 
-impl Display for Types {
+impl Display for Types<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
-            Self::Number(n) => write!(f, "{}", n),
+            Self::Number(n) => write!(f, "{}", format_number(*n)),
             Self::Boolean(b) => write!(f, "{}", b),
             Self::Nil => write!(f, "nil"),
             Self::ReturnString(s) => write!(f, "{}", s),
+            Self::Function(function) => write!(f, "{:?}", function),
+            Self::NativeFunction(function) => write!(f, "{:?}", function),
+            Self::Class(class) => write!(f, "{:?}", class),
+            Self::Instance(instance) => write!(f, "{:?}", instance),
+            Self::Module(module) => write!(f, "{:?}", module),
+            Self::List(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Self::Tuple(elements) => {
+                write!(f, "(")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                // `(1,)`, so a tuple of one doesn't look like a grouping.
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Self::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
 
-pub struct Interpreter;
+/// An error raised while running a program, pointing at the token whose
+/// evaluation failed.
+#[derive(Error, Debug)]
+pub struct RuntimeError {
+    pub code: &'static str,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    /// Byte offsets of the source the error is about: the token that caused
+    /// it, or the whole expression for errors about operands and calls.
+    pub start: usize,
+    pub end: usize,
+    /// Whether this came from a `throw` statement, in which case the thrown
+    /// value is waiting in `Interpreter::thrown`.
+    pub thrown: bool,
+    /// The Lox calls in progress when the error was raised, innermost first.
+    /// Only filled in for stack overflows.
+    pub trace: Vec<String>,
+    /// The exit code the program asked for by calling `exit`, which unwinds
+    /// like an error that `try` can't catch.
+    pub exit: Option<i32>,
+}
+
+impl RuntimeError {
+    pub fn new(token: &Token, code: &'static str, message: &str) -> Self {
+        RuntimeError {
+            code,
+            message: message.to_string(),
+            line: token.line,
+            column: token.column,
+            start: token.start,
+            end: token.end,
+            thrown: false,
+            trace: vec![],
+            exit: None,
+        }
+    }
+
+    /// Widens the source this error covers to all of `span`, such as the
+    /// whole expression whose operands were wrong. The line and column it's
+    /// reported at stay those of the token that caused it.
+    pub fn spanning(mut self, span: Span) -> Self {
+        self.start = span.start;
+        self.end = span.end;
+        self
+    }
+
+    /// Stops the program with exit code `code`, from the call ending at
+    /// `paren`.
+    pub fn exit(paren: &Token, code: i32) -> Self {
+        let mut error =
+            RuntimeError::new(paren, codes::EXIT, &format!("Exited with code {}.", code));
+        error.exit = Some(code);
+        error
+    }
 
-impl Interpreter {
-    pub fn interpret(&self, e: &Expression) -> anyhow::Result<()> {
-        let t = self.visit_expression(e)?;
-        println!("{}", t);
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            code: self.code,
+            severity: Severity::Error,
+            message: self.message.clone(),
+            line: self.line,
+            column: self.column,
+            start: self.start,
+            end: self.end,
+            notes: self.trace.clone(),
+            input: None,
+        }
+    }
+}
 
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}\n[line {}, column {}]",
+            self.message, self.line, self.column
+        )?;
+        for line in &self.trace {
+            write!(f, "\n{}", line)?;
+        }
         Ok(())
     }
 }
 
-impl Visitor for Interpreter {
-    type E = anyhow::Result<Types>;
-    fn visit_expression(&self, e: &Expression) -> Self::E {
-        match e {
-            &Expression::Literal { ref token } => match token.token_type {
-                TokenType::Number { number } => Ok(Types::Number(number)),
-                TokenType::StringLiteral { literal } => {
-                    Ok(Types::ReturnString(literal.to_string()))
+pub type RuntimeResult<T> = Result<T, RuntimeError>;
+
+/// How a statement finished, so that `return` can unwind out of nested
+/// blocks and loops up to the enclosing call, and `break` and `continue` out
+/// of blocks up to the enclosing loop.
+pub enum Flow<'a> {
+    Next,
+    Break,
+    Continue,
+    Return(Types<'a>),
+    /// A `return` of a call to a Lox function, which the function being
+    /// returned from makes in place of its own body so that tail recursion
+    /// runs in constant stack.
+    TailCall {
+        function: Rc<LoxFunction<'a>>,
+        paren: Token<'a>,
+        arguments: Vec<Types<'a>>,
+    },
+}
+
+/// How deeply Lox calls can nest before raising "Stack overflow." rather
+/// than exhausting the Rust stack. A thread running at this depth needs
+/// around 8 MB of stack in a release build, and several times that in a
+/// debug one.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// How many frames of a stack trace are shown before the rest are elided.
+const TRACE_FRAMES: usize = 10;
+
+/// A call to a Lox function that hasn't returned yet.
+struct Frame<'a> {
+    /// The function's name, or `None` if it's anonymous.
+    function: Option<&'a str>,
+    /// The line the call was made from.
+    line: usize,
+}
+
+/// Something told about a program as it runs, like a debugger or tracer.
+pub trait ExecutionHook<'a> {
+    /// Called just before a statement runs, with the token it starts at as
+    /// given by [`Ast::statement_token`]. The token's lexeme points into the
+    /// source the statement came from, which tells a module's code apart
+    /// from the main program's.
+    fn before_statement(&self, _interpreter: &Interpreter<'a>, _token: &Token<'a>) {}
+
+    /// Called when a function, Lox or native, starts running, once its call
+    /// has been counted in [`Interpreter::call_depth`].
+    fn on_call(&self, _interpreter: &Interpreter<'a>, _name: &str, _arguments: &[Types<'a>]) {}
+
+    /// Called when the function from the matching `on_call` finishes, with
+    /// the value it returned. The value is `None` if it didn't return one of
+    /// its own, because it failed or handed over to a tail call, which is
+    /// reported as a call of its own at the same depth.
+    fn on_return(&self, _interpreter: &Interpreter<'a>, _name: &str, _value: Option<&Types<'a>>) {}
+}
+
+pub struct Interpreter<'a> {
+    globals: Rc<RefCell<Environment<'a>>>,
+    environment: RefCell<Rc<RefCell<Environment<'a>>>>,
+    /// The arena holding the expressions of the code being run.
+    ast: RefCell<Rc<Ast<'a>>>,
+    /// The value of the exception currently unwinding, if it came from a
+    /// `throw`. Runtime errors can't hold Lox values themselves, since they
+    /// have to outlive the source they're reported against.
+    thrown: RefCell<Option<Types<'a>>>,
+    /// The top-level scope of every module imported so far, keyed by
+    /// canonical path, so each file only runs once.
+    modules: RefCell<HashMap<PathBuf, Rc<RefCell<Environment<'a>>>>>,
+    /// The files currently running, innermost last. Imports are relative to
+    /// the last one, and importing any of them again is a cycle.
+    loading: RefCell<Vec<PathBuf>>,
+    /// Directories searched, in order, for imports given as a bare name.
+    search_path: RefCell<Vec<PathBuf>>,
+    frames: RefCell<Vec<Frame<'a>>>,
+    max_call_depth: Cell<usize>,
+    /// Whether code is run through the optimizer before it's run.
+    optimize: Cell<bool>,
+    /// How warnings found while loading code are printed.
+    diagnostic_format: Cell<Format>,
+    /// Where output, input, the time and random numbers come from.
+    host: Rc<dyn Host + 'a>,
+    hook: RefCell<Option<Rc<dyn ExecutionHook<'a> + 'a>>>,
+    /// Whether a native function is running. Natives can't call back into
+    /// Lox, so there's at most one, and it doesn't need a frame.
+    in_native: Cell<bool>,
+    /// Where `random` and `randomInt` get their numbers from.
+    rng: Rc<Rng>,
+    /// Whether variables the resolver knows nothing about are looked up
+    /// through the enclosing scopes, rather than only among the globals.
+    dynamic_scope: Cell<bool>,
+}
+
+impl Default for Interpreter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new() -> Self {
+        Self::with_host(Rc::new(OsHost))
+    }
+
+    /// An interpreter that does all its input and output through `host`,
+    /// and takes the time and its random seed from it.
+    ///
+    /// ```
+    /// use std::rc::Rc;
+    /// use rlox_treewalk::host::CaptureHost;
+    /// use rlox_treewalk::interpreter::Interpreter;
+    /// use rlox_treewalk::Lox;
+    ///
+    /// let host = Rc::new(CaptureHost::new());
+    /// host.set_time(60.0);
+    /// let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+    /// lox.run("print clock() / 60;").unwrap();
+    /// assert_eq!(host.take_stdout(), "1\n");
+    /// ```
+    pub fn with_host(host: Rc<dyn Host + 'a>) -> Self {
+        let globals = Environment::new().into_shared();
+        let interpreter = Interpreter {
+            environment: RefCell::new(Rc::clone(&globals)),
+            globals,
+            ast: RefCell::new(Rc::new(Ast::new())),
+            thrown: RefCell::new(None),
+            modules: RefCell::new(HashMap::new()),
+            loading: RefCell::new(Vec::new()),
+            search_path: RefCell::new(Vec::new()),
+            frames: RefCell::new(Vec::new()),
+            max_call_depth: Cell::new(DEFAULT_MAX_CALL_DEPTH),
+            optimize: Cell::new(false),
+            diagnostic_format: Cell::new(Format::Human),
+            rng: Rc::new(Rng::new(host.random_seed())),
+            host,
+            hook: RefCell::new(None),
+            in_native: Cell::new(false),
+            dynamic_scope: Cell::new(false),
+        };
+
+        let host = Rc::clone(&interpreter.host);
+        interpreter.define_native("clock", 0, move |_| {
+            host.now()
+                .map(Types::Number)
+                .ok_or_else(|| "There's no clock on this platform.".to_string())
+        });
+        interpreter.define_builtin("exit", 1, callable::exit);
+        interpreter.define_builtin("assert", 2, callable::assert);
+        let write = NativeFunction::with_interpreter("write", 1, |interpreter, arguments| {
+            interpreter.write(&arguments[0].to_string());
+            Ok(Types::Nil)
+        });
+        interpreter.globals.borrow_mut().define(
+            Symbol::intern("write"),
+            Types::NativeFunction(Rc::new(write)),
+        );
+        let read_line = NativeFunction::with_interpreter("readLine", 0, |interpreter, _| {
+            Ok(interpreter.read_line().map_or(Types::Nil, Types::from))
+        });
+        interpreter.globals.borrow_mut().define(
+            Symbol::intern("readLine"),
+            Types::NativeFunction(Rc::new(read_line)),
+        );
+        let gc = NativeFunction::with_interpreter("gc", 0, |interpreter, _| {
+            Ok(Types::Number(interpreter.collect_garbage() as f64))
+        });
+        interpreter
+            .globals
+            .borrow_mut()
+            .define(Symbol::intern("gc"), Types::NativeFunction(Rc::new(gc)));
+        interpreter.define_native("type", 1, |arguments| Ok(arguments[0].type_name().into()));
+        strings::define(&interpreter);
+        interpreter.set_args(&[]);
+
+        let rng = Rc::clone(&interpreter.rng);
+        interpreter.define_native("random", 0, move |_| Ok(Types::Number(rng.next_f64())));
+        let rng = Rc::clone(&interpreter.rng);
+        interpreter.define_native("randomInt", 2, move |arguments| {
+            match (integer(&arguments[0]), integer(&arguments[1])) {
+                (Some(low), Some(high)) if low <= high => {
+                    Ok(Types::Number(rng.between(low, high) as f64))
                 }
-                TokenType::True => Ok(Types::Boolean(true)),
-                TokenType::False => Ok(Types::Boolean(false)),
-                TokenType::Nil => Ok(Types::Nil),
-                _ => Err(anyhow::anyhow!("Unrecognized literal")),
-            },
-            &Expression::Grouping { ref expr } => self.visit_expression(expr),
-            &Expression::Unary {
-                ref operator,
-                ref r_expr,
+                (Some(_), Some(_)) => Err("Lower bound is above the upper bound.".to_string()),
+                _ => Err("Bounds must be integers.".to_string()),
+            }
+        });
+        interpreter
+    }
+
+    /// Frees the values kept alive only by referring to each other, such as
+    /// a function declared inside another that calls itself, and returns how
+    /// many scopes, functions and other objects were freed. This also happens
+    /// on its own as the program allocates, and Lox code can call it as
+    /// `gc()`.
+    pub fn collect_garbage(&self) -> usize {
+        self.heap().collect()
+    }
+
+    pub(crate) fn heap(&self) -> Rc<Heap<'a>> {
+        Rc::clone(self.globals.borrow().heap())
+    }
+
+    /// Restarts the numbers from `random` and `randomInt` from `seed`, so a
+    /// run can be repeated exactly.
+    pub fn set_seed(&self, seed: u64) {
+        self.rng.seed(seed);
+    }
+
+    /// Sets the global list `ARGS`, which holds the arguments given to the
+    /// script on the command line. It's empty unless this is called.
+    pub fn set_args(&self, args: &[String]) {
+        let args = args.iter().map(|arg| Types::from(arg.as_str())).collect();
+        self.globals.borrow_mut().define(
+            Symbol::intern("ARGS"),
+            Types::List(Rc::new(RefCell::new(args))),
+        );
+    }
+
+    /// Makes a Rust function callable from Lox as a global named `name`. It
+    /// is called with exactly `arity` arguments; returning `Err` raises a
+    /// runtime error with that message at the call.
+    ///
+    /// ```
+    /// use rlox_treewalk::{Lox, Value};
+    ///
+    /// let lox = Lox::new();
+    /// lox.interpreter().define_native("double", 1, |args| match args[0] {
+    ///     Value::Number(n) => Ok(Value::from(n * 2.0)),
+    ///     _ => Err("Expected a number.".to_string()),
+    /// });
+    ///
+    /// assert!(matches!(lox.evaluate("double(21)").unwrap(), Value::Number(n) if n == 42.0));
+    /// assert!(lox.evaluate("double(nil)").is_err());
+    /// ```
+    pub fn define_native<F>(&self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Types<'a>]) -> NativeResult<'a> + 'a,
+    {
+        let native = NativeFunction::new(name, arity, function);
+        self.globals
+            .borrow_mut()
+            .define(Symbol::intern(name), Types::NativeFunction(Rc::new(native)));
+    }
+
+    /// Like [`Interpreter::define_native`], for the natives that come with
+    /// the interpreter.
+    pub(crate) fn define_builtin<F>(&self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Types<'a>]) -> Result<Types<'a>, NativeFailure> + 'a,
+    {
+        let native = NativeFunction::builtin(name, arity, function);
+        self.globals
+            .borrow_mut()
+            .define(Symbol::intern(name), Types::NativeFunction(Rc::new(native)));
+    }
+
+    /// Records that the program being run was read from `path`, so that its
+    /// imports are looked up next to it rather than in the working
+    /// directory.
+    pub fn set_script_path(&self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.loading.replace(vec![path]);
+    }
+
+    /// Adds a directory to search for modules imported by a bare name like
+    /// `"util.lox"`, after the importing file's own directory.
+    pub fn add_search_path(&self, directory: &Path) {
+        self.search_path.borrow_mut().push(directory.to_path_buf());
+    }
+
+    /// Sets how deeply Lox calls can nest, which defaults to
+    /// [`DEFAULT_MAX_CALL_DEPTH`]. The thread running the interpreter needs
+    /// enough stack for the limit to be reached.
+    pub fn set_max_call_depth(&self, depth: usize) {
+        self.max_call_depth.set(depth);
+    }
+
+    /// Sets whether code is optimized before it's run, which is off by
+    /// default.
+    pub fn set_optimize(&self, optimize: bool) {
+        self.optimize.set(optimize);
+    }
+
+    /// Sets how warnings are printed, which is [`Format::Human`] by default.
+    pub fn set_diagnostic_format(&self, format: Format) {
+        self.diagnostic_format.set(format);
+    }
+
+    /// Calls `hook` before running each statement from now on.
+    pub fn set_hook(&self, hook: Rc<dyn ExecutionHook<'a> + 'a>) {
+        self.hook.replace(Some(hook));
+    }
+
+    /// How many calls are in progress.
+    pub fn call_depth(&self) -> usize {
+        self.frames.borrow().len() + self.in_native.get() as usize
+    }
+
+    /// Evaluates an expression that hasn't been resolved in the innermost
+    /// scope of the code being run, so it can refer to the locals there.
+    /// This is how a debugger inspects a paused program.
+    pub fn evaluate_in_scope(&self, ast: &Rc<Ast<'a>>, expr: ExprId) -> RuntimeResult<Types<'a>> {
+        let dynamic_scope = self.dynamic_scope.replace(true);
+        let result = self.evaluate(ast, expr);
+        self.dynamic_scope.set(dynamic_scope);
+        result
+    }
+
+    /// An interpreter whose programs print to `output` rather than stdout.
+    ///
+    /// ```
+    /// use rlox_treewalk::interpreter::Interpreter;
+    /// use rlox_treewalk::Lox;
+    ///
+    /// let mut printed = vec![];
+    /// let lox = Lox::with_interpreter(Interpreter::with_output(&mut printed));
+    /// lox.run("print 1 + 2;").unwrap();
+    /// drop(lox);
+    /// assert_eq!(printed, b"3\n");
+    /// ```
+    pub fn with_output(output: impl Write + 'a) -> Self {
+        Self::with_host(Rc::new(StreamHost::new(Some(Box::new(output)), None)))
+    }
+
+    /// An interpreter whose programs read lines from `input` rather than
+    /// stdin.
+    pub fn with_input(input: impl BufRead + 'a) -> Self {
+        Self::with_host(Rc::new(StreamHost::new(None, Some(Box::new(input)))))
+    }
+
+    /// Writes out what the program printed.
+    pub(crate) fn write(&self, text: &str) {
+        self.host.write_stdout(text);
+    }
+
+    /// The next line of input without its line ending, or `None` at the end.
+    pub(crate) fn read_line(&self) -> Option<String> {
+        self.host.read_line()
+    }
+
+    /// Reports warnings found in `source` before running it. They never stop
+    /// it from running or change its result.
+    pub(crate) fn warn(&self, source: &str, warnings: impl IntoIterator<Item = Diagnostic>) {
+        for warning in warnings {
+            self.emit(&warning, source, self.diagnostic_format.get());
+        }
+    }
+
+    /// Prints a diagnostic about `source` to the host's stderr.
+    pub(crate) fn emit(&self, diagnostic: &Diagnostic, source: &str, format: Format) {
+        self.host.write_stderr(&diagnostic::display(
+            diagnostic,
+            source,
+            format,
+            self.host.stderr_color(),
+        ));
+    }
+
+    /// Prints an error that isn't about any source to the host's stderr.
+    pub(crate) fn emit_error(&self, error: &dyn Display) {
+        self.host.write_stderr(&format!("{}\n", error));
+    }
+
+    /// Optimizes newly parsed code, if optimization is on.
+    pub(crate) fn optimize(&self, ast: &mut Ast<'a>) {
+        if self.optimize.get() {
+            optimizer::fold_constants(ast);
+        }
+    }
+
+    /// Records that `function` has been called from `paren`, failing if
+    /// that's one call too many.
+    pub(crate) fn enter_call(
+        &self,
+        function: &LoxFunction<'a>,
+        paren: &Token,
+    ) -> RuntimeResult<()> {
+        if self.frames.borrow().len() >= self.max_call_depth.get() {
+            let mut error = RuntimeError::new(paren, codes::STACK_OVERFLOW, "Stack overflow.");
+            error.trace = self.stack_trace(paren.line);
+            return Err(error);
+        }
+
+        self.frames.borrow_mut().push(Frame {
+            function: function.trace_name(),
+            line: paren.line,
+        });
+        Ok(())
+    }
+
+    /// Replaces the innermost call with a tail call to `function`.
+    pub(crate) fn replace_call(&self, function: &LoxFunction<'a>) {
+        if let Some(frame) = self.frames.borrow_mut().last_mut() {
+            frame.function = function.trace_name();
+        }
+    }
+
+    pub(crate) fn exit_call(&self) {
+        self.frames.borrow_mut().pop();
+    }
+
+    /// Runs the native `function` named `name`, telling the hook about it.
+    pub(crate) fn call_native<T>(
+        &self,
+        name: &str,
+        arguments: &[Types<'a>],
+        function: impl FnOnce() -> Result<Types<'a>, T>,
+    ) -> Result<Types<'a>, T> {
+        let hook = match self.hook() {
+            Some(hook) => hook,
+            None => return function(),
+        };
+
+        self.in_native.set(true);
+        hook.on_call(self, name, arguments);
+        let result = function();
+        hook.on_return(self, name, result.as_ref().ok());
+        self.in_native.set(false);
+        result
+    }
+
+    /// Describes the calls in progress, innermost first, for a call that
+    /// failed on `line`.
+    fn stack_trace(&self, line: usize) -> Vec<String> {
+        let frames = self.frames.borrow();
+        // Each frame is running the line it made the next call from.
+        let lines = frames
+            .iter()
+            .skip(1)
+            .map(|frame| frame.line)
+            .chain(Some(line));
+        let mut trace = frames
+            .iter()
+            .zip(lines)
+            .map(|(frame, line)| match frame.function {
+                Some(name) => format!("[line {}] in {}()", line, name),
+                None => format!("[line {}] in anonymous function", line),
+            })
+            .collect::<Vec<_>>();
+        trace.reverse();
+
+        if trace.len() > TRACE_FRAMES {
+            let elided = trace.len() - TRACE_FRAMES;
+            trace.truncate(TRACE_FRAMES);
+            trace.push(format!("... {} more", elided));
+        }
+        let script_line = frames.first().map_or(line, |frame| frame.line);
+        trace.push(format!("[line {}] in script", script_line));
+        trace
+    }
+
+    /// Runs `statements`, whose expressions are in `ast`.
+    pub fn interpret(&self, ast: &Rc<Ast<'a>>, statements: &[Stmt<'a>]) -> RuntimeResult<()> {
+        self.with_ast(ast, || {
+            statements
+                .iter()
+                .try_for_each(|statement| self.execute(statement).map(|_| ()))
+        })
+    }
+
+    /// Runs `f` with `ast` as the arena that expressions are looked up in,
+    /// for running code parsed separately from the caller's, such as a
+    /// function's body or a module.
+    pub(crate) fn with_ast<T>(&self, ast: &Rc<Ast<'a>>, f: impl FnOnce() -> T) -> T {
+        if Rc::ptr_eq(&self.ast.borrow(), ast) {
+            return f();
+        }
+
+        let previous = self.ast.replace(Rc::clone(ast));
+        let result = f();
+        self.ast.replace(previous);
+        result
+    }
+
+    /// The arena of the code being run, which functions declared by it keep
+    /// hold of.
+    fn current_ast(&self) -> Rc<Ast<'a>> {
+        Rc::clone(&self.ast.borrow())
+    }
+
+    pub fn evaluate(&self, ast: &Rc<Ast<'a>>, expr: ExprId) -> RuntimeResult<Types<'a>> {
+        self.with_ast(ast, || self.visit_expression(expr))
+    }
+
+    /// The hook to tell about what's running, if there is one.
+    pub(crate) fn hook(&self) -> Option<Rc<dyn ExecutionHook<'a> + 'a>> {
+        self.hook.borrow().clone()
+    }
+
+    fn before_statement(&self, stmt: &Stmt<'a>) {
+        if let Some(hook) = self.hook() {
+            // The hook may run code of its own, which swaps the arena out.
+            let ast = self.current_ast();
+            if let Some(token) = ast.statement_token(stmt) {
+                hook.before_statement(self, token);
+            }
+        }
+    }
+
+    fn execute(&self, stmt: &Stmt<'a>) -> RuntimeResult<Flow<'a>> {
+        self.before_statement(stmt);
+
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                let environment = Environment::with_enclosing(self.environment.borrow().clone());
+                return self.execute_block(statements, environment.into_shared());
+            }
+            Stmt::Break { .. } => return Ok(Flow::Break),
+            Stmt::Continue { .. } => return Ok(Flow::Continue),
+            Stmt::Class {
+                name,
+                superclass,
+                mixins,
+                methods,
+                class_methods,
+                ..
             } => {
-                let right = self.visit_expression(r_expr)?;
-                match (right, &operator.token_type) {
-                    (Types::Number(n), TokenType::Minus) => Ok(Types::Number(-n)),
-                    (Types::Boolean(false) | Types::Nil, TokenType::Bang) => {
-                        Ok(Types::Boolean(true))
+                let superclass = match superclass {
+                    Some(expr) => match self.visit_expression(*expr)? {
+                        Types::Class(class) => Some(class),
+                        _ => {
+                            return Err(RuntimeError::new(
+                                name,
+                                codes::INVALID_SUPERCLASS,
+                                "Superclass must be a class.",
+                            ))
+                        }
+                    },
+                    None => None,
+                };
+
+                // Methods of a subclass close over an extra scope holding `super`.
+                let mut method_environment = Rc::clone(&self.environment.borrow());
+                if let Some(superclass) = &superclass {
+                    let mut environment = Environment::with_enclosing(method_environment);
+                    environment.define(Symbol::SUPER, Types::Class(Rc::clone(superclass)));
+                    method_environment = environment.into_shared();
+                }
+
+                let to_functions = |methods: &[Rc<FunctionDeclaration<'a>>], can_init: bool| {
+                    methods
+                        .iter()
+                        .map(|method| {
+                            let function = LoxFunction::new(
+                                Rc::clone(method),
+                                self.current_ast(),
+                                Rc::clone(&method_environment),
+                                can_init && method.name.lexeme == "init",
+                            );
+                            (method.name.lexeme.to_string(), Rc::new(function))
+                        })
+                        .collect()
+                };
+
+                // Mixins are flattened in order, so a later mixin's method wins
+                // over an earlier one's, and the class's own methods win over
+                // them all.
+                let mut all_methods = HashMap::new();
+                for mixin in mixins {
+                    match self.visit_expression(*mixin)? {
+                        Types::Class(class) => all_methods.extend(class.all_methods()),
+                        _ => {
+                            return Err(RuntimeError::new(
+                                name,
+                                codes::INVALID_MIXIN,
+                                "Mixin must be a class.",
+                            ))
+                        }
                     }
-                    (_, TokenType::Bang) => Ok(Types::Boolean(false)),
-                    _ => Err(anyhow::anyhow!("Unrecognized unary")),
                 }
+                all_methods.extend(to_functions(methods, true));
+
+                let class = LoxClass::new(
+                    name.lexeme,
+                    superclass,
+                    all_methods,
+                    to_functions(class_methods, false),
+                );
+                self.environment
+                    .borrow()
+                    .borrow_mut()
+                    .define(name.symbol, Types::Class(Rc::new(class)));
+            }
+            Stmt::Expression { expr, .. } => {
+                self.visit_expression(*expr)?;
             }
-            &Expression::Binary {
-                ref l_expr,
-                ref operator,
-                ref r_expr,
+            Stmt::Function { declaration, .. } => {
+                let function = LoxFunction::new(
+                    Rc::clone(declaration),
+                    self.current_ast(),
+                    Rc::clone(&self.environment.borrow()),
+                    false,
+                );
+                self.environment
+                    .borrow()
+                    .borrow_mut()
+                    .define(declaration.name.symbol, Types::Function(Rc::new(function)));
+            }
+            Stmt::ForIn {
+                name,
+                keyword,
+                iterable,
+                body,
+                ..
             } => {
-                let left = self.visit_expression(l_expr)?;
-                let right = self.visit_expression(r_expr)?;
-
-                match (left, right, &operator.token_type) {
-                    (Types::Number(n_first), Types::Number(n_second), t) => match t {
-                        &TokenType::Plus => Ok(Types::Number(n_first + n_second)),
-                        &TokenType::Minus => Ok(Types::Number(n_first - n_second)),
-                        &TokenType::Star => Ok(Types::Number(n_first * n_second)),
-                        &TokenType::Slash => Ok(Types::Number(n_first / n_second)),
-                        &TokenType::Greater => Ok(Types::Boolean(n_first > n_second)),
-                        &TokenType::GreaterEqual => Ok(Types::Boolean(n_first >= n_second)),
-                        &TokenType::Less => Ok(Types::Boolean(n_first < n_second)),
-                        &TokenType::LessEqual => Ok(Types::Boolean(n_first <= n_second)),
-                        &TokenType::EqualEqual => Ok(Types::Boolean(n_first == n_second)),
-                        &TokenType::BangEqual => Ok(Types::Boolean(n_first != n_second)),
-                        _ => Err(anyhow::anyhow!(
-                            "Unrecognized binary operation to two numbers"
-                        )),
-                    },
+                let items = match self.visit_expression(*iterable)? {
+                    Types::List(elements) => elements.borrow().clone(),
+                    Types::Map(map) => map.borrow().keys().iter().map(MapKey::to_value).collect(),
+                    Types::ReturnString(s) => {
+                        s.chars().map(|c| Types::from(c.to_string())).collect()
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(
+                            keyword,
+                            codes::NOT_ITERABLE,
+                            "Can only iterate over lists, maps and strings.",
+                        ))
+                    }
+                };
+
+                for item in items {
+                    let mut environment =
+                        Environment::with_enclosing(self.environment.borrow().clone());
+                    environment.define(name.symbol, item);
+                    let flow = self.execute_block(
+                        std::slice::from_ref(body.as_ref()),
+                        environment.into_shared(),
+                    )?;
+
+                    match flow {
+                        Flow::Next | Flow::Continue => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let condition = self.visit_expression(*condition)?;
+                if is_truthy(&condition) {
+                    return self.execute(then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.execute(else_branch);
+                }
+            }
+            Stmt::Import {
+                keyword,
+                path,
+                alias,
+                ..
+            } => {
+                let environment = self.import(keyword, path)?;
+                match alias {
+                    Some(alias) => {
+                        let module = LoxModule::new(alias.lexeme, environment);
+                        self.environment
+                            .borrow()
+                            .borrow_mut()
+                            .define(alias.symbol, Types::Module(Rc::new(module)));
+                    }
+                    None => {
+                        let mut globals = self.globals.borrow_mut();
+                        for (name, value) in environment.borrow().own_values() {
+                            globals.define(name, value);
+                        }
+                    }
+                }
+            }
+            Stmt::Print { expr, .. } => {
+                let value = self.visit_expression(*expr)?;
+                self.write(&format!("{}\n", value));
+            }
+            Stmt::Return { value, .. } => {
+                let ast = self.current_ast();
+                let span = value.map_or_else(Span::default, |value| ast.span(value));
+                if let Some(Expression::Call {
+                    callee,
+                    paren,
+                    arguments,
+                }) = value.map(|value| &ast[value])
+                {
+                    let (callee, arguments) =
+                        match self.evaluate_call(&ast, span, *callee, paren, arguments)? {
+                            Some(call) => call,
+                            None => return Ok(Flow::Return(Types::Nil)),
+                        };
+                    return match callee {
+                        Types::Function(function) => Ok(Flow::TailCall {
+                            function,
+                            paren: paren.clone(),
+                            arguments,
+                        }),
+                        callee => {
+                            let value =
+                                Self::callable(&callee, paren)?.call(self, paren, arguments)?;
+                            Ok(Flow::Return(value))
+                        }
+                    };
+                }
+
+                let value = match value {
+                    Some(expr) => self.evaluate_in(&ast, *expr)?,
+                    None => Types::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Throw { keyword, value, .. } => {
+                let value = self.visit_expression(*value)?;
+                let mut error = RuntimeError::new(
+                    keyword,
+                    codes::UNCAUGHT_EXCEPTION,
+                    &format!("Uncaught exception: {}", value),
+                );
+                error.thrown = true;
+                self.thrown.replace(Some(value));
+                return Err(error);
+            }
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                let environment = Environment::with_enclosing(self.environment.borrow().clone());
+                let result = match self.execute_block(body, environment.into_shared()) {
+                    // The call has to be made before leaving the try block, or
+                    // its errors would escape the handler.
+                    Ok(Flow::TailCall {
+                        function,
+                        paren,
+                        arguments,
+                    }) => function.call(self, &paren, arguments).map(Flow::Return),
+                    result => result,
+                };
+                let error = match result {
+                    Err(error) if error.exit.is_none() => error,
+                    flow => return flow,
+                };
 
-                    (
-                        Types::ReturnString(s_first),
-                        Types::ReturnString(s_second),
-                        TokenType::Plus,
-                    ) => Ok(Types::ReturnString(s_first + &s_second)),
+                // Errors raised by the interpreter itself are caught as their
+                // message.
+                let value = match self.thrown.take() {
+                    Some(value) if error.thrown => value,
+                    _ => Types::from(error.message),
+                };
 
-                    (Types::Nil, Types::Nil, TokenType::Equal) => Ok(Types::Boolean(true)),
-                    (Types::Nil, Types::Nil, TokenType::BangEqual) => Ok(Types::Boolean(false)),
+                let mut environment =
+                    Environment::with_enclosing(self.environment.borrow().clone());
+                environment.define(name.symbol, value);
+                return self.execute_block(handler, environment.into_shared());
+            }
+            Stmt::Destructure {
+                paren,
+                names,
+                initializer,
+                is_const,
+                ..
+            } => {
+                let values = match self.visit_expression(*initializer)? {
+                    Types::Tuple(values) if values.len() == names.len() => values,
+                    Types::Tuple(values) => {
+                        return Err(RuntimeError::new(
+                            paren,
+                            codes::CANNOT_DESTRUCTURE,
+                            &format!(
+                                "Expected a tuple of {} values but got {}.",
+                                names.len(),
+                                values.len()
+                            ),
+                        ))
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(
+                            paren,
+                            codes::CANNOT_DESTRUCTURE,
+                            "Can only destructure a tuple.",
+                        ))
+                    }
+                };
 
-                    (Types::Boolean(b_first), Types::Boolean(b_second), TokenType::EqualEqual) => {
-                        Ok(Types::Boolean(b_first == b_second))
+                let environment = self.environment.borrow();
+                let mut environment = environment.borrow_mut();
+                for (name, value) in names.iter().zip(values.iter()) {
+                    if *is_const {
+                        environment.define_const(name.symbol, value.clone());
+                    } else {
+                        environment.define(name.symbol, value.clone());
+                    }
+                }
+            }
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+                ..
+            } => {
+                let value = match initializer {
+                    Some(expr) => self.visit_expression(*expr)?,
+                    None => Types::Nil,
+                };
+                let environment = self.environment.borrow();
+                let mut environment = environment.borrow_mut();
+                if *is_const {
+                    environment.define_const(name.symbol, value);
+                } else {
+                    environment.define(name.symbol, value);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                while is_truthy(&self.visit_expression(*condition)?) {
+                    match self.execute(body)? {
+                        Flow::Next | Flow::Continue => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
                     }
-                    (Types::Boolean(b_first), Types::Boolean(b_second), TokenType::BangEqual) => {
-                        Ok(Types::Boolean(b_first != b_second))
+
+                    if let Some(increment) = increment {
+                        self.visit_expression(*increment)?;
                     }
-                    _ => Err(anyhow::anyhow!("Unrecognized binary")),
+                    // Each check of the condition counts as running the
+                    // loop's statement again.
+                    self.before_statement(stmt);
+                }
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    /// Loads the module named by the string literal `path`, running it first
+    /// if this is the first time it's been imported, and returns its
+    /// top-level scope.
+    fn import(&self, keyword: &Token, path: &Token) -> RuntimeResult<Rc<RefCell<Environment<'a>>>> {
+        let path = match &path.token_type {
+            TokenType::StringLiteral { literal } => literal.to_string(),
+            _ => {
+                return Err(RuntimeError::new(
+                    path,
+                    codes::INVALID_MODULE_PATH,
+                    "Module path must be a string.",
+                ))
+            }
+        };
+        if !self.host.imports() {
+            return Err(RuntimeError::new(
+                keyword,
+                codes::MODULE_NOT_FOUND,
+                &format!("Could not read module '{}': imports are turned off.", path),
+            ));
+        }
+
+        let base = match self.loading.borrow().last() {
+            Some(importer) => importer.parent().map(Path::to_path_buf),
+            None => env::current_dir().ok(),
+        };
+        let full_path = self
+            .find_module(base.unwrap_or_default(), &path)
+            .map_err(|e| {
+                RuntimeError::new(
+                    keyword,
+                    codes::MODULE_NOT_FOUND,
+                    &format!("Could not read module '{}': {}", path, e),
+                )
+            })?;
+
+        if let Some(environment) = self.modules.borrow().get(&full_path) {
+            return Ok(Rc::clone(environment));
+        }
+        if self.loading.borrow().contains(&full_path) {
+            return Err(RuntimeError::new(
+                keyword,
+                codes::IMPORT_CYCLE,
+                &format!("Import cycle through module '{}'.", path),
+            ));
+        }
+
+        let source = fs::read_to_string(&full_path).map_err(|e| {
+            RuntimeError::new(
+                keyword,
+                codes::MODULE_NOT_FOUND,
+                &format!("Could not read module '{}': {}", path, e),
+            )
+        })?;
+        // Like the main program, a module's functions and classes point back
+        // into its source, which therefore lives as long as the session.
+        let source: &'a str = Box::leak(source.into_boxed_str());
+
+        let load_error = |e: &dyn Display| {
+            RuntimeError::new(
+                keyword,
+                codes::MODULE_FAILED,
+                &format!("Could not load module '{}': {}", path, e),
+            )
+        };
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().map_err(|e| load_error(&e))?;
+        let parser = Parser::new(tokens);
+        let statements = parser.parse().map_err(|e| load_error(&e))?;
+        let mut ast = parser.into_ast();
+        self.optimize(&mut ast);
+        let ast = Rc::new(ast);
+        let mut resolver = Resolver::new(&ast);
+        resolver
+            .resolve_module(&statements)
+            .map_err(|e| load_error(&e))?;
+        self.warn(
+            source,
+            resolver.take_warnings().iter().map(Warning::diagnostic),
+        );
+
+        let environment = Environment::with_enclosing(Rc::clone(&self.globals)).into_shared();
+        self.loading.borrow_mut().push(full_path.clone());
+        let result = self.with_ast(&ast, || {
+            self.execute_block(&statements, Rc::clone(&environment))
+        });
+        self.loading.borrow_mut().pop();
+        result?;
+
+        self.modules
+            .borrow_mut()
+            .insert(full_path, Rc::clone(&environment));
+        Ok(environment)
+    }
+
+    /// Finds the file an import of `path` refers to. Paths starting with `.`
+    /// or `/` are only looked up from `base`; bare names are also looked up
+    /// in each directory of the search path.
+    fn find_module(&self, base: PathBuf, path: &str) -> std::io::Result<PathBuf> {
+        let found = base.join(path).canonicalize();
+        if found.is_ok() || path.starts_with('.') || Path::new(path).is_absolute() {
+            return found;
+        }
+
+        self.search_path
+            .borrow()
+            .iter()
+            .find_map(|directory| directory.join(path).canonicalize().ok())
+            .map_or(found, Ok)
+    }
+
+    fn look_up_variable(
+        &self,
+        name: &Token,
+        slot: &Cell<Option<Slot>>,
+    ) -> RuntimeResult<Types<'a>> {
+        match slot.get() {
+            Some(slot) => self.environment.borrow().borrow().get_at(slot, name),
+            None if self.dynamic_scope.get() => self.environment.borrow().borrow().get(name),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    /// Runs `statements` with `environment` as the innermost scope, restoring
+    /// the previous scope afterwards even if execution fails.
+    pub fn execute_block(
+        &self,
+        statements: &[Stmt<'a>],
+        environment: Rc<RefCell<Environment<'a>>>,
+    ) -> RuntimeResult<Flow<'a>> {
+        let previous = self.environment.replace(environment);
+
+        let mut result = Ok(Flow::Next);
+        for statement in statements {
+            result = self.execute(statement);
+            if !matches!(result, Ok(Flow::Next)) {
+                break;
+            }
+        }
+
+        self.environment.replace(previous);
+        result
+    }
+
+    /// The function called by a call expression and its arguments, checked
+    /// against its parameters, or `None` if it's a method call like
+    /// `object?.method()` on nil, which isn't made at all. Errors about the
+    /// call as a whole point at `span`.
+    fn evaluate_call(
+        &self,
+        ast: &Rc<Ast<'a>>,
+        span: Span,
+        callee: ExprId,
+        paren: &Token,
+        arguments: &[ExprId],
+    ) -> RuntimeResult<Option<(Types<'a>, Vec<Types<'a>>)>> {
+        let callee = match &ast[callee] {
+            Expression::Get {
+                object,
+                name,
+                optional: true,
+            } => match self.evaluate_in(ast, *object)? {
+                Types::Nil => return Ok(None),
+                object => self.get_property(object, name)?,
+            },
+            _ => self.evaluate_in(ast, callee)?,
+        };
+        let mut values = Vec::with_capacity(arguments.len());
+        let mut named = vec![];
+        for &argument in arguments {
+            match &ast[argument] {
+                Expression::Named { name, value } => {
+                    named.push((name, self.evaluate_in(ast, *value)?));
                 }
+                Expression::Spread { ellipsis, expr } => match self.evaluate_in(ast, *expr)? {
+                    Types::List(elements) => values.extend(elements.borrow().iter().cloned()),
+                    Types::Tuple(elements) => values.extend(elements.iter().cloned()),
+                    _ => {
+                        return Err(RuntimeError::new(
+                            ellipsis,
+                            codes::NOT_ITERABLE,
+                            "Can only spread lists and tuples.",
+                        ))
+                    }
+                },
+                _ => values.push(self.evaluate_in(ast, argument)?),
+            }
+        }
+        let callable = Self::callable(&callee, paren).map_err(|error| error.spanning(span))?;
+        let arguments = if named.is_empty() {
+            values
+        } else {
+            Self::match_named(callable, paren, values, named)?
+        };
+
+        let arity = callable.arity();
+        if callable.is_variadic() && arguments.len() < arity {
+            return Err(RuntimeError::new(
+                paren,
+                codes::WRONG_ARGUMENT_COUNT,
+                &format!(
+                    "Expected at least {} arguments but got {}.",
+                    arity,
+                    arguments.len()
+                ),
+            )
+            .spanning(span));
+        } else if !callable.is_variadic() && arguments.len() != arity {
+            return Err(RuntimeError::new(
+                paren,
+                codes::WRONG_ARGUMENT_COUNT,
+                &format!("Expected {} arguments but got {}.", arity, arguments.len()),
+            )
+            .spanning(span));
+        }
+
+        Ok(Some((callee, arguments)))
+    }
+
+    /// Puts each named argument in the place of the parameter it names,
+    /// after the `positional` arguments that came before them.
+    fn match_named(
+        callable: &dyn LoxCallable<'a>,
+        paren: &Token,
+        positional: Vec<Types<'a>>,
+        named: Vec<(&Token, Types<'a>)>,
+    ) -> RuntimeResult<Vec<Types<'a>>> {
+        let names = callable.parameter_names().ok_or_else(|| {
+            RuntimeError::new(
+                named[0].0,
+                codes::INVALID_NAMED_ARGUMENT,
+                "Only functions declared in Lox take named arguments.",
+            )
+        })?;
+        // A rest parameter can't be named.
+        let names = &names[..callable.arity()];
+
+        let mut slots: Vec<Option<Types<'a>>> = positional.into_iter().map(Some).collect();
+        if slots.len() < names.len() {
+            slots.resize(names.len(), None);
+        }
+        for (name, value) in named {
+            let i = names
+                .iter()
+                .position(|&param| param == name.lexeme)
+                .ok_or_else(|| {
+                    RuntimeError::new(
+                        name,
+                        codes::INVALID_NAMED_ARGUMENT,
+                        &format!("No parameter named '{}'.", name.lexeme),
+                    )
+                })?;
+            if slots[i].is_some() {
+                return Err(RuntimeError::new(
+                    name,
+                    codes::INVALID_NAMED_ARGUMENT,
+                    &format!("Argument '{}' was given more than once.", name.lexeme),
+                ));
             }
+            slots[i] = Some(value);
+        }
+
+        slots
+            .into_iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                slot.ok_or_else(|| {
+                    RuntimeError::new(
+                        paren,
+                        codes::WRONG_ARGUMENT_COUNT,
+                        &format!("Missing argument '{}'.", names[i]),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// The element of a list or the value in a map at `index`.
+    fn index(bracket: &Token, object: &Types<'a>, index: &Types<'a>) -> RuntimeResult<Types<'a>> {
+        match object {
+            Types::List(elements) => {
+                let elements = elements.borrow();
+                let i = Self::list_index(bracket, index, elements.len())?;
+                Ok(elements[i].clone())
+            }
+            Types::Map(map) => {
+                let key = Self::map_key(bracket, index)?;
+                map.borrow().get(&key).cloned().ok_or_else(|| {
+                    RuntimeError::new(
+                        bracket,
+                        codes::UNDEFINED_KEY,
+                        &format!("Undefined key '{}'.", key),
+                    )
+                })
+            }
+            _ => Err(RuntimeError::new(
+                bracket,
+                codes::NOT_INDEXABLE,
+                "Only lists and maps can be indexed.",
+            )),
+        }
+    }
+
+    /// Looks up the property `name` of `object`, running it if it's a
+    /// getter.
+    fn get_property(&self, object: Types<'a>, name: &Token<'a>) -> RuntimeResult<Types<'a>> {
+        let property = match object {
+            Types::Instance(instance) => LoxInstance::get(&instance, name)?,
+            Types::Class(class) => LoxClass::get(&class, name)?,
+            Types::Module(module) => module.get(name)?,
+            _ => {
+                return Err(RuntimeError::new(
+                    name,
+                    codes::NOT_AN_INSTANCE,
+                    "Only instances have properties.",
+                ))
+            }
+        };
+
+        match property {
+            Types::Function(getter) if getter.is_getter() => getter.call(self, name, vec![]),
+            property => Ok(property),
+        }
+    }
+
+    fn callable<'v>(
+        callee: &'v Types<'a>,
+        paren: &Token,
+    ) -> RuntimeResult<&'v dyn LoxCallable<'a>> {
+        match callee {
+            Types::Function(function) => Ok(function.as_ref()),
+            Types::NativeFunction(function) => Ok(function.as_ref()),
+            Types::Class(class) => Ok(class),
+            _ => Err(RuntimeError::new(
+                paren,
+                codes::NOT_CALLABLE,
+                "Can only call functions and classes.",
+            )),
         }
     }
+
+    /// Checks that `index` is a whole number that's in bounds for a list of
+    /// `len` elements.
+    fn list_index(bracket: &Token, index: &Types, len: usize) -> RuntimeResult<usize> {
+        let index = match index {
+            Types::Number(n) if n.fract() == 0.0 => *n,
+            _ => {
+                return Err(RuntimeError::new(
+                    bracket,
+                    codes::INVALID_INDEX,
+                    "List index must be a whole number.",
+                ))
+            }
+        };
+
+        if index < 0.0 || index >= len as f64 {
+            return Err(RuntimeError::new(
+                bracket,
+                codes::INDEX_OUT_OF_BOUNDS,
+                &format!(
+                    "List index {} is out of bounds for a list of length {}.",
+                    index, len
+                ),
+            ));
+        }
+
+        Ok(index as usize)
+    }
+
+    fn map_key(token: &Token, key: &Types) -> RuntimeResult<MapKey> {
+        MapKey::from_value(key).ok_or_else(|| {
+            RuntimeError::new(
+                token,
+                codes::INVALID_INDEX,
+                "Map keys must be strings or numbers.",
+            )
+        })
+    }
+
+    /// Evaluates a bitwise operator, which works on the operands as 64-bit
+    /// integers.
+    fn bitwise(operator: &Token, left: f64, right: f64) -> RuntimeResult<Types<'a>> {
+        let to_integer = |n: f64| {
+            if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+                Ok(n as i64)
+            } else {
+                Err(RuntimeError::new(
+                    operator,
+                    codes::OPERAND_TYPE,
+                    "Operands must be whole numbers.",
+                ))
+            }
+        };
+        let (left, right) = (to_integer(left)?, to_integer(right)?);
+
+        let shift_amount = || {
+            u32::try_from(right)
+                .ok()
+                .filter(|&amount| amount < 64)
+                .ok_or_else(|| {
+                    RuntimeError::new(
+                        operator,
+                        codes::SHIFT_OUT_OF_RANGE,
+                        "Shift amount must be between 0 and 63.",
+                    )
+                })
+        };
+
+        let result = match operator.token_type {
+            TokenType::Ampersand => left & right,
+            TokenType::Pipe => left | right,
+            TokenType::Caret => left ^ right,
+            TokenType::LessLess => left << shift_amount()?,
+            TokenType::GreaterGreater => left >> shift_amount()?,
+            _ => {
+                return Err(RuntimeError::new(
+                    operator,
+                    codes::INTERNAL,
+                    "Unknown binary operator.",
+                ))
+            }
+        };
+
+        Ok(Types::Number(result as f64))
+    }
+}
+
+impl<'a> Visitor<'a> for Interpreter<'a> {
+    type E = RuntimeResult<Types<'a>>;
+    fn visit_expression(&self, e: ExprId) -> Self::E {
+        self.evaluate_in(&self.current_ast(), e)
+    }
+}
+
+impl<'a> StmtVisitor<'a> for Interpreter<'a> {
+    type S = RuntimeResult<Flow<'a>>;
+    fn visit_statement(&self, stmt: &Stmt<'a>) -> Self::S {
+        self.execute(stmt)
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    /// Evaluates `e`, which is in `ast`. Subexpressions are evaluated here
+    /// rather than through `visit_expression` so that the arena is only
+    /// looked up once per expression tree.
+    fn evaluate_in(&self, ast: &Rc<Ast<'a>>, e: ExprId) -> RuntimeResult<Types<'a>> {
+        match &ast[e] {
+            Expression::Assign { name, value, slot } => {
+                let value = self.evaluate_in(ast, *value)?;
+                match slot.get() {
+                    Some(slot) => self.environment.borrow().borrow_mut().assign_at(
+                        slot,
+                        name,
+                        value.clone(),
+                    )?,
+                    None => self.globals.borrow_mut().assign(name, value.clone())?,
+                }
+                Ok(value)
+            }
+            Expression::Call {
+                callee,
+                paren,
+                arguments,
+            } => match self.evaluate_call(ast, ast.span(e), *callee, paren, arguments)? {
+                Some((callee, arguments)) => {
+                    Self::callable(&callee, paren)?.call(self, paren, arguments)
+                }
+                None => Ok(Types::Nil),
+            },
+            Expression::Get {
+                object,
+                name,
+                optional,
+            } => match self.evaluate_in(ast, *object)? {
+                Types::Nil if *optional => Ok(Types::Nil),
+                object => self.get_property(object, name),
+            },
+            Expression::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate_in(ast, *object)?;
+                let index = self.evaluate_in(ast, *index)?;
+                Self::index(bracket, &object, &index)
+            }
+            Expression::List { elements, .. } => {
+                let elements = elements
+                    .iter()
+                    .map(|e| self.evaluate_in(ast, *e))
+                    .collect::<RuntimeResult<Vec<_>>>()?;
+                Ok(Types::List(Rc::new(RefCell::new(elements))))
+            }
+            // Only allowed as arguments, which `evaluate_call` handles.
+            Expression::Named { name: token, .. }
+            | Expression::Spread {
+                ellipsis: token, ..
+            } => Err(RuntimeError::new(
+                token,
+                codes::INTERNAL,
+                "Unexpected argument outside a call.",
+            )),
+            Expression::Tuple { elements, .. } => {
+                let elements = elements
+                    .iter()
+                    .map(|e| self.evaluate_in(ast, *e))
+                    .collect::<RuntimeResult<Vec<_>>>()?;
+                Ok(Types::Tuple(Rc::new(elements)))
+            }
+            Expression::Map { brace, entries } => {
+                let mut map = LoxMap::new();
+                for (key, value) in entries {
+                    let key = Self::map_key(brace, &self.evaluate_in(ast, *key)?)?;
+                    map.insert(key, self.evaluate_in(ast, *value)?);
+                }
+                Ok(Types::Map(Rc::new(RefCell::new(map))))
+            }
+            Expression::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+                operator,
+            } => {
+                let object = self.evaluate_in(ast, *object)?;
+                let index = self.evaluate_in(ast, *index)?;
+                let value = match operator {
+                    Some(operator) => {
+                        let old = Self::index(bracket, &object, &index)?;
+                        let value = self.evaluate_in(ast, *value)?;
+                        Self::binary(operator, old, value)
+                            .map_err(|error| error.spanning(ast.span(e)))?
+                    }
+                    None => self.evaluate_in(ast, *value)?,
+                };
+                match object {
+                    Types::List(elements) => {
+                        let mut elements = elements.borrow_mut();
+                        let i = Self::list_index(bracket, &index, elements.len())?;
+                        elements[i] = value.clone();
+                        Ok(value)
+                    }
+                    Types::Map(map) => {
+                        let key = Self::map_key(bracket, &index)?;
+                        map.borrow_mut().insert(key, value.clone());
+                        Ok(value)
+                    }
+                    _ => Err(RuntimeError::new(
+                        bracket,
+                        codes::NOT_INDEXABLE,
+                        "Only lists and maps can be indexed.",
+                    )),
+                }
+            }
+            Expression::Set {
+                object,
+                name,
+                value,
+                operator,
+            } => match self.evaluate_in(ast, *object)? {
+                Types::Instance(instance) => {
+                    let value = match operator {
+                        Some(operator) => {
+                            let old =
+                                self.get_property(Types::Instance(Rc::clone(&instance)), name)?;
+                            let value = self.evaluate_in(ast, *value)?;
+                            Self::binary(operator, old, value)
+                                .map_err(|error| error.spanning(ast.span(e)))?
+                        }
+                        None => self.evaluate_in(ast, *value)?,
+                    };
+                    instance.set(name, value.clone());
+                    Ok(value)
+                }
+                _ => Err(RuntimeError::new(
+                    name,
+                    codes::NOT_AN_INSTANCE,
+                    "Only instances have fields.",
+                )),
+            },
+            Expression::Super {
+                keyword,
+                method,
+                slot,
+            } => {
+                let slot = slot.get().unwrap_or_default();
+                let environment = Rc::clone(&self.environment.borrow());
+                let superclass = match environment.borrow().get_at(slot, keyword)? {
+                    Types::Class(class) => class,
+                    _ => {
+                        return Err(RuntimeError::new(
+                            keyword,
+                            codes::INVALID_SUPERCLASS,
+                            "Superclass must be a class.",
+                        ))
+                    }
+                };
+
+                // `this` is always bound, alone, in the scope just inside the
+                // one holding `super`.
+                let this = Token {
+                    token_type: TokenType::This,
+                    lexeme: "this",
+                    ..keyword.clone()
+                };
+                let this_slot = Slot {
+                    depth: slot.depth.saturating_sub(1),
+                    index: 0,
+                };
+                let object = match environment.borrow().get_at(this_slot, &this)? {
+                    Types::Instance(instance) => instance,
+                    _ => {
+                        return Err(RuntimeError::new(
+                            keyword,
+                            codes::INTERNAL,
+                            "Can't use 'super' here.",
+                        ))
+                    }
+                };
+
+                match superclass.find_method(method.lexeme) {
+                    Some(found) if found.is_getter() => {
+                        found.bind(object).call(self, method, vec![])
+                    }
+                    Some(found) => Ok(Types::Function(Rc::new(found.bind(object)))),
+                    None => Err(RuntimeError::new(
+                        method,
+                        codes::UNDEFINED_PROPERTY,
+                        &format!("Undefined property '{}'.", method.lexeme),
+                    )),
+                }
+            }
+            Expression::This { keyword, slot } => self.look_up_variable(keyword, slot),
+            Expression::Variable { name, slot } => self.look_up_variable(name, slot),
+            Expression::Literal { token } => Self::literal(token),
+            Expression::Function { declaration } => {
+                let function = LoxFunction::new(
+                    Rc::clone(declaration),
+                    Rc::clone(ast),
+                    Rc::clone(&self.environment.borrow()),
+                    false,
+                );
+                Ok(Types::Function(Rc::new(function)))
+            }
+            Expression::Grouping { expr } => self.evaluate_in(ast, *expr),
+            Expression::Logical {
+                l_expr,
+                operator,
+                r_expr,
+            } => {
+                let left = self.evaluate_in(ast, *l_expr)?;
+                // Short-circuit, handing back the operand itself rather than a boolean.
+                match (&operator.token_type, is_truthy(&left)) {
+                    (TokenType::Or, true) | (TokenType::And, false) => Ok(left),
+                    (TokenType::QuestionQuestion, _) if !matches!(left, Types::Nil) => Ok(left),
+                    _ => self.evaluate_in(ast, *r_expr),
+                }
+            }
+            Expression::Unary { operator, r_expr } => {
+                Self::unary(operator, self.evaluate_in(ast, *r_expr)?)
+                    .map_err(|error| error.spanning(ast.span(e)))
+            }
+            Expression::Binary {
+                l_expr,
+                operator,
+                r_expr,
+            } => {
+                let left = self.evaluate_in(ast, *l_expr)?;
+                let right = self.evaluate_in(ast, *r_expr)?;
+                Self::binary(operator, left, right).map_err(|error| error.spanning(ast.span(e)))
+            }
+        }
+    }
+
+    /// The value of a literal token.
+    pub(crate) fn literal(token: &Token) -> RuntimeResult<Types<'a>> {
+        match &token.token_type {
+            TokenType::Number { number } => Ok(Types::Number(*number)),
+            TokenType::StringLiteral { literal } => Ok(Types::from(literal.as_ref())),
+            TokenType::True => Ok(Types::Boolean(true)),
+            TokenType::False => Ok(Types::Boolean(false)),
+            TokenType::Nil => Ok(Types::Nil),
+            _ => Err(RuntimeError::new(
+                token,
+                codes::INTERNAL,
+                "Unrecognized literal.",
+            )),
+        }
+    }
+
+    /// Applies a unary operator to an already evaluated operand.
+    pub(crate) fn unary(operator: &Token, right: Types<'a>) -> RuntimeResult<Types<'a>> {
+        match (right, &operator.token_type) {
+            (Types::Number(n), TokenType::Minus) => Ok(Types::Number(-n)),
+            (right, TokenType::Bang) => Ok(Types::Boolean(!is_truthy(&right))),
+            _ => Err(RuntimeError::new(
+                operator,
+                codes::OPERAND_TYPE,
+                "Operand must be a number.",
+            )),
+        }
+    }
+
+    /// Applies a binary operator to already evaluated operands.
+    pub(crate) fn binary(
+        operator: &Token,
+        left: Types<'a>,
+        right: Types<'a>,
+    ) -> RuntimeResult<Types<'a>> {
+        match operator.token_type {
+            TokenType::Comma => return Ok(right),
+            TokenType::EqualEqual => return Ok(Types::Boolean(is_equal(&left, &right))),
+            TokenType::BangEqual => return Ok(Types::Boolean(!is_equal(&left, &right))),
+            _ => {}
+        }
+
+        match (left, right, &operator.token_type) {
+            (Types::Number(n_first), Types::Number(n_second), t) => match *t {
+                TokenType::Plus => Ok(Types::Number(n_first + n_second)),
+                TokenType::Minus => Ok(Types::Number(n_first - n_second)),
+                TokenType::Star => Ok(Types::Number(n_first * n_second)),
+                TokenType::Slash if n_second == 0.0 => Err(RuntimeError::new(
+                    operator,
+                    codes::DIVISION_BY_ZERO,
+                    "Division by zero.",
+                )),
+                TokenType::Slash => Ok(Types::Number(n_first / n_second)),
+                TokenType::StarStar => Ok(Types::Number(n_first.powf(n_second))),
+                TokenType::Greater => Ok(Types::Boolean(n_first > n_second)),
+                TokenType::GreaterEqual => Ok(Types::Boolean(n_first >= n_second)),
+                TokenType::Less => Ok(Types::Boolean(n_first < n_second)),
+                TokenType::LessEqual => Ok(Types::Boolean(n_first <= n_second)),
+                TokenType::Ampersand
+                | TokenType::Pipe
+                | TokenType::Caret
+                | TokenType::LessLess
+                | TokenType::GreaterGreater => Self::bitwise(operator, n_first, n_second),
+                _ => Err(RuntimeError::new(
+                    operator,
+                    codes::INTERNAL,
+                    "Unknown binary operator.",
+                )),
+            },
+
+            (Types::ReturnString(s_first), Types::ReturnString(s_second), TokenType::Plus) => {
+                Ok(Types::from([s_first.as_str(), s_second.as_str()].concat()))
+            }
+
+            (_, _, TokenType::Plus) => Err(RuntimeError::new(
+                operator,
+                codes::OPERAND_TYPE,
+                "Operands must be two numbers or two strings.",
+            )),
+            _ => Err(RuntimeError::new(
+                operator,
+                codes::OPERAND_TYPE,
+                "Operands must be numbers.",
+            )),
+        }
+    }
+}
+
+/// Whether a value counts as true in a condition: everything does except
+/// `nil` and `false`.
+pub(crate) fn is_truthy(value: &Types) -> bool {
+    !matches!(value, Types::Boolean(false) | Types::Nil)
+}
+
+/// Whether two values are equal, as `==` decides. Values of different types
+/// are never equal, and functions, classes, instances, lists, maps and
+/// modules are only equal to themselves. Tuples are equal when their
+/// elements are.
+fn is_equal<'a>(a: &Types<'a>, b: &Types<'a>) -> bool {
+    match (a, b) {
+        (Types::Number(a), Types::Number(b)) => a == b,
+        (Types::ReturnString(a), Types::ReturnString(b)) => a == b,
+        (Types::Boolean(a), Types::Boolean(b)) => a == b,
+        (Types::Nil, Types::Nil) => true,
+        (Types::Function(a), Types::Function(b)) => Rc::ptr_eq(a, b),
+        (Types::NativeFunction(a), Types::NativeFunction(b)) => Rc::ptr_eq(a, b),
+        (Types::Class(a), Types::Class(b)) => Rc::ptr_eq(a, b),
+        (Types::Instance(a), Types::Instance(b)) => Rc::ptr_eq(a, b),
+        (Types::List(a), Types::List(b)) => Rc::ptr_eq(a, b),
+        (Types::Tuple(a), Types::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| is_equal(a, b))
+        }
+        (Types::Map(a), Types::Map(b)) => Rc::ptr_eq(a, b),
+        (Types::Module(a), Types::Module(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+/// The value as an integer, if it's a number without a fractional part.
+fn integer(value: &Types) -> Option<i64> {
+    match *value {
+        Types::Number(n) if n.fract() == 0.0 && n.abs() <= i64::MAX as f64 => Some(n as i64),
+        _ => None,
+    }
 }
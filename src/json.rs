@@ -0,0 +1,318 @@
+//! Just enough JSON for the interpreter's machine-readable output, and
+//! for reading what editors send the language server.
+
+use std::fmt::Write;
+
+/// `s` as a quoted JSON string.
+pub fn string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `n` as a JSON number, or `null` for infinities and NaN, which JSON has
+/// no way to write.
+pub fn number(n: f64) -> String {
+    if n.is_finite() {
+        n.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// A JSON array of already formatted values.
+pub fn array<I: IntoIterator<Item = String>>(values: I) -> String {
+    format!("[{}]", values.into_iter().collect::<Vec<_>>().join(","))
+}
+
+/// Builds a JSON object out of already formatted values, keeping fields in
+/// the order they're added.
+pub struct Object(String);
+
+impl Object {
+    pub fn new() -> Self {
+        Object(String::from("{"))
+    }
+
+    pub fn field(mut self, name: &str, value: String) -> Self {
+        if self.0.len() > 1 {
+            self.0.push(',');
+        }
+        self.0.push_str(&string(name));
+        self.0.push(':');
+        self.0.push_str(&value);
+        self
+    }
+
+    pub fn build(mut self) -> String {
+        self.0.push('}');
+        self.0
+    }
+}
+
+/// A parsed JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Fields in the order they were written.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The field called `name`, if this is an object that has one.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// The value written back out as JSON.
+    pub fn write(&self) -> String {
+        match self {
+            Value::Null => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => number(*n),
+            Value::String(s) => string(s),
+            Value::Array(values) => array(values.iter().map(Value::write)),
+            Value::Object(fields) => fields
+                .iter()
+                .fold(Object::new(), |object, (name, value)| {
+                    object.field(name, value.write())
+                })
+                .build(),
+        }
+    }
+}
+
+/// Parses a JSON document, or returns `None` if it isn't valid JSON.
+pub fn parse(text: &str) -> Option<Value> {
+    let mut parser = JsonParser {
+        chars: text.chars().collect(),
+        current: 0,
+    };
+    let value = parser.value(0)?;
+    parser.whitespace();
+    if parser.current == parser.chars.len() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// How deeply arrays and objects can nest before a document is rejected,
+/// so that parsing can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+struct JsonParser {
+    chars: Vec<char>,
+    current: usize,
+}
+
+impl JsonParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.current += 1;
+        Some(c)
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.current += 1;
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Option<()> {
+        for expected in word.chars() {
+            if self.advance()? != expected {
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    fn value(&mut self, depth: usize) -> Option<Value> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+
+        self.whitespace();
+        match self.peek()? {
+            'n' => self.expect("null").map(|_| Value::Null),
+            't' => self.expect("true").map(|_| Value::Bool(true)),
+            'f' => self.expect("false").map(|_| Value::Bool(false)),
+            '"' => self.string().map(Value::String),
+            '[' => {
+                self.advance();
+                let mut values = vec![];
+                self.whitespace();
+                if self.peek() == Some(']') {
+                    self.advance();
+                    return Some(Value::Array(values));
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    self.whitespace();
+                    match self.advance()? {
+                        ',' => {}
+                        ']' => return Some(Value::Array(values)),
+                        _ => return None,
+                    }
+                }
+            }
+            '{' => {
+                self.advance();
+                let mut fields = vec![];
+                self.whitespace();
+                if self.peek() == Some('}') {
+                    self.advance();
+                    return Some(Value::Object(fields));
+                }
+                loop {
+                    self.whitespace();
+                    let name = self.string()?;
+                    self.whitespace();
+                    self.expect(":")?;
+                    fields.push((name, self.value(depth + 1)?));
+                    self.whitespace();
+                    match self.advance()? {
+                        ',' => {}
+                        '}' => return Some(Value::Object(fields)),
+                        _ => return None,
+                    }
+                }
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Option<Value> {
+        let start = self.current;
+        while matches!(self.peek(), Some('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+            self.current += 1;
+        }
+        let text: String = self.chars[start..self.current].iter().collect();
+        text.parse().ok().map(Value::Number)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect("\"")?;
+        let mut out = String::new();
+        loop {
+            match self.advance()? {
+                '"' => return Some(out),
+                '\\' => match self.advance()? {
+                    '"' => out.push('"'),
+                    '\\' => out.push('\\'),
+                    '/' => out.push('/'),
+                    'b' => out.push('\u{8}'),
+                    'f' => out.push('\u{c}'),
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'u' => {
+                        let unit = self.hex4()?;
+                        let c = if (0xd800..0xdc00).contains(&unit) {
+                            // The first half of a surrogate pair.
+                            self.expect("\\u")?;
+                            let low = self.hex4()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return None;
+                            }
+                            char::from_u32(0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00))?
+                        } else {
+                            char::from_u32(unit)?
+                        };
+                        out.push(c);
+                    }
+                    _ => return None,
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let mut n = 0;
+        for _ in 0..4 {
+            n = n * 16 + self.advance()?.to_digit(16)?;
+        }
+        Some(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        assert_eq!(string("say \"hi\"\n\u{1}"), r#""say \"hi\"\n\u0001""#);
+        assert_eq!(number(1.5), "1.5");
+        assert_eq!(number(f64::INFINITY), "null");
+        assert_eq!(
+            Object::new()
+                .field("a", number(1.0))
+                .field("b", array(vec![string("x"), "null".to_string()]))
+                .build(),
+            r#"{"a":1,"b":["x",null]}"#
+        );
+        assert_eq!(Object::new().build(), "{}");
+    }
+
+    #[test]
+    fn test_parse() {
+        let value =
+            parse(r#" {"a": [1, -2.5e1, true, null], "b": "x\"\u00e9\ud83d\ude00"} "#).unwrap();
+        assert_eq!(
+            value.get("a"),
+            Some(&Value::Array(vec![
+                Value::Number(1.0),
+                Value::Number(-25.0),
+                Value::Bool(true),
+                Value::Null
+            ]))
+        );
+        assert_eq!(value.get("b").and_then(Value::as_str), Some("x\"é😀"));
+        assert_eq!(value.write(), r#"{"a":[1,-25,true,null],"b":"x\"é😀"}"#);
+
+        assert_eq!(parse("[1,"), None);
+        assert_eq!(parse("{} x"), None);
+        assert_eq!(parse(&"[".repeat(1000)), None);
+    }
+}
@@ -0,0 +1,1987 @@
+//! A tree-walk interpreter for Lox, the language from the book "Crafting
+//! Interpreters".
+//!
+//! Most programs only need [`Lox`]:
+//!
+//! ```
+//! use rlox_treewalk::{Lox, Value};
+//!
+//! let lox = Lox::new();
+//! lox.run("var greeting = \"hi\";").unwrap();
+//! match lox.evaluate("greeting + \"!\"").unwrap() {
+//!     Value::ReturnString(s) => assert_eq!(&*s, "hi!"),
+//!     other => panic!("unexpected value {}", other),
+//! }
+//! ```
+
+pub mod ast;
+pub mod ast_dot;
+pub mod ast_json;
+pub mod callable;
+pub mod class;
+pub mod clock;
+pub mod coverage;
+pub mod diagnostic;
+pub mod environment;
+pub mod formatter;
+mod gc;
+pub mod highlight;
+pub mod host;
+pub mod interpreter;
+mod json;
+pub mod lsp;
+pub mod map;
+pub mod module;
+pub mod optimizer;
+pub mod parser;
+pub mod playground;
+pub mod profiler;
+pub mod random;
+pub mod resolver;
+pub mod scanner;
+mod strings;
+pub mod symbol;
+pub mod test_runner;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+use std::{panic, thread};
+
+use clock::{Clock, MonotonicClock};
+use diagnostic::{Diagnostic, Format, StaticErrors};
+use host::CaptureHost;
+use interpreter::Interpreter;
+use parser::{Parser, ReplInput};
+use resolver::{Resolver, Warning};
+use scanner::Scanner;
+
+pub use interpreter::Types as Value;
+
+/// What happened to a chunk of input given to [`Lox::run_line`].
+pub enum LineResult<'a> {
+    /// The input stopped partway through, so nothing was run.
+    Incomplete,
+    /// The input was a lone expression, which evaluated to this.
+    Value(Value<'a>),
+    /// The input was a list of statements, which have been run.
+    Executed,
+}
+
+/// The grammar [`Lox::execute`] parses its source with.
+#[derive(Clone, Copy)]
+enum Grammar {
+    /// A script: statements, each ending with `;` or a block.
+    Program,
+    /// A lone expression.
+    Expression,
+    /// What's typed at the REPL: statements, or a lone expression without a
+    /// trailing semicolon.
+    Repl,
+}
+
+/// An interpreter session. Globals defined by one call to [`Lox::run`] are
+/// visible to later ones.
+///
+/// Functions and classes keep pointing into the source text that declared
+/// them, so every source passed in has to outlive the session.
+pub struct Lox<'a> {
+    interpreter: Interpreter<'a>,
+    timings: Cell<Timings>,
+    clock: MonotonicClock,
+    /// Every input given to [`Lox::run_line`], laid end to end in one
+    /// virtual buffer so an error can be traced back to the input it's in.
+    inputs: RefCell<Vec<Input<'a>>>,
+}
+
+/// One chunk of interactive input, and where it sits in the virtual buffer.
+#[derive(Clone, Copy)]
+struct Input<'a> {
+    /// Counting from 1.
+    number: usize,
+    /// Byte offset of the input's start.
+    offset: usize,
+    source: &'a str,
+}
+
+impl Input<'_> {
+    /// Moves a diagnostic about the virtual buffer to one about this input.
+    fn localize(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        diagnostic.start = diagnostic.start.saturating_sub(self.offset);
+        diagnostic.end = diagnostic.end.saturating_sub(self.offset);
+        diagnostic.input = Some(self.number);
+        diagnostic
+    }
+}
+
+/// Wall-clock time spent in each phase of running code, added up over every
+/// call to [`Lox::run`] or [`Lox::run_line`] in a session. Modules imported
+/// while running count towards `interpret`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    pub scan: Duration,
+    pub parse: Duration,
+    pub optimize: Duration,
+    pub resolve: Duration,
+    pub interpret: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.scan + self.parse + self.optimize + self.resolve + self.interpret
+    }
+}
+
+impl<'a> Lox<'a> {
+    pub fn new() -> Self {
+        Self::with_interpreter(Interpreter::new())
+    }
+
+    /// A session run by `interpreter`, e.g. one made with
+    /// [`Interpreter::with_host`].
+    pub fn with_interpreter(interpreter: Interpreter<'a>) -> Self {
+        Lox {
+            interpreter,
+            timings: Cell::new(Timings::default()),
+            clock: MonotonicClock::new(),
+            inputs: RefCell::new(vec![]),
+        }
+    }
+
+    /// The interpreter behind this session, e.g. to register native
+    /// functions with [`Interpreter::define_native`].
+    pub fn interpreter(&self) -> &Interpreter<'a> {
+        &self.interpreter
+    }
+
+    /// How long the code run so far has spent in each phase.
+    pub fn timings(&self) -> Timings {
+        self.timings.get()
+    }
+
+    /// Adds the time since `start` to one of the phases, and returns now.
+    fn time(&self, start: Duration, phase: impl FnOnce(&mut Timings) -> &mut Duration) -> Duration {
+        let now = self.clock.elapsed();
+        let mut timings = self.timings.get();
+        *phase(&mut timings) += now - start;
+        self.timings.set(timings);
+        now
+    }
+
+    /// Runs `source`, a script like one read from a file.
+    pub fn run(&self, source: &'a str) -> anyhow::Result<()> {
+        self.execute(source, Grammar::Program, true, None)?;
+        Ok(())
+    }
+
+    /// Evaluates `source`, which has to be a lone expression, and returns
+    /// its value.
+    pub fn evaluate(&self, source: &'a str) -> anyhow::Result<Value<'a>> {
+        match self.execute(source, Grammar::Expression, true, None)? {
+            LineResult::Value(value) => Ok(value),
+            LineResult::Incomplete | LineResult::Executed => {
+                unreachable!("an expression always has a value")
+            }
+        }
+    }
+
+    /// Runs a chunk of interactive input. Unless `force` is set, input that
+    /// ends partway through a statement is left alone and reported as
+    /// [`LineResult::Incomplete`] so the caller can ask for more.
+    ///
+    /// Each input that isn't incomplete is numbered, and errors from it
+    /// should be printed with [`Lox::report`] to say which input they're in.
+    pub fn run_line(&self, source: &'a str, force: bool) -> anyhow::Result<LineResult<'a>> {
+        let input = {
+            let mut inputs = self.inputs.borrow_mut();
+            let input = Input {
+                number: inputs.len() + 1,
+                offset: inputs
+                    .last()
+                    .map_or(0, |last| last.offset + last.source.len()),
+                source,
+            };
+            inputs.push(input);
+            input
+        };
+        let result = self.execute(source, Grammar::Repl, force, Some(input));
+        if let Ok(LineResult::Incomplete) = result {
+            self.inputs.borrow_mut().pop();
+        }
+        result
+    }
+
+    /// Prints an error from [`Lox::run_line`] to stderr like
+    /// [`diagnostic::report`], pointing each diagnostic at the input it's
+    /// about rather than at the virtual buffer they're all in.
+    pub fn report(&self, error: &anyhow::Error, format: Format) {
+        let diagnostics = match diagnostic::from_error(error) {
+            Some(diagnostics) => diagnostics,
+            None => return self.interpreter.emit_error(error),
+        };
+        for diagnostic in diagnostics {
+            let (diagnostic, source) = self.locate(diagnostic);
+            self.interpreter.emit(&diagnostic, source, format);
+        }
+    }
+
+    /// Moves a diagnostic about the virtual buffer to the input it's in, and
+    /// returns it with that input's source.
+    fn locate(&self, diagnostic: Diagnostic) -> (Diagnostic, &'a str) {
+        let inputs = self.inputs.borrow();
+        match inputs
+            .iter()
+            .rev()
+            .find(|input| input.offset <= diagnostic.start)
+        {
+            Some(input) => (input.localize(diagnostic), input.source),
+            None => (diagnostic, ""),
+        }
+    }
+
+    /// Runs `source`, parsed with `grammar`, which is `repl` if it came from
+    /// [`Lox::run_line`].
+    fn execute(
+        &self,
+        source: &'a str,
+        grammar: Grammar,
+        force: bool,
+        repl: Option<Input<'a>>,
+    ) -> anyhow::Result<LineResult<'a>> {
+        let start = self.clock.elapsed();
+        let mut scanner = Scanner::with_offset(source, repl.map_or(0, |input| input.offset));
+        // Every pass runs even if the ones before it failed, and nothing is
+        // run unless they all succeeded.
+        let mut errors = StaticErrors::default();
+        errors.0.extend(scanner.scan_tokens().err());
+        let start = self.time(start, |t| &mut t.scan);
+
+        let parser = Parser::new(scanner.tokens());
+        let (input, parse_errors) = match grammar {
+            Grammar::Program => {
+                let (statements, errors) = parser.parse_all();
+                (ReplInput::Statements(statements), errors)
+            }
+            Grammar::Expression => parser.parse_expression_all(),
+            Grammar::Repl => parser.parse_repl_all(),
+        };
+        let start = self.time(start, |t| &mut t.parse);
+        if let Some(parse_errors) = parse_errors {
+            if errors.0.is_empty() && parse_errors.is_incomplete() && !force {
+                return Ok(LineResult::Incomplete);
+            }
+            errors.0.push(parse_errors.into());
+        }
+        let mut ast = parser.into_ast();
+        self.interpreter.optimize(&mut ast);
+        let ast = Rc::new(ast);
+        let start = self.time(start, |t| &mut t.optimize);
+
+        let mut resolver = Resolver::new(&ast);
+        let resolved = match &input {
+            ReplInput::Expression(expr) => resolver.resolve_expression(*expr),
+            ReplInput::Statements(statements) => resolver.resolve_all(statements),
+        };
+        let start = self.time(start, |t| &mut t.resolve);
+        errors.0.extend(resolved.err());
+        if !errors.0.is_empty() {
+            return Err(errors.into());
+        }
+        self.warn(source, repl, resolver.take_warnings());
+
+        match input {
+            ReplInput::Expression(expr) => {
+                let value = self.interpreter.evaluate(&ast, expr);
+                self.time(start, |t| &mut t.interpret);
+                Ok(LineResult::Value(value?))
+            }
+            ReplInput::Statements(statements) => {
+                let interpreted = self.interpreter.interpret(&ast, &statements);
+                self.time(start, |t| &mut t.interpret);
+                interpreted?;
+                Ok(LineResult::Executed)
+            }
+        }
+    }
+
+    /// Reports warnings about `source`, which is `repl` if it came from
+    /// [`Lox::run_line`].
+    fn warn(&self, source: &str, repl: Option<Input<'a>>, warnings: Vec<Warning>) {
+        let warnings = warnings.iter().map(Warning::diagnostic);
+        match repl {
+            Some(input) => self
+                .interpreter
+                .warn(source, warnings.map(|w| input.localize(w))),
+            None => self.interpreter.warn(source, warnings),
+        }
+    }
+}
+
+/// Whether `source` stops partway through, so that [`Lox::run_line`] would
+/// return [`LineResult::Incomplete`] for it without `force`. Unlike
+/// `run_line`, this only borrows `source` for the call, so a REPL can check a
+/// line before deciding whether to keep it for good.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    if scanner.scan_tokens().is_err() {
+        return false;
+    }
+    let parser = Parser::new(scanner.tokens());
+    match parser.parse_repl_all() {
+        (_, Some(errors)) => errors.is_incomplete(),
+        (_, None) => false,
+    }
+}
+
+/// How deep Lox calls can go under [`fuzz_run`].
+const FUZZ_MAX_CALL_DEPTH: usize = 32;
+
+/// The stack [`fuzz_run`] runs a program on, with room for its deepest calls
+/// and for [`parser::MAX_NESTING`] in a debug build.
+const FUZZ_STACK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Runs `source` in a fresh session, throwing away the result and anything
+/// it prints. Whatever the input, this returns rather than panicking, so
+/// it's the entry point to point a fuzzer like cargo-fuzz at. It can still
+/// loop forever on a program that does.
+pub fn fuzz_run(source: &str) {
+    let run = || {
+        // Nothing a fuzzed program does reaches outside, like waiting on
+        // stdin, printing or reading files, and it runs the same way every
+        // time.
+        let host = Rc::new(CaptureHost::new());
+        host.deny_imports();
+        let lox = Lox::with_interpreter(Interpreter::with_host(host));
+        lox.interpreter().set_max_call_depth(FUZZ_MAX_CALL_DEPTH);
+        let _ = lox.run(source);
+    };
+    let result = thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(FUZZ_STACK_SIZE)
+            .spawn_scoped(scope, run)
+            .expect("couldn't start a thread to fuzz on")
+            .join()
+    });
+    // A panic is what a fuzzer is looking for, so it's passed on.
+    if let Err(panic) = result {
+        panic::resume_unwind(panic);
+    }
+}
+
+impl Default for Lox<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use host::CaptureHost;
+    use interpreter::RuntimeError;
+
+    /// Runs `source` in a fresh session as if it had been typed at the REPL,
+    /// and formats the value of a lone expression, nil for statements, or the
+    /// error.
+    fn eval(source: &str) -> String {
+        match Lox::new().run_line(source, true) {
+            Ok(LineResult::Value(value)) => value.to_string(),
+            Ok(_) => "nil".to_string(),
+            Err(e) => format!("error: {}", e),
+        }
+    }
+
+    /// Runs `source` as a script in a fresh session and returns what it
+    /// printed.
+    fn printed(source: &str) -> String {
+        let host = Rc::new(CaptureHost::new());
+        let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+        lox.run(source).unwrap();
+        host.take_stdout()
+    }
+
+    /// Runs `source` in a fresh session and returns the codes of the static
+    /// errors it has.
+    fn error_codes(source: &str) -> Vec<&'static str> {
+        let error = Lox::new().run(source).err().unwrap();
+        diagnostic::from_error(&error)
+            .unwrap()
+            .iter()
+            .map(|d| d.code)
+            .collect()
+    }
+
+    /// Resolves `source` and formats the warnings it produces.
+    fn warnings(source: &str) -> Vec<String> {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let parser = Parser::new(tokens);
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        let mut resolver = Resolver::new(&ast);
+        resolver.resolve(&statements).unwrap();
+        resolver
+            .take_warnings()
+            .iter()
+            .map(|w| w.to_string().trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_statements() {
+        assert_eq!(
+            printed("print 1 + 2;\n\"unused\";\nprint \"two\";"),
+            "3\ntwo\n"
+        );
+        assert_eq!(printed("print 1; print 2;"), "1\n2\n");
+        assert_eq!(printed(""), "");
+        assert!(Lox::new().run("print;").is_err());
+        assert!(Lox::new().run("print 1").is_err());
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(
+            printed("var a = 1; var b; print a; print b; a = b = 3; print a + b;"),
+            "1\nnil\n6\n"
+        );
+        assert_eq!(printed("var a = 1; var a = \"again\"; print a;"), "again\n");
+        assert_eq!(
+            eval("undefined"),
+            "error: Undefined variable 'undefined'.\n[line 1, column 1]"
+        );
+        assert_eq!(
+            eval("x = 1"),
+            "error: Undefined variable 'x'.\n[line 1, column 1]"
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!(
+            printed(
+                "var a = \"global\";
+                { var a = \"outer\"; { var a = \"inner\"; print a; } print a; }
+                print a;"
+            ),
+            "inner\nouter\nglobal\n"
+        );
+        // Assigning doesn't declare, so the outer variable changes.
+        assert_eq!(
+            printed("var b = 1; { b = 2; { b = b + 1; } } print b;"),
+            "3\n"
+        );
+        assert!(Lox::new().run("{ var c = 1; } print c;").is_err());
+    }
+
+    #[test]
+    fn test_if() {
+        // The else belongs to the nearest if.
+        assert_eq!(
+            printed("if (true) if (false) print 1; else print 2;"),
+            "2\n"
+        );
+        assert_eq!(printed("if (false) if (true) print 1; else print 2;"), "");
+        assert_eq!(
+            printed(
+                "if (0) print \"zero\";
+                if (\"\") print \"empty\";
+                if (nil) print \"nil\"; else print \"not nil\";
+                if (false) print \"false\"; else if (true) print \"else if\";"
+            ),
+            "zero\nempty\nnot nil\nelse if\n"
+        );
+    }
+
+    #[test]
+    fn test_logical() {
+        // The result is an operand, not a boolean.
+        assert_eq!(eval("nil or \"yes\""), "yes");
+        assert_eq!(eval("1 and 2"), "2");
+        assert_eq!(eval("nil and 2"), "nil");
+        assert_eq!(eval("0 or 2"), "0");
+        // The right operand is only evaluated when it's needed.
+        assert_eq!(eval("false and undefined"), "false");
+        assert_eq!(eval("1 or undefined"), "1");
+        assert!(eval("true and undefined").starts_with("error: Undefined variable"));
+        assert_eq!(eval("false or nil and 1"), "nil");
+    }
+
+    #[test]
+    fn test_for() {
+        assert_eq!(
+            printed("for (var i = 0; i < 3; i = i + 1) print i;"),
+            "0\n1\n2\n"
+        );
+        assert_eq!(
+            printed("var n = 0; for (; n < 2;) n = n + 1; print n;"),
+            "2\n"
+        );
+        assert_eq!(
+            printed("var i = \"outer\"; for (var i = 0; i < 1; i = i + 1) {} print i;"),
+            "outer\n"
+        );
+        assert!(Lox::new()
+            .run("for (var j = 0; j < 1; j = j + 1) {} print j;")
+            .is_err());
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(printed("fun add(a, b) { print a + b; } add(1, 2);"), "3\n");
+        let lox = Lox::new();
+        lox.run(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            fun nothing() {}
+            fun early() { return; print \"not reached\"; }",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("fib(10)").unwrap().to_string(), "55");
+        assert_eq!(lox.evaluate("nothing()").unwrap().to_string(), "nil");
+        assert_eq!(lox.evaluate("early()").unwrap().to_string(), "nil");
+        assert_eq!(lox.evaluate("fib").unwrap().to_string(), "<fn fib>");
+        assert_eq!(
+            lox.evaluate("fib(1, 2)").unwrap_err().to_string(),
+            "Expected 1 arguments but got 2.\n[line 1, column 9]"
+        );
+        assert_eq!(
+            eval("\"s\"()"),
+            "error: Can only call functions and classes.\n[line 1, column 5]"
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        assert_eq!(
+            printed(
+                "fun makeCounter() {
+                    var i = 0;
+                    fun count() { i = i + 1; print i; }
+                    return count;
+                }
+                var a = makeCounter();
+                var b = makeCounter();
+                a(); a(); b(); a();"
+            ),
+            "1\n2\n1\n3\n"
+        );
+        // A closure outlives the call that declared it, and sees later
+        // changes to what it captured.
+        assert_eq!(
+            printed(
+                "var get; var set;
+                fun make() { var x = \"one\"; fun g() { return x; } fun s(v) { x = v; } get = g; set = s; }
+                make(); set(\"two\"); print get();"
+            ),
+            "two\n"
+        );
+    }
+
+    #[test]
+    fn test_resolved_scopes() {
+        // Both calls see the `a` that was in scope where `showA` was
+        // declared, even though another is declared before the second.
+        assert_eq!(
+            printed(
+                "var a = \"global\";
+                {
+                    fun showA() { print a; }
+                    showA();
+                    var a = \"block\";
+                    showA();
+                    print a;
+                }"
+            ),
+            "global\nglobal\nblock\n"
+        );
+        assert_eq!(error_codes("{ var a = a; }"), ["E0200"]);
+        assert_eq!(error_codes("{ var a; var a; }"), ["E0201"]);
+        assert_eq!(error_codes("return 1;"), ["E0202"]);
+        // Globals can be redeclared and read before they're defined.
+        assert_eq!(printed("var g = 1; var g = g + 1; print g;"), "2\n");
+        assert_eq!(
+            printed("fun f() { return later; } var later = \"ok\"; print f();"),
+            "ok\n"
+        );
+    }
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(
+            printed(
+                "class Bagel {
+                    eat() { print \"Crunch crunch crunch!\"; }
+                    name() { return this.kind + \" bagel\"; }
+                }
+                print Bagel;
+                var b = Bagel();
+                print b;
+                b.eat();
+                b.kind = \"sesame\";
+                print b.name();
+                var name = b.name;
+                b.kind = \"plain\";
+                print name();"
+            ),
+            "Bagel\nBagel instance\nCrunch crunch crunch!\nsesame bagel\nplain bagel\n"
+        );
+        // Fields are per instance, and shadow methods.
+        assert_eq!(
+            printed(
+                "class A { m() { return \"method\"; } }
+                var a = A(); var b = A();
+                a.m = \"field\"; print a.m; print b.m();"
+            ),
+            "field\nmethod\n"
+        );
+        let lox = Lox::new();
+        lox.run("class B {} var n = 1;").unwrap();
+        assert_eq!(
+            lox.evaluate("B().x").unwrap_err().to_string(),
+            "Undefined property 'x'.\n[line 1, column 5]"
+        );
+        assert!(lox.evaluate("n.x = 2").is_err());
+        assert!(lox.evaluate("n.x").is_err());
+        assert_eq!(error_codes("print this;"), ["E0204"]);
+    }
+
+    #[test]
+    fn test_init() {
+        let lox = Lox::new();
+        lox.run(
+            "class Point {
+                init(x, y) { this.x = x; this.y = y; }
+            }
+            class Early {
+                init(stop) { this.ran = \"some\"; if (stop) return; this.ran = \"all\"; }
+            }
+            var p = Point(1, 2);",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("p.x + p.y").unwrap().to_string(), "3");
+        // Calling init again returns the instance, not nil.
+        assert_eq!(
+            lox.evaluate("p.init(3, 4) == p").unwrap().to_string(),
+            "true"
+        );
+        assert_eq!(lox.evaluate("p.x").unwrap().to_string(), "3");
+        assert_eq!(lox.evaluate("Early(true).ran").unwrap().to_string(), "some");
+        assert_eq!(lox.evaluate("Early(false).ran").unwrap().to_string(), "all");
+        // The class takes the initializer's arguments.
+        assert!(lox.evaluate("Point(1)").is_err());
+        assert_eq!(error_codes("class A { init() { return 1; } }"), ["E0203"]);
+    }
+
+    #[test]
+    fn test_super() {
+        let lox = Lox::new();
+        lox.run(
+            "class A {
+                method() { return \"A method\"; }
+                name() { return \"A\"; }
+            }
+            class B < A {
+                method() { return \"B method\"; }
+                test() { return super.method(); }
+                name() { return \"B then \" + super.name(); }
+            }
+            class C < B {
+                name() { return \"C then \" + super.name(); }
+            }",
+        )
+        .unwrap();
+        // `super` is the superclass of the class the method is in, not of
+        // the instance's class.
+        assert_eq!(lox.evaluate("C().test()").unwrap().to_string(), "A method");
+        assert_eq!(
+            lox.evaluate("C().method()").unwrap().to_string(),
+            "B method"
+        );
+        assert_eq!(
+            lox.evaluate("C().name()").unwrap().to_string(),
+            "C then B then A"
+        );
+        assert!(lox.run("var n = 1; class Y < n {}").is_err());
+        assert_eq!(error_codes("class X < X {}"), ["E0207"]);
+        assert_eq!(error_codes("print super.x;"), ["E0205"]);
+        assert_eq!(error_codes("class D { f() { super.f(); } }"), ["E0206"]);
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let host = Rc::new(CaptureHost::new());
+        let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+        let error = lox
+            .run("print \"ran\";\nprint -\"a\";\nprint \"not ran\";")
+            .unwrap_err();
+        let error = error.downcast_ref::<RuntimeError>().unwrap();
+        assert_eq!(error.message, "Operand must be a number.");
+        assert_eq!((error.line, error.column), (2, 7));
+        assert_eq!(host.take_stdout(), "ran\n");
+    }
+
+    #[test]
+    fn test_error_columns() {
+        let source = "var a = 1;\nprint a +;";
+        let error = Lox::new().run(source).unwrap_err();
+        let diagnostic = diagnostic::from_error(&error).unwrap().remove(0);
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 10));
+        assert_eq!(&source[diagnostic.start..diagnostic.end], ";");
+        assert_eq!(
+            error.to_string(),
+            "[line 2, column 10] Error at ';': Expect expression.\n"
+        );
+
+        let source = "var s = \"x\";\n  print s - 1;";
+        let error = Lox::new().run(source).unwrap_err();
+        let diagnostic = diagnostic::from_error(&error).unwrap().remove(0);
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 11));
+        assert_eq!(&source[diagnostic.start..diagnostic.end], "s - 1");
+    }
+
+    #[test]
+    fn test_repl_keeps_state() {
+        let lox = Lox::new();
+        let value = |source| match lox.run_line(source, false) {
+            Ok(LineResult::Value(value)) => value.to_string(),
+            _ => panic!("{} has no value", source),
+        };
+        assert!(lox.run_line("var x = 1;", false).is_ok());
+        assert!(lox.run_line("fun inc() { x = x + 1; }", false).is_ok());
+        assert!(lox.run_line("inc(); inc();", false).is_ok());
+        assert_eq!(value("x"), "3");
+        // An error in one input doesn't lose what came before it.
+        assert!(lox.run_line("x = -nil;", false).is_err());
+        assert!(lox.run_line("var y = ;", false).is_err());
+        assert_eq!(value("x"), "3");
+        assert!(lox.run_line("y", false).is_err());
+    }
+
+    #[test]
+    fn test_repl_values() {
+        let host = Rc::new(CaptureHost::new());
+        let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+        let run = |source| lox.run_line(source, false);
+        assert!(matches!(run("1 + 2"), Ok(LineResult::Value(Value::Number(n))) if n == 3.0));
+        assert!(matches!(run("var a = 1;"), Ok(LineResult::Executed)));
+        // An assignment is an expression too.
+        assert!(matches!(run("a = 5"), Ok(LineResult::Value(Value::Number(n))) if n == 5.0));
+        assert!(matches!(run("print a;"), Ok(LineResult::Executed)));
+        assert!(matches!(run("nil"), Ok(LineResult::Value(Value::Nil))));
+        // Values are returned for the caller to show, not printed.
+        assert_eq!(host.take_stdout(), "5\n");
+    }
+
+    #[test]
+    fn test_multiline_input() {
+        let lox = Lox::new();
+        let run = |source| lox.run_line(source, false);
+        for source in [
+            "fun f() {",
+            "fun f() {\n  return (1 +",
+            "print \"a\" +",
+            "f(",
+        ] {
+            assert!(
+                matches!(run(source), Ok(LineResult::Incomplete)),
+                "{}",
+                source
+            );
+        }
+        assert!(matches!(
+            run("fun f() {\n  return (1 +\n    2);\n}"),
+            Ok(LineResult::Executed)
+        ));
+        assert!(matches!(run("f()"), Ok(LineResult::Value(Value::Number(n))) if n == 3.0));
+        // A mistake isn't mistaken for the input going on.
+        assert!(run("print 1 +;").is_err());
+        assert!(run("}").is_err());
+        // Forcing input ends it, even partway through.
+        assert!(lox.run_line("fun g() {", true).is_err());
+    }
+
+    #[test]
+    fn test_clock() {
+        let lox = Lox::new();
+        lox.run("var start = clock();").unwrap();
+        assert!(matches!(lox.evaluate("start").unwrap(), Value::Number(n) if n > 0.0));
+        assert_eq!(
+            lox.evaluate("clock() >= start").unwrap().to_string(),
+            "true"
+        );
+        assert_eq!(lox.evaluate("clock").unwrap().to_string(), "<native fn>");
+        assert_eq!(
+            lox.evaluate("clock(1)").unwrap_err().to_string(),
+            "Expected 0 arguments but got 1.\n[line 1, column 8]"
+        );
+    }
+
+    #[test]
+    fn test_define_native() {
+        let lox = Lox::new();
+        let calls = Rc::new(Cell::new(0));
+        let counted = calls.clone();
+        lox.interpreter().define_native("join", 2, move |args| {
+            counted.set(counted.get() + 1);
+            match args {
+                [Value::ReturnString(a), Value::ReturnString(b)] => {
+                    Ok(Value::from(format!("{}{}", a, b)))
+                }
+                _ => Err("Expected two strings.".to_string()),
+            }
+        });
+
+        lox.run("fun shout(s) { return join(s, \"!\"); }").unwrap();
+        assert_eq!(lox.evaluate("shout(\"hi\")").unwrap().to_string(), "hi!");
+        assert_eq!(
+            lox.evaluate("join(1, \"!\")").unwrap_err().to_string(),
+            "Expected two strings.\n[line 1, column 12]"
+        );
+        assert!(lox.evaluate("join(\"a\")").is_err());
+        // Arity is checked before the function is called.
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_exponent() {
+        assert_eq!(eval("2 ** 10"), "1024");
+        assert_eq!(eval("2 ** 3 ** 2"), "512");
+        assert_eq!(eval("2 * 3 ** 2"), "18");
+        assert_eq!(eval("-2 ** 2"), "4");
+        assert_eq!(eval("2 ** -1"), "0.5");
+    }
+
+    #[test]
+    fn test_bitwise() {
+        assert_eq!(eval("6 & 3"), "2");
+        assert_eq!(eval("6 | 3"), "7");
+        assert_eq!(eval("6 ^ 3"), "5");
+        assert_eq!(eval("1 << 4 + 1"), "32");
+        assert_eq!(eval("-16 >> 2"), "-4");
+        assert_eq!(eval("5 & 1 == 1"), "true");
+        assert_eq!(
+            eval("1.5 | 0"),
+            "error: Operands must be whole numbers.\n[line 1, column 5]"
+        );
+        assert_eq!(
+            eval("1 << 64"),
+            "error: Shift amount must be between 0 and 63.\n[line 1, column 3]"
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let lox = Lox::new();
+        lox.run("var a = 10; a += 5; a -= 3; a *= 2; a /= 4;")
+            .unwrap();
+        assert_eq!(lox.evaluate("a").unwrap().to_string(), "6");
+
+        lox.run("var s = \"a\"; s += \"b\";").unwrap();
+        assert_eq!(lox.evaluate("s").unwrap().to_string(), "ab");
+
+        lox.run("class C {} var c = C(); c.n = 1; c.n += 41;")
+            .unwrap();
+        assert_eq!(lox.evaluate("c.n").unwrap().to_string(), "42");
+
+        // The receiver and index are only evaluated once.
+        lox.run(
+            "var calls = 0; var l = [1]; fun f() { calls += 1; return l; }
+            fun i() { calls += 1; return 0; } f()[i()] += 10;
+            fun g() { calls += 1; return c; } g().n += 1;",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("calls").unwrap().to_string(), "3");
+        assert_eq!(lox.evaluate("l[0]").unwrap().to_string(), "11");
+        assert_eq!(lox.evaluate("c.n").unwrap().to_string(), "43");
+
+        assert!(lox.run("1 += 2;").is_err());
+    }
+
+    #[test]
+    fn test_comma_operator() {
+        let lox = Lox::new();
+        assert_eq!(lox.evaluate("1, 2, 3").unwrap().to_string(), "3");
+
+        lox.run("var a = 0; var b; a = 1, b = a + 1;").unwrap();
+        assert_eq!(lox.evaluate("b").unwrap().to_string(), "2");
+        lox.run("var c = a = 5, a + 1;").unwrap();
+        assert_eq!(lox.evaluate("c").unwrap().to_string(), "6");
+
+        // In parentheses it's a tuple instead, with every operand in it.
+        lox.run("var d = (a = 1, a + 1);").unwrap();
+        assert_eq!(lox.evaluate("d").unwrap().to_string(), "(1, 2)");
+        assert_eq!(lox.evaluate("a").unwrap().to_string(), "1");
+
+        lox.run("fun first(x, y) { return x; } fun second(x, y) { return y; }")
+            .unwrap();
+        assert_eq!(lox.evaluate("second(1, 2)").unwrap().to_string(), "2");
+        assert_eq!(
+            lox.evaluate("first((1, 2), 3)").unwrap().to_string(),
+            "(1, 2)"
+        );
+    }
+
+    #[test]
+    fn test_break() {
+        let lox = Lox::new();
+        lox.run(
+            "var n = 0;
+            while (true) { n = n + 1; if (n == 3) break; }
+            for (var i = 0; i < 10; i = i + 1) { { if (i == 4) break; } n = n + 10; }",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("n").unwrap().to_string(), "43");
+
+        assert!(lox.run("break;").is_err());
+        assert!(lox.run("while (true) { fun f() { break; } }").is_err());
+    }
+
+    #[test]
+    fn test_continue() {
+        let lox = Lox::new();
+        lox.run(
+            "var odd = 0;
+            for (var i = 0; i < 10; i = i + 1) { if (i / 2 == (i >> 1)) continue; odd = odd + i; }
+            var j = 0; var evens = 0;
+            while (j < 6) { j = j + 1; { if (j & 1 == 1) continue; } evens = evens + j; }",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("odd").unwrap().to_string(), "25");
+        assert_eq!(lox.evaluate("evens").unwrap().to_string(), "12");
+
+        assert!(lox.run("continue;").is_err());
+    }
+
+    #[test]
+    fn test_anonymous_functions() {
+        let lox = Lox::new();
+        lox.run(
+            "fun twice(f, x) { return f(f(x)); }
+            var n = 10;
+            var add_n = fun (x) { return x + n; };",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("twice(add_n, 1)").unwrap().to_string(), "21");
+        assert_eq!(
+            lox.evaluate("twice(fun (s) { return s + \"!\"; }, \"hi\")")
+                .unwrap()
+                .to_string(),
+            "hi!!"
+        );
+        assert_eq!(lox.evaluate("add_n").unwrap().to_string(), "<fn>");
+
+        lox.run("fun () { n = 0; }();").unwrap();
+        assert_eq!(lox.evaluate("n").unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn test_arrow_functions() {
+        let lox = Lox::new();
+        lox.run("fun apply(f, x) { return f(x); } var k = 3;")
+            .unwrap();
+        assert_eq!(
+            lox.evaluate("apply(fun (x) => x * k, 2)")
+                .unwrap()
+                .to_string(),
+            "6"
+        );
+        assert_eq!(
+            lox.evaluate("(fun (a, b) => a ** b)(2, 5)")
+                .unwrap()
+                .to_string(),
+            "32"
+        );
+        assert!(lox.run("fun (x) => ;").is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        let lox = Lox::new();
+        lox.run("var xs = [1, \"two\", [3],]; var ys = xs;")
+            .unwrap();
+        assert_eq!(lox.evaluate("xs").unwrap().to_string(), "[1, two, [3]]");
+        assert_eq!(lox.evaluate("xs[2][0]").unwrap().to_string(), "3");
+        assert_eq!(lox.evaluate("[]").unwrap().to_string(), "[]");
+
+        lox.run("ys[0] = 10; ys[0] += 1; xs[2][0] = nil;").unwrap();
+        assert_eq!(lox.evaluate("xs").unwrap().to_string(), "[11, two, [nil]]");
+
+        assert_eq!(
+            lox.evaluate("xs[3]").unwrap_err().to_string(),
+            "List index 3 is out of bounds for a list of length 3.\n[line 1, column 5]"
+        );
+        assert!(lox.evaluate("xs[-1]").is_err());
+        assert!(lox.evaluate("xs[0.5]").is_err());
+        assert!(lox.evaluate("\"str\"[0]").is_err());
+    }
+
+    #[test]
+    fn test_maps() {
+        let lox = Lox::new();
+        lox.run("var m = {\"b\": 1, 2: [3], \"a\": nil,}; var alias = m;")
+            .unwrap();
+        assert_eq!(
+            lox.evaluate("m").unwrap().to_string(),
+            "{b: 1, 2: [3], a: nil}"
+        );
+        assert_eq!(lox.evaluate("m[\"b\"] + m[2][0]").unwrap().to_string(), "4");
+        assert_eq!(lox.evaluate("{}").unwrap().to_string(), "{}");
+
+        lox.run("alias[\"b\"] += 1; alias[\"c\"] = true; alias[-0] = 0;")
+            .unwrap();
+        assert_eq!(
+            lox.evaluate("m").unwrap().to_string(),
+            "{b: 2, 2: [3], a: nil, c: true, 0: 0}"
+        );
+        assert_eq!(lox.evaluate("m[0]").unwrap().to_string(), "0");
+
+        assert_eq!(
+            lox.evaluate("m[\"z\"]").unwrap_err().to_string(),
+            "Undefined key 'z'.\n[line 1, column 6]"
+        );
+        assert!(lox.run("m[nil] = 1;").is_err());
+        assert!(lox.evaluate("{[]: 1}").is_err());
+    }
+
+    #[test]
+    fn test_for_in() {
+        let lox = Lox::new();
+        lox.run(
+            "var sum = 0; var out = \"\";
+            for (x in [1, 2, 3, 4]) { if (x == 2) continue; if (x == 4) break; sum += x; }
+            for (var k in {\"a\": 1, \"b\": 2}) out += k;
+            for (c in \"hé\") out += c;
+            var fs = [nil];
+            for (i in [1, 2]) fs[0] = fun () => i;",
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(lox.evaluate("sum").unwrap().to_string(), "4");
+        assert_eq!(lox.evaluate("out").unwrap().to_string(), "abhé");
+        assert_eq!(lox.evaluate("fs[0]()").unwrap().to_string(), "2");
+
+        assert_eq!(
+            lox.run("for (x in 1) {}").unwrap_err().to_string(),
+            "Can only iterate over lists, maps and strings.\n[line 1, column 8]"
+        );
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let lox = Lox::new();
+        lox.run(
+            "var caught = [nil, nil, nil];
+            fun fail(value) { throw value; }
+            try { fail({\"code\": 7}); caught[0] = \"unreachable\"; } catch (e) { caught[0] = e; }
+            try { nil + 1; } catch (e) { caught[1] = e; }
+            try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { caught[2] = e; }",
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(
+            lox.evaluate("caught").unwrap().to_string(),
+            "[{code: 7}, Operands must be two numbers or two strings., 2]"
+        );
+
+        assert_eq!(
+            lox.run("throw \"oops\";").unwrap_err().to_string(),
+            "Uncaught exception: oops\n[line 1, column 1]"
+        );
+    }
+
+    #[test]
+    fn test_class_methods() {
+        let lox = Lox::new();
+        lox.run(
+            "class Math {
+                class square(n) { return n * n; }
+                class fourth(n) { return this.square(this.square(n)); }
+                init() { this.made = true; }
+            }
+            class More < Math {}",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("Math.square(3)").unwrap().to_string(), "9");
+        assert_eq!(lox.evaluate("More.fourth(2)").unwrap().to_string(), "16");
+        assert!(lox.evaluate("Math().square").is_err());
+        assert!(lox.evaluate("Math.made").is_err());
+    }
+
+    #[test]
+    fn test_getters() {
+        let lox = Lox::new();
+        lox.run(
+            "class Circle {
+                init(radius) { this.radius = radius; }
+                area { return 3 * this.radius ** 2; }
+                class unit { return Circle(1); }
+            }
+            class Ring < Circle { area { return super.area - 1; } }
+            var c = Circle(2);",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("c.area").unwrap().to_string(), "12");
+        assert_eq!(lox.evaluate("Ring(2).area").unwrap().to_string(), "11");
+        assert_eq!(lox.evaluate("Circle.unit.area").unwrap().to_string(), "3");
+        assert!(lox.evaluate("c.area()").is_err());
+    }
+
+    #[test]
+    fn test_mixins() {
+        let lox = Lox::new();
+        lox.run(
+            "class Walks { move() { return \"walk\"; } legs { return 2; } }
+            class Swims { move() { return \"swim\"; } fins() { return 1; } }
+            class Duck with Walks, Swims { fins() { return 0; } }",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("Duck().move()").unwrap().to_string(), "swim");
+        assert_eq!(lox.evaluate("Duck().legs").unwrap().to_string(), "2");
+        assert_eq!(lox.evaluate("Duck().fins()").unwrap().to_string(), "0");
+
+        assert!(lox.run("class A with A {}").is_err());
+        assert!(lox.run("class B with Walks, Walks {}").is_err());
+        assert!(lox.run("var n = 1; class C with n {}").is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = std::env::temp_dir().join(format!("lox_import_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("util.lox"),
+            "var loads = 0; fun twice(x) { return 2 * x; } loads = loads + 1;",
+        )
+        .unwrap();
+        std::fs::write(dir.join("a.lox"), "import \"b.lox\";").unwrap();
+        std::fs::write(dir.join("b.lox"), "import \"a.lox\";").unwrap();
+
+        let lox = Lox::new();
+        lox.interpreter().set_script_path(&dir.join("main.lox"));
+        lox.run("import \"util.lox\"; import \"util.lox\" as util;")
+            .unwrap();
+        assert_eq!(lox.evaluate("twice(21)").unwrap().to_string(), "42");
+        assert_eq!(lox.evaluate("util.twice(4)").unwrap().to_string(), "8");
+        assert_eq!(lox.evaluate("util.loads").unwrap().to_string(), "1");
+        assert_eq!(lox.evaluate("util").unwrap().to_string(), "<module util>");
+        assert!(lox
+            .evaluate("util.missing")
+            .unwrap_err()
+            .to_string()
+            .contains("Module 'util' has no member 'missing'."));
+        assert!(lox
+            .run("import \"a.lox\";")
+            .unwrap_err()
+            .to_string()
+            .contains("Import cycle through module 'a.lox'."));
+        assert!(lox.run("import \"nope.lox\";").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_search_path() {
+        let dir = std::env::temp_dir().join(format!("lox_search_path_{}", std::process::id()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(lib.join("shared.lox"), "var answer = 42;").unwrap();
+
+        let lox = Lox::new();
+        lox.interpreter().set_script_path(&dir.join("main.lox"));
+        assert!(lox.run("import \"shared.lox\";").is_err());
+
+        lox.interpreter().add_search_path(&lib);
+        lox.run("import \"shared.lox\";").unwrap();
+        assert_eq!(lox.evaluate("answer").unwrap().to_string(), "42");
+        assert!(lox.run("import \"./shared.lox\";").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_const() {
+        assert_eq!(eval("const x = 1;"), "nil");
+        assert_eq!(
+            eval("const x = 1;\nx = 2;"),
+            "error: [line 2, column 1] Error at 'x': Can't assign to a constant.\n"
+        );
+        assert!(
+            eval("{ const x = 1; fun f() { x += 1; } }").contains("Can't assign to a constant.")
+        );
+        assert!(eval("const x;").starts_with("error:"));
+        assert_eq!(eval("const x = 1; { var x = 2; x = 3; }"), "nil");
+
+        // The resolver only sees one chunk of input at a time, so a global
+        // from an earlier one is checked when the assignment runs.
+        let lox = Lox::new();
+        lox.run("const limit = 10;").unwrap();
+        assert!(lox
+            .run("limit = 11;")
+            .unwrap_err()
+            .to_string()
+            .contains("Can't assign to constant 'limit'."));
+        assert_eq!(lox.evaluate("limit").unwrap().to_string(), "10");
+        lox.run("var limit = 12; limit = 13;").unwrap();
+        assert_eq!(lox.evaluate("limit").unwrap().to_string(), "13");
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let lox = Lox::new();
+        lox.run(
+            "fun count(n, total) { if (n == 0) return total; return count(n - 1, total + 1); }
+             fun is_even(n) { if (n == 0) return true; return is_odd(n - 1); }
+             fun is_odd(n) { if (n == 0) return false; return is_even(n - 1); }",
+        )
+        .unwrap();
+        assert_eq!(
+            lox.evaluate("count(100000, 0)").unwrap().to_string(),
+            "100000"
+        );
+        assert_eq!(
+            lox.evaluate("is_even(100001)").unwrap().to_string(),
+            "false"
+        );
+
+        // A tail call inside a try block still has its errors caught.
+        lox.run("fun fail() { throw \"oops\"; } fun f() { try { return fail(); } catch (e) { return e; } }")
+            .unwrap();
+        assert_eq!(lox.evaluate("f()").unwrap().to_string(), "oops");
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        let lox = Lox::new();
+        lox.interpreter().set_max_call_depth(20);
+        lox.run("fun f(n) {\n  if (n == 0) return 0;\n  return 1 + f(n - 1);\n}")
+            .unwrap();
+        assert_eq!(lox.evaluate("f(19)").unwrap().to_string(), "19");
+
+        let error = lox.evaluate("f(20)").unwrap_err().to_string();
+        assert!(error.starts_with("Stack overflow.\n[line 3, column 21]\n[line 3] in f()\n"));
+        assert!(error.ends_with("[line 3] in f()\n... 10 more\n[line 1] in script"));
+
+        // Tail calls don't count towards the limit, and an overflow leaves
+        // nothing behind.
+        lox.run("fun g(n) { if (n == 0) return 0; return g(n - 1); }")
+            .unwrap();
+        assert_eq!(lox.evaluate("g(1000)").unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn test_optimize() {
+        let lox = Lox::new();
+        lox.interpreter().set_optimize(true);
+        assert_eq!(lox.evaluate("1 + 2 * 3").unwrap().to_string(), "7");
+        assert_eq!(lox.evaluate("\"a\" + \"b\"").unwrap().to_string(), "ab");
+        lox.run("var x = 4;").unwrap();
+        assert_eq!(lox.evaluate("x * (2 + 3)").unwrap().to_string(), "20");
+        assert!(lox
+            .evaluate("1 + nil")
+            .unwrap_err()
+            .to_string()
+            .contains("Operands must be two numbers or two strings."));
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        assert_eq!(
+            warnings("fun f() {\n  return 1;\n  print 2;\n}"),
+            ["[line 2, column 3] Warning: Code after 'return' is unreachable."]
+        );
+        assert_eq!(
+            warnings("while (true) { break; print 1; print 2; }"),
+            ["[line 1, column 16] Warning: Code after 'break' is unreachable."]
+        );
+        assert!(warnings("fun f(x) { if (x) return 1; return 2; }").is_empty());
+
+        // Warnings don't stop the code from running.
+        let lox = Lox::new();
+        lox.run("fun f() { return 1; print 2; }").unwrap();
+        assert_eq!(lox.evaluate("f()").unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn test_unused_locals() {
+        assert_eq!(
+            warnings("{\n  var x = 1;\n  fun f() {}\n  var _y = 2;\n}"),
+            [
+                "[line 2, column 7] Warning: local variable 'x' is never used.",
+                "[line 3, column 7] Warning: local function 'f' is never used.",
+            ]
+        );
+        assert_eq!(
+            warnings("fun f() { var x = 1; x = 2; }"),
+            ["[line 1, column 15] Warning: local variable 'x' is never used."]
+        );
+        assert!(
+            warnings("var g = 1; fun f(a) { var x = a; return fun () { return x; }; }").is_empty()
+        );
+    }
+
+    #[test]
+    fn test_timings() {
+        let lox = Lox::new();
+        assert_eq!(lox.timings().total(), Duration::ZERO);
+        lox.run("var a = 0; while (a < 100) a = a + 1;").unwrap();
+        let timings = lox.timings();
+        assert!(timings.interpret > Duration::ZERO);
+        assert_eq!(
+            timings.total(),
+            timings.scan + timings.parse + timings.optimize + timings.resolve + timings.interpret
+        );
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        // Nesting as deep as is allowed needs more stack than a test thread
+        // has in a debug build.
+        thread::Builder::new()
+            .stack_size(FUZZ_STACK_SIZE)
+            .spawn(deep_nesting)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn deep_nesting() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(250)), "1");
+        let blocks = format!("{}print 1;{}", "{".repeat(250), "}".repeat(250));
+        assert_eq!(eval(&blocks), "nil");
+        assert!(eval(&nested(10_000)).contains("Too much nesting."));
+        assert!(eval(&format!("{}1", "-".repeat(10_000))).contains("Too much nesting."));
+        assert!(eval(&"{".repeat(10_000)).contains("Too much nesting."));
+        assert!(eval(&"if (true) ".repeat(10_000)).contains("Too much nesting."));
+
+        // Left-associative operators and call chains build trees as deep as
+        // they are long, even without any parentheses.
+        let sum = |terms: usize| vec!["1"; terms].join(" + ");
+        assert_eq!(eval(&sum(200)), "200");
+        assert!(eval(&sum(5_000)).contains("Too much nesting."));
+        assert!(eval(&vec!["true"; 5_000].join(" and ")).contains("Too much nesting."));
+        assert!(eval(&format!("clock{}", "()".repeat(5_000))).contains("Too much nesting."));
+        assert!(eval(&format!("a{}", ".b".repeat(5_000))).contains("Too much nesting."));
+        assert!(eval(&format!("a{}", "[0]".repeat(5_000))).contains("Too much nesting."));
+    }
+
+    #[test]
+    fn test_fuzz_run() {
+        for source in [
+            "",
+            "(",
+            ")",
+            "}",
+            "1.",
+            ".5",
+            "é",
+            "\"\\u{110000}\"",
+            "\"\\u{",
+            "/*",
+            "fun f() { return 1 + f(); } f();",
+            "class A < A {}",
+            "var a = [1]; a[-1] = a[1.5];",
+            "for (x in 1) {}",
+            "import \"\";",
+            "import \"Cargo.toml\";",
+            "while (readLine() != nil) print clock();",
+        ] {
+            fuzz_run(source);
+        }
+    }
+
+    #[test]
+    fn test_execution_hook() {
+        use interpreter::ExecutionHook;
+        use scanner::Token;
+        use std::cell::RefCell;
+
+        /// Records the line of each statement and the value of `n` there,
+        /// and each call and return at line 0.
+        #[derive(Default)]
+        struct Recorder(RefCell<Vec<(usize, String)>>);
+
+        impl<'a> ExecutionHook<'a> for Recorder {
+            fn before_statement(&self, interpreter: &Interpreter<'a>, token: &Token<'a>) {
+                let line = token.line;
+                let mut scanner = Scanner::new("n");
+                let parser = Parser::new(scanner.scan_tokens().unwrap());
+                let expr = match parser.parse_repl().unwrap() {
+                    parser::ReplInput::Expression(expr) => expr,
+                    parser::ReplInput::Statements(_) => unreachable!(),
+                };
+                let n = interpreter
+                    .evaluate_in_scope(&Rc::new(parser.into_ast()), expr)
+                    .map_or_else(|e| e.message, |n| n.to_string());
+                self.0.borrow_mut().push((line, n));
+            }
+
+            fn on_call(&self, interpreter: &Interpreter<'a>, name: &str, arguments: &[Value<'a>]) {
+                let call = format!(
+                    "call {}({}) at depth {}",
+                    name,
+                    arguments[0],
+                    interpreter.call_depth()
+                );
+                self.0.borrow_mut().push((0, call));
+            }
+
+            fn on_return(
+                &self,
+                _interpreter: &Interpreter<'a>,
+                name: &str,
+                value: Option<&Value<'a>>,
+            ) {
+                let value = value.map_or("nothing".to_string(), Value::to_string);
+                self.0
+                    .borrow_mut()
+                    .push((0, format!("return {} from {}", value, name)));
+            }
+        }
+
+        let recorder = Rc::new(Recorder::default());
+        let lox = Lox::new();
+        lox.interpreter().set_hook(recorder.clone());
+        lox.run("fun f(n) {\n  return n;\n}\nvar i = 0;\nwhile (i < 1) i = f(i + 1);")
+            .unwrap();
+
+        let lines: Vec<_> = recorder.0.borrow().iter().cloned().collect();
+        let undefined = "Undefined variable 'n'.".to_string();
+        assert_eq!(
+            lines,
+            vec![
+                (1, undefined.clone()),
+                (4, undefined.clone()),
+                (5, undefined.clone()),
+                (5, undefined.clone()),
+                (0, "call f(1) at depth 1".to_string()),
+                (2, "1".to_string()),
+                (0, "return 1 from f".to_string()),
+                (5, undefined),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_args() {
+        assert_eq!(eval("ARGS"), "[]");
+        let lox = Lox::new();
+        lox.interpreter()
+            .set_args(&["a".to_string(), "b c".to_string()]);
+        assert_eq!(lox.evaluate("ARGS[1]").unwrap().to_string(), "b c");
+    }
+
+    #[test]
+    fn test_exit() {
+        let exit_code = |source| {
+            let error = Lox::new().run(source).unwrap_err();
+            error.downcast_ref::<RuntimeError>().and_then(|e| e.exit)
+        };
+        assert_eq!(exit_code("exit(3);"), Some(3));
+        assert_eq!(
+            exit_code("fun f() { try { exit(0); } catch (e) { print e; } } f();"),
+            Some(0)
+        );
+        assert_eq!(exit_code("exit(1.5);"), None);
+        assert_eq!(exit_code("exit(255);"), Some(255));
+        assert_eq!(exit_code("exit(256);"), None);
+        assert_eq!(exit_code("exit(-1);"), None);
+        assert!(eval("exit(\"a\")").contains("Exit code must be an integer from 0 to 255."));
+    }
+
+    #[test]
+    fn test_random() {
+        let run = |seed| {
+            let lox = Lox::new();
+            lox.interpreter().set_seed(seed);
+            lox.run("var r = random(); var i = randomInt(1, 6);")
+                .unwrap();
+            lox.evaluate("[r >= 0 and r < 1, i >= 1 and i <= 6, r, i]")
+                .unwrap()
+                .to_string()
+        };
+        assert!(run(7).starts_with("[true, true, "));
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+        assert_eq!(eval("randomInt(3, 3)"), "3");
+        assert!(eval("randomInt(1.5, 3)").contains("Bounds must be integers."));
+        assert!(eval("randomInt(4, 3)").contains("Lower bound is above the upper bound."));
+    }
+
+    #[test]
+    fn test_string_natives() {
+        assert_eq!(eval("len(\"héllo\")"), "5");
+        assert_eq!(eval("len([1, 2])"), "2");
+        assert_eq!(eval("substring(\"héllo\", 1, 3)"), "él");
+        assert_eq!(eval("substring(\"abc\", 3, 3)"), "");
+        assert!(eval("substring(\"abc\", 1, 4)")
+            .contains("Substring from 1 to 4 is out of bounds for a string of length 3."));
+        assert!(eval("substring(\"abc\", 2, 1)").contains("out of bounds"));
+        assert_eq!(eval("toUpper(\"abc\") + toLower(\"DEF\")"), "ABCdef");
+        assert_eq!(eval("indexOf(\"héllo\", \"l\")"), "2");
+        assert_eq!(eval("indexOf(\"abc\", \"z\")"), "-1");
+        assert_eq!(eval("split(\"a,b,,c\", \",\")"), "[a, b, , c]");
+        assert_eq!(eval("split(\"ab\", \"\")"), "[a, b]");
+        assert_eq!(eval("trim(\"  a b \\n\")"), "a b");
+        assert!(eval("toUpper(1)").contains("Expected a string."));
+    }
+
+    #[test]
+    fn test_type() {
+        let lox = Lox::new();
+        lox.run("class A {}").unwrap();
+        let types = lox.evaluate(
+            "[type(1), type(\"a\"), type(true), type(nil), type(clock), type(fun () {}), type(A), type(A()), type([]), type({})]",
+        );
+        let types = types.unwrap().to_string();
+        assert_eq!(
+            types,
+            "[number, string, boolean, nil, function, function, class, instance, list, map]"
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(
+            eval("str(1.5) + str(nil) + str([1, \"a\"])"),
+            "1.5nil[1, a]"
+        );
+        assert_eq!(
+            eval("num(\" 12.5 \") + num(\"-2\") + num(\"1e2\")"),
+            "110.5"
+        );
+        assert_eq!(eval("num(3)"), "3");
+        for source in [
+            "num(\"\")",
+            "num(\"abc\")",
+            "num(\"inf\")",
+            "num(\"1.2.3\")",
+            "num(nil)",
+        ] {
+            assert_eq!(eval(source), "nil", "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_eq!(eval("assert(1 < 2, \"math\")"), "nil");
+        assert_eq!(
+            eval("var x = 1;\nassert(x == 2, \"x is \" + str(x));"),
+            "error: Assertion failed: x is 1\n[line 2, column 32]"
+        );
+        assert!(eval("assert(nil, 3)").contains("Assertion failed: 3"));
+    }
+
+    #[test]
+    fn test_write() {
+        let host = Rc::new(CaptureHost::new());
+        let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+        lox.run("write(\"[\"); for (i in [1, 2]) write(i); write(\"]\"); print \"!\";")
+            .unwrap();
+        assert_eq!(host.take_stdout(), "[12]!\n");
+    }
+
+    #[test]
+    fn test_input_and_output() {
+        let host = Rc::new(CaptureHost::new());
+        host.push_input("first\r\nsecond");
+        let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+        lox.run("print readLine(); write(readLine()); print readLine();")
+            .unwrap();
+        assert_eq!(host.take_stdout(), "first\nsecondnil\n");
+    }
+
+    #[test]
+    fn test_with_input() {
+        let lox = Lox::with_interpreter(Interpreter::with_input("first\r\nsecond".as_bytes()));
+        lox.run("var a = readLine(); var b = readLine(); var c = readLine();")
+            .unwrap();
+        assert_eq!(
+            lox.evaluate("[a, b, c]").unwrap().to_string(),
+            "[first, second, nil]"
+        );
+    }
+
+    #[test]
+    fn test_number_formatting() {
+        assert_eq!(eval("4 / 2"), "2");
+        assert_eq!(eval("5 / 2"), "2.5");
+        assert_eq!(eval("-0"), "-0");
+        assert_eq!(eval("\"n = \" + str(10 * 3)"), "n = 30");
+        assert_eq!(interpreter::format_number(f64::NAN), "NaN");
+        assert_eq!(interpreter::format_number(f64::INFINITY), "Infinity");
+        assert_eq!(interpreter::format_number(-f64::INFINITY), "-Infinity");
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        assert_eq!(eval("0 / 4"), "0");
+        assert_eq!(
+            eval("1 +\n  2 / 0"),
+            "error: Division by zero.\n[line 2, column 5]"
+        );
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let covered = |source| {
+            let error = Lox::new().run(source).unwrap_err();
+            let error = error.downcast_ref::<RuntimeError>().unwrap();
+            &source[error.start..error.end]
+        };
+        assert_eq!(covered("var a = 1; print (a + nil) * 2;"), "a + nil");
+        assert_eq!(covered("print -\"a\";"), "-\"a\"");
+        assert_eq!(covered("fun f(x) {} f(1, 2);"), "f(1, 2)");
+        assert_eq!(covered("fun f() { return \"s\"(); } f();"), "\"s\"()");
+        assert_eq!(covered("print undefined;"), "undefined");
+    }
+
+    #[test]
+    fn test_operand_errors() {
+        assert_eq!(
+            eval("\"a\" < \"b\""),
+            "error: Operands must be numbers.\n[line 1, column 5]"
+        );
+        assert_eq!(
+            eval("true + 1"),
+            "error: Operands must be two numbers or two strings.\n[line 1, column 6]"
+        );
+        assert_eq!(
+            eval("2 *\n  nil"),
+            "error: Operands must be numbers.\n[line 1, column 3]"
+        );
+        assert_eq!(
+            eval("-\"a\""),
+            "error: Operand must be a number.\n[line 1, column 1]"
+        );
+    }
+
+    #[test]
+    fn test_equality() {
+        assert_eq!(eval("nil == nil"), "true");
+        assert_eq!(eval("\"a\" + \"b\" == \"ab\""), "true");
+        assert_eq!(eval("\"a\" != \"b\""), "true");
+        assert_eq!(eval("1 == \"1\""), "false");
+        assert_eq!(eval("nil != false"), "true");
+        assert_eq!(eval("0 == -0"), "true");
+        assert_eq!(eval("[1] == [1]"), "false");
+        let lox = Lox::new();
+        lox.run("var l = [1]; fun f() {} class C {}").unwrap();
+        assert_eq!(
+            lox.evaluate("l == l and f == f and C == C and clock == clock")
+                .unwrap()
+                .to_string(),
+            "true"
+        );
+        assert_eq!(lox.evaluate("C() == C()").unwrap().to_string(), "false");
+    }
+
+    #[test]
+    fn test_truthiness() {
+        assert_eq!(eval("!nil"), "true");
+        assert_eq!(eval("!0"), "false");
+        assert_eq!(eval("!\"\""), "false");
+        assert_eq!(eval("0 and \"yes\""), "yes");
+        assert_eq!(eval("false or nil"), "nil");
+        let host = Rc::new(CaptureHost::new());
+        let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+        lox.run("if (0) print \"zero\"; var l = []; while (l) { print \"list\"; l = nil; }")
+            .unwrap();
+        assert_eq!(host.take_stdout(), "zero\nlist\n");
+    }
+
+    #[test]
+    fn test_nil_coalescing() {
+        assert_eq!(eval("nil ?? 2"), "2");
+        assert_eq!(eval("false ?? 2"), "false");
+        assert_eq!(eval("nil ?? nil ?? \"c\""), "c");
+        assert_eq!(eval("false or nil ?? true"), "true");
+        assert_eq!(eval("1 ?? undefined"), "1");
+        assert_eq!(eval("{\"a\": nil}[\"a\"] ?? \"default\""), "default");
+    }
+
+    #[test]
+    fn test_optional_chaining() {
+        let lox = Lox::new();
+        lox.run(
+            "class P { init() { this.x = 1; } get() { return this.x; } } var p = P(); var n = nil;",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("p?.x").unwrap().to_string(), "1");
+        assert_eq!(lox.evaluate("p?.get()").unwrap().to_string(), "1");
+        assert_eq!(lox.evaluate("n?.x").unwrap().to_string(), "nil");
+        assert_eq!(
+            lox.evaluate("n?.get(undefined)").unwrap().to_string(),
+            "nil"
+        );
+        assert_eq!(lox.evaluate("n?.x ?? 2").unwrap().to_string(), "2");
+        assert!(lox
+            .evaluate("n.x")
+            .unwrap_err()
+            .to_string()
+            .contains("Only instances have properties."));
+        assert!(lox
+            .evaluate("1?.x")
+            .unwrap_err()
+            .to_string()
+            .contains("Only instances have properties."));
+        assert!(lox.run("n?.x = 1;").is_err());
+    }
+
+    #[test]
+    fn test_tuples() {
+        let lox = Lox::new();
+        assert_eq!(
+            lox.evaluate("(1, \"a\", nil)").unwrap().to_string(),
+            "(1, a, nil)"
+        );
+        assert_eq!(lox.evaluate("(1,)").unwrap().to_string(), "(1,)");
+        assert_eq!(
+            lox.evaluate("(1, 2) == (1, 2)").unwrap().to_string(),
+            "true"
+        );
+        assert_eq!(lox.evaluate("type((1, 2))").unwrap().to_string(), "tuple");
+
+        lox.run("fun divide(a, b) { return a / b, a - b * 2; } var (q, r) = divide(6, 2);")
+            .unwrap();
+        assert_eq!(lox.evaluate("q + r").unwrap().to_string(), "5");
+        lox.run("const (x, y) = (1, 2);").unwrap();
+        assert!(lox.run("x = 3;").is_err());
+
+        assert_eq!(
+            eval("var (a, b) = (1, 2, 3);"),
+            "error: Expected a tuple of 2 values but got 3.\n[line 1, column 5]"
+        );
+        assert!(eval("var (a, b) = [1, 2];").contains("Can only destructure a tuple."));
+    }
+
+    #[test]
+    fn test_variadics() {
+        let lox = Lox::new();
+        lox.run(
+            "fun sum(...nums) { var total = 0; for (n in nums) total += n; return total; }
+            fun tag(name, ...rest) { return name + \":\" + str(rest); }",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("sum()").unwrap().to_string(), "0");
+        assert_eq!(lox.evaluate("sum(1, 2, 3)").unwrap().to_string(), "6");
+        assert_eq!(lox.evaluate("tag(\"a\")").unwrap().to_string(), "a:[]");
+        assert_eq!(
+            lox.evaluate("tag(\"a\", 1, 2)").unwrap().to_string(),
+            "a:[1, 2]"
+        );
+        assert_eq!(
+            lox.evaluate("sum(...[1, 2], 3, ...(4, 5))")
+                .unwrap()
+                .to_string(),
+            "15"
+        );
+        assert_eq!(
+            lox.evaluate("(fun (a, b) => a - b)(...[5, 2])")
+                .unwrap()
+                .to_string(),
+            "3"
+        );
+        assert!(lox
+            .evaluate("tag()")
+            .unwrap_err()
+            .to_string()
+            .contains("Expected at least 1 arguments but got 0."));
+        assert!(lox
+            .evaluate("sum(...1)")
+            .unwrap_err()
+            .to_string()
+            .contains("Can only spread lists and tuples."));
+        assert!(lox.run("fun f(...a, b) {}").is_err());
+    }
+
+    #[test]
+    fn test_named_arguments() {
+        let lox = Lox::new();
+        lox.run(
+            "fun point(x, y) { return str(x) + \",\" + str(y); }
+            class P { init(x, y) { this.sum = x + y; } }",
+        )
+        .unwrap();
+        assert_eq!(
+            lox.evaluate("point(x: 1, y: 2)").unwrap().to_string(),
+            "1,2"
+        );
+        assert_eq!(
+            lox.evaluate("point(y: 2, x: 1)").unwrap().to_string(),
+            "1,2"
+        );
+        assert_eq!(lox.evaluate("point(1, y: 2)").unwrap().to_string(), "1,2");
+        assert_eq!(lox.evaluate("P(y: 1, x: 2).sum").unwrap().to_string(), "3");
+
+        let error = |source| lox.evaluate(source).unwrap_err().to_string();
+        assert!(error("point(x: 1, z: 2)").contains("No parameter named 'z'."));
+        assert!(error("point(1, x: 2)").contains("Argument 'x' was given more than once."));
+        assert!(error("point(y: 2)").contains("Missing argument 'x'."));
+        assert!(error("clock(x: 1)").contains("Only functions declared in Lox"));
+        assert!(error("point(x: 1, 2)").contains("Expect named argument after a named argument."));
+    }
+
+    #[test]
+    fn test_gc_frees_cycles() {
+        let lox = Lox::new();
+        lox.run(
+            "fun leak() { fun f() { return f; } }
+            class Node { init() { this.me = this; this.get = this.value; } value() { return 1; } }
+            fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }
+            var count = counter();
+            count();",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("gc()").unwrap().to_string(), "0");
+        lox.run("leak(); leak();").unwrap();
+        assert_eq!(lox.evaluate("gc()").unwrap().to_string(), "4");
+        lox.run("{ var node = Node(); }").unwrap();
+        assert_eq!(lox.evaluate("gc()").unwrap().to_string(), "3");
+        assert_eq!(lox.evaluate("gc()").unwrap().to_string(), "0");
+        assert_eq!(lox.evaluate("count()").unwrap().to_string(), "2");
+
+        // Collections also happen on their own, without calling gc().
+        lox.run("for (var i = 0; i < 5000; i = i + 1) { leak(); Node(); }")
+            .unwrap();
+        assert_eq!(lox.evaluate("gc() < 5000").unwrap().to_string(), "true");
+    }
+
+    #[test]
+    fn test_local_slots() {
+        let lox = Lox::new();
+        lox.run(
+            "fun f(a, ...rest) { var b = a * 10; { var a = b + len(rest); b = a; } return b; }
+            class A { init(n) { this.n = n; } }
+            class B < A { init(n) { var twice = n * 2; super.init(twice); } }",
+        )
+        .unwrap();
+        assert_eq!(lox.evaluate("f(1, 2, 3)").unwrap().to_string(), "12");
+        assert_eq!(lox.evaluate("f(a: 2)").unwrap().to_string(), "20");
+        assert_eq!(lox.evaluate("B(4).n").unwrap().to_string(), "8");
+        lox.run("fun g() { var x = 1; var y = 2; var (p, q) = (y, x); return p * 10 + q; }")
+            .unwrap();
+        assert_eq!(lox.evaluate("g()").unwrap().to_string(), "21");
+    }
+
+    #[test]
+    fn test_value_size() {
+        // Every variant is at most a pointer or a number, plus the tag.
+        assert_eq!(std::mem::size_of::<Value>(), 16);
+        let lox = Lox::new();
+        lox.run("var s = \"shared\"; var t = s;").unwrap();
+        match (lox.evaluate("s").unwrap(), lox.evaluate("t").unwrap()) {
+            (Value::ReturnString(s), Value::ReturnString(t)) => assert!(Rc::ptr_eq(&s, &t)),
+            other => panic!("unexpected values {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_repl_inputs() {
+        let lox = Lox::new();
+        let run = |source| lox.run_line(source, false);
+        assert!(matches!(
+            run("fun f(x) {\n  return -x;\n}"),
+            Ok(LineResult::Executed)
+        ));
+        assert!(matches!(run("print \"a\" +"), Ok(LineResult::Incomplete)));
+        assert!(is_incomplete("print \"a\" +"));
+        assert!(!is_incomplete("print \"a\";"));
+        assert!(matches!(run("print 1;"), Ok(LineResult::Executed)));
+
+        // The error is in the function declared by the first input.
+        let error = run("f(\"a\");").err().unwrap();
+        let diagnostic = diagnostic::from_error(&error).unwrap().remove(0);
+        let (diagnostic, source) = lox.locate(diagnostic);
+        assert_eq!(diagnostic.input, Some(1));
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 10));
+        assert_eq!(&source[diagnostic.start..diagnostic.end], "-x");
+
+        let error = run("var = 1;").err().unwrap();
+        let diagnostic = diagnostic::from_error(&error).unwrap().remove(0);
+        let (diagnostic, source) = lox.locate(diagnostic);
+        assert_eq!(diagnostic.input, Some(4));
+        assert_eq!(source, "var = 1;");
+        assert_eq!(diagnostic.start, 4);
+    }
+
+    #[test]
+    fn test_run_takes_statements() {
+        let lox = Lox::new();
+        // A script can't end in a bare expression, whatever comes before it.
+        assert!(lox.run("1 + 2").is_err());
+        assert!(lox.run("print 1;\n1 + 2").is_err());
+        assert!(lox.run("var a = 1 + 2;").is_ok());
+        assert_eq!(lox.evaluate("a * 2").unwrap().to_string(), "6");
+        assert!(lox.evaluate("print a;").is_err());
+        assert!(lox.evaluate("a a").is_err());
+        // Only the REPL takes either.
+        assert!(matches!(lox.run_line("a", false), Ok(LineResult::Value(_))));
+        assert!(matches!(
+            lox.run_line("print a;", false),
+            Ok(LineResult::Executed)
+        ));
+    }
+
+    #[test]
+    fn test_static_errors_together() {
+        let host = Rc::new(CaptureHost::new());
+        let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+        let error = lox
+            .run("print \"ran\";\nvar a = @;\nprint 1 +;\nreturn 3;\nbreak;")
+            .err()
+            .unwrap();
+        let codes: Vec<&str> = diagnostic::from_error(&error)
+            .unwrap()
+            .iter()
+            .map(|d| d.code)
+            .collect();
+        assert_eq!(codes, ["E0001", "E0100", "E0202", "E0210"]);
+        // Nothing runs if anything's wrong.
+        assert!(host.take_stdout().is_empty());
+    }
+
+    #[test]
+    fn test_no_static_errors_from_recovery() {
+        // Recovery resumes inside the method body, where the `return` isn't
+        // really at the top level, unlike the one after the class.
+        let error = Lox::new()
+            .run("class A { static f() { return 1; } }\nreturn 2;")
+            .err()
+            .unwrap();
+        let codes: Vec<&str> = diagnostic::from_error(&error)
+            .unwrap()
+            .iter()
+            .map(|d| d.code)
+            .collect();
+        assert_eq!(codes, ["E0101", "E0100", "E0202"]);
+    }
+
+    #[test]
+    fn test_missing_token_at_end() {
+        let source = "print 1 +\n\n";
+        let error = Lox::new().run(source).err().unwrap();
+        let diagnostics = diagnostic::from_error(&error).unwrap();
+        let rendered = diagnostics[0].render(source, false);
+        assert!(rendered.contains("--> [line 1, column 10]"), "{}", rendered);
+        assert!(
+            rendered.contains("1 | print 1 +\n  |          ^"),
+            "{}",
+            rendered
+        );
+        assert!(diagnostics[0]
+            .to_json()
+            .contains(r#""line":1,"column":10,"span":{"start":9,"end":9}"#));
+    }
+
+    #[test]
+    fn test_scan_errors_reported_once() {
+        for (source, code) in [("print \"a\\q\";", "E0004"), ("var a = @;", "E0001")] {
+            let error = Lox::new().run(source).err().unwrap();
+            let codes: Vec<&str> = diagnostic::from_error(&error)
+                .unwrap()
+                .iter()
+                .map(|d| d.code)
+                .collect();
+            assert_eq!(codes, [code], "{}", source);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Config, Editor, Helper};
+
+const MAX_HISTORY: usize = 1000;
+
+/// The REPL's line editor: rustyline, so arrow keys move through the line
+/// and through history and the usual emacs bindings (Ctrl-A, Ctrl-E, ...)
+/// work. History is persisted between sessions.
+pub struct LineEditor {
+    editor: Editor<LoxHelper, DefaultHistory>,
+    history_path: Option<PathBuf>,
+}
+
+/// What rustyline asks about the line being edited. Only highlighting is
+/// filled in.
+#[derive(Default)]
+struct LoxHelper {
+    /// Dresses up the line being edited, e.g. with colors, when it's drawn.
+    highlighter: Option<fn(&str) -> String>,
+}
+
+impl LineEditor {
+    pub fn new(history_path: Option<PathBuf>) -> rustyline::Result<Self> {
+        let config = Config::builder().max_history_size(MAX_HISTORY)?.build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(LoxHelper::default()));
+        if let Some(path) = &history_path {
+            // There's no history the first time.
+            let _ = editor.load_history(path);
+        }
+
+        Ok(LineEditor {
+            editor,
+            history_path,
+        })
+    }
+
+    /// Draws the line being edited as `highlighter` returns it. What it
+    /// returns must look the same width as the line, so only escape
+    /// sequences like colors can be added.
+    pub fn set_highlighter(&mut self, highlighter: fn(&str) -> String) {
+        self.editor.set_helper(Some(LoxHelper {
+            highlighter: Some(highlighter),
+        }));
+    }
+
+    /// The default history file, `~/.rlox_history`.
+    pub fn default_history_path() -> Option<PathBuf> {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rlox_history"))
+    }
+
+    /// Reads one line, without its trailing newline. Returns `None` at end of
+    /// input. Ctrl-C abandons the line being typed and returns an empty one.
+    pub fn readline(&mut self, prompt: &str) -> rustyline::Result<Option<String>> {
+        match self.editor.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn add_history_entry(&mut self, line: &str) {
+        let line = line.trim_end();
+        if !line.is_empty() {
+            // Only fails for history kept in a database, not in memory.
+            let _ = self.editor.add_history_entry(line);
+        }
+    }
+
+    pub fn save_history(&mut self) -> rustyline::Result<()> {
+        match &self.history_path {
+            Some(path) => self.editor.save_history(path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Highlighter for LoxHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        match self.highlighter {
+            Some(highlighter) => Cow::Owned(highlighter(line)),
+            None => Cow::Borrowed(line),
+        }
+    }
+
+    /// Any character can change how the rest of the line is colored, like a
+    /// quote starting a string.
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        self.highlighter.is_some()
+    }
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_history_is_kept() {
+        let path = std::env::temp_dir().join(format!("lox-history-{}", std::process::id()));
+        let mut editor = LineEditor::new(Some(path.clone())).unwrap();
+        editor.add_history_entry("var a = 1;\n");
+        editor.add_history_entry("  ");
+        editor.add_history_entry("print a;");
+        editor.save_history().unwrap();
+
+        // Blank lines aren't worth keeping.
+        let editor = LineEditor::new(Some(path.clone())).unwrap();
+        let lines: Vec<&str> = editor.editor.history().iter().map(String::as_str).collect();
+        assert_eq!(lines, ["var a = 1;", "print a;"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+//! A Language Server Protocol server, so editors can show the errors and
+//! warnings in Lox files as they're edited. Editors send the whole text of
+//! a document whenever it changes, and the server answers with all of its
+//! diagnostics.
+
+use std::io::{BufRead, Write};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::diagnostic::{self, Diagnostic, Severity};
+use crate::json::{self, Object, Value};
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+
+/// The JSON-RPC error code for a request the server doesn't handle.
+const METHOD_NOT_FOUND: i32 = -32601;
+
+/// Answers messages from `input` on `output` until the client says to exit
+/// or closes `input`.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    while let Some(message) = read_message(&mut input)? {
+        let message = match json::parse(&message) {
+            Some(message) => message,
+            None => continue,
+        };
+        let method = message.get("method").and_then(Value::as_str);
+        let id = message.get("id");
+        let params = message.get("params");
+        let text_document = params.and_then(|p| p.get("textDocument"));
+        let uri = text_document
+            .and_then(|d| d.get("uri"))
+            .and_then(Value::as_str);
+
+        match (method, id) {
+            (Some("initialize"), Some(id)) => {
+                let capabilities = Object::new()
+                    // Whole documents are sent on every change.
+                    .field("textDocumentSync", "1".to_string())
+                    .build();
+                let result = Object::new()
+                    .field("capabilities", capabilities)
+                    .field(
+                        "serverInfo",
+                        Object::new().field("name", json::string("lox")).build(),
+                    )
+                    .build();
+                write_message(&mut output, &response(id, result))?;
+            }
+            (Some("shutdown"), Some(id)) => {
+                write_message(&mut output, &response(id, "null".to_string()))?;
+            }
+            (Some("exit"), _) => break,
+            (Some("textDocument/didOpen"), _) => {
+                let text = text_document
+                    .and_then(|d| d.get("text"))
+                    .and_then(Value::as_str);
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    write_message(&mut output, &publish(uri, text))?;
+                }
+            }
+            (Some("textDocument/didChange"), _) => {
+                // With full syncing, the last change holds the whole text.
+                let text = params
+                    .and_then(|p| p.get("contentChanges"))
+                    .and_then(Value::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Value::as_str);
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    write_message(&mut output, &publish(uri, text))?;
+                }
+            }
+            (Some("textDocument/didClose"), _) => {
+                if let Some(uri) = uri {
+                    write_message(&mut output, &publish(uri, ""))?;
+                }
+            }
+            (Some(_), Some(id)) => {
+                let error = Object::new()
+                    .field("code", METHOD_NOT_FOUND.to_string())
+                    .field("message", json::string("Method not found."))
+                    .build();
+                let message = Object::new()
+                    .field("jsonrpc", json::string("2.0"))
+                    .field("id", id.write())
+                    .field("error", error)
+                    .build();
+                write_message(&mut output, &message)?;
+            }
+            // Other notifications don't need an answer.
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads one message's content, or `None` once the input is closed.
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .context("bad Content-Length")?,
+            );
+        }
+    }
+
+    let length = length.ok_or_else(|| anyhow!("message without a Content-Length"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    Ok(Some(String::from_utf8_lossy(&content).into_owned()))
+}
+
+fn write_message(output: &mut impl Write, content: &str) -> Result<()> {
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()?;
+    Ok(())
+}
+
+fn response(id: &Value, result: String) -> String {
+    Object::new()
+        .field("jsonrpc", json::string("2.0"))
+        .field("id", id.write())
+        .field("result", result)
+        .build()
+}
+
+/// The notification giving every diagnostic for the document at `uri`.
+fn publish(uri: &str, text: &str) -> String {
+    let diagnostics = check(text);
+    let params = Object::new()
+        .field("uri", json::string(uri))
+        .field(
+            "diagnostics",
+            json::array(diagnostics.iter().map(|d| to_lsp(d, text))),
+        )
+        .build();
+    Object::new()
+        .field("jsonrpc", json::string("2.0"))
+        .field("method", json::string("textDocument/publishDiagnostics"))
+        .field("params", params)
+        .build()
+}
+
+/// Everything wrong with `source` that can be found without running it.
+fn check(source: &str) -> Vec<Diagnostic> {
+    let mut scanner = Scanner::new(source);
+    let mut errors = vec![];
+    errors.extend(scanner.scan_tokens().err());
+    let parser = Parser::new(scanner.tokens());
+    let (statements, parse_errors) = parser.parse_all();
+    errors.extend(parse_errors.map(anyhow::Error::from));
+    let ast = parser.into_ast();
+    let mut resolver = Resolver::new(&ast);
+    errors.extend(resolver.resolve_all(&statements).err());
+
+    let mut diagnostics: Vec<Diagnostic> = resolver
+        .take_warnings()
+        .iter()
+        .map(|w| w.diagnostic())
+        .collect();
+    for error in &errors {
+        diagnostics.extend(diagnostic::from_error(error).unwrap_or_default());
+    }
+    diagnostics
+}
+
+fn to_lsp(diagnostic: &Diagnostic, source: &str) -> String {
+    let fallback = (
+        diagnostic.line.saturating_sub(1),
+        diagnostic.column.saturating_sub(1),
+    );
+    let start = position(source, diagnostic.start).unwrap_or(fallback);
+    let end = position(source, diagnostic.end.max(diagnostic.start)).unwrap_or(start);
+    let range = Object::new()
+        .field("start", position_json(start))
+        .field("end", position_json(end))
+        .build();
+
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    Object::new()
+        .field("range", range)
+        .field("severity", severity.to_string())
+        .field("code", json::string(diagnostic.code))
+        .field("source", json::string("lox"))
+        .field("message", json::string(&diagnostic.message))
+        .build()
+}
+
+/// The zero-based line and UTF-16 character of byte `offset`, which is how
+/// LSP counts positions.
+fn position(source: &str, offset: usize) -> Option<(usize, usize)> {
+    let before = source.get(..offset)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    Some((line, before[line_start..].encode_utf16().count()))
+}
+
+fn position_json((line, character): (usize, usize)) -> String {
+    Object::new()
+        .field("line", line.to_string())
+        .field("character", character.to_string())
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(content: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+    }
+
+    #[test]
+    fn test_serve() {
+        let input = [
+            message(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#),
+            message(
+                r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.lox","text":"var é = 1;\nprint é +;"}}}"#,
+            ),
+            message(r#"{"jsonrpc":"2.0","id":"x","method":"hover","params":{}}"#),
+            message(r#"{"jsonrpc":"2.0","method":"exit"}"#),
+        ]
+        .concat();
+
+        let mut output = vec![];
+        serve(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let messages: Vec<&str> = output
+            .split("Content-Length: ")
+            .skip(1)
+            .map(|m| &m[m.find("\r\n\r\n").unwrap() + 4..])
+            .collect();
+
+        assert_eq!(
+            messages[0],
+            r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":{"textDocumentSync":1},"serverInfo":{"name":"lox"}}}"#
+        );
+        assert_eq!(
+            messages[1],
+            concat!(
+                r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.lox","diagnostics":["#,
+                r#"{"range":{"start":{"line":1,"character":9},"end":{"line":1,"character":10}},"#,
+                r#""severity":1,"code":"E0100","source":"lox","message":"Expect expression."}]}}"#
+            )
+        );
+        assert!(messages[2].contains(r#""id":"x","error":{"code":-32601"#));
+        assert_eq!(messages.len(), 3);
+    }
+}
@@ -1,76 +1,564 @@
 use anyhow::{Context, Result};
-use interpreter::Interpreter;
-use std::{
-    cmp, env, fs,
-    io::{self, BufRead, Write},
-    process,
-};
-
-use ast::AstPrinter;
-use parser::Parser;
-use scanner::Scanner;
-
-mod ast;
-mod interpreter;
-mod parser;
-mod scanner;
+use debugger::Debugger;
+use line_editor::LineEditor;
+use rlox_treewalk::ast::AstPrinter;
+use rlox_treewalk::ast_dot::AstDotPrinter;
+use rlox_treewalk::ast_json::AstJsonPrinter;
+use rlox_treewalk::coverage::Coverage;
+use rlox_treewalk::diagnostic::{self, Format, StaticErrors};
+use rlox_treewalk::formatter;
+use rlox_treewalk::highlight;
+use rlox_treewalk::interpreter::{RuntimeError, DEFAULT_MAX_CALL_DEPTH};
+use rlox_treewalk::lsp;
+use rlox_treewalk::parser::{Parser, MAX_NESTING};
+use rlox_treewalk::profiler::Profiler;
+use rlox_treewalk::resolver::Resolver;
+use rlox_treewalk::scanner::{Scanner, Token};
+use rlox_treewalk::test_runner;
+use rlox_treewalk::{is_incomplete, LineResult, Lox};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, mem, process, thread};
+use tracer::Tracer;
+
+mod debugger;
+mod line_editor;
+mod tracer;
+
+/// The system allocator, counting allocations so `--time` can report them.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    /// Counts as an allocation of however much the block grew, since
+    /// growing a `Vec` or `String` is what most reallocations are.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A generous estimate of the Rust stack one Lox call uses, in a debug build.
+const STACK_PER_CALL: usize = 64 * 1024;
+
+/// The same for one level of nesting in the source, which every pass over
+/// it recurses through.
+const STACK_PER_NESTING: usize = 32 * 1024;
+
+/// The most stack the interpreter's thread is given, which limits how deep
+/// `--max-depth` can go.
+const MAX_STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+/// The script path that means to read the script from stdin instead.
+const STDIN: &str = "-";
+
+/// What the command line asked for.
+struct Options {
+    search_path: Vec<PathBuf>,
+    max_call_depth: usize,
+    optimize: bool,
+    /// Seeds the random numbers, to make a run repeatable.
+    seed: Option<u64>,
+    diagnostic_format: Format,
+    /// Print this instead of running the script.
+    emit: Option<Emit>,
+    /// Only look for static errors in the script, without running it.
+    check: bool,
+    /// Print the syntax tree of a script that's being checked.
+    print_ast: bool,
+    /// Report how long each phase of running the script took.
+    time: bool,
+    /// Run the script under the debugger.
+    debug: bool,
+    /// Print each statement, call and return as the script runs.
+    trace: bool,
+    /// Report how often each function was called and how long it took.
+    profile: bool,
+    /// Report which lines of the script ran.
+    coverage: bool,
+    /// Where to write which lines of the script ran, as an lcov tracefile.
+    lcov: Option<PathBuf>,
+    script: Option<String>,
+    /// The arguments after the script, which are passed on to it.
+    script_args: Vec<String>,
+}
+
+/// Something about a script that can be printed instead of running it.
+#[derive(Clone, Copy)]
+enum Emit {
+    /// Each token the scanner finds, one per line.
+    Tokens,
+    /// The syntax tree, as JSON.
+    AstJson,
+    /// The syntax tree, as a Graphviz graph.
+    Dot,
+}
 
 fn main() -> Result<()> {
-    let args = env::args().collect::<Vec<_>>();
-    match args.len().cmp(&2) {
-        cmp::Ordering::Greater => {
-            println!("Usage: jlox [script]");
-            process::exit(64);
+    let mut options = Options {
+        search_path: Vec::new(),
+        max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        optimize: false,
+        seed: None,
+        diagnostic_format: Format::Human,
+        emit: None,
+        check: false,
+        print_ast: false,
+        time: false,
+        debug: false,
+        trace: false,
+        profile: false,
+        coverage: false,
+        lcov: None,
+        script: None,
+        script_args: Vec::new(),
+    };
+
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("fmt") {
+        args.next();
+        return format_files(args.collect());
+    }
+    if args.peek().map(String::as_str) == Some("lsp") {
+        args.next();
+        if args.next().is_some() {
+            usage();
+        }
+        return lsp::serve(io::stdin().lock(), io::stdout().lock());
+    }
+    if args.peek().map(String::as_str) == Some("test") {
+        args.next();
+        let coverage = args.peek().map(String::as_str) == Some("--coverage");
+        if coverage {
+            args.next();
         }
-        cmp::Ordering::Equal => {
-            run_file(&args[1])?;
+        return match (args.next(), args.next()) {
+            (Some(dir), None) if !test_runner::run(Path::new(&dir), coverage)? => process::exit(1),
+            (Some(_), None) => Ok(()),
+            _ => usage(),
+        };
+    }
+    if args.peek().map(String::as_str) == Some("debug") {
+        args.next();
+        options.debug = true;
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" => match args.next() {
+                Some(paths) => options.search_path.extend(env::split_paths(&paths)),
+                None => usage(),
+            },
+            "--max-depth" => match args.next().and_then(|depth| depth.parse().ok()) {
+                Some(depth) if stack_size(depth).is_some() => options.max_call_depth = depth,
+                _ => usage(),
+            },
+            "--optimize" => options.optimize = true,
+            "--seed" => match args.next().and_then(|seed| seed.parse().ok()) {
+                Some(seed) => options.seed = Some(seed),
+                None => usage(),
+            },
+            "--diagnostics=human" => options.diagnostic_format = Format::Human,
+            "--diagnostics=json" => options.diagnostic_format = Format::Json,
+            "--check" => options.check = true,
+            "--ast" => {
+                options.check = true;
+                options.print_ast = true;
+            }
+            "--time" => options.time = true,
+            "--trace" => options.trace = true,
+            "--profile" => options.profile = true,
+            "--coverage" => options.coverage = true,
+            "--lcov" => match args.next() {
+                Some(path) => options.lcov = Some(PathBuf::from(path)),
+                None => usage(),
+            },
+            "--tokens" | "--emit=tokens" => options.emit = Some(Emit::Tokens),
+            "--emit=ast-json" => options.emit = Some(Emit::AstJson),
+            "--emit=dot" => options.emit = Some(Emit::Dot),
+            _ if options.script.is_none() => {
+                let script = if arg == "--stdin" {
+                    STDIN.to_string()
+                } else {
+                    arg
+                };
+                options.script = Some(script);
+                // Everything after the script is for the script.
+                options.script_args = args.by_ref().collect();
+            }
+            _ => usage(),
         }
-        _ => {
-            run_prompt()?;
+    }
+
+    // Only one of these can watch the script at a time.
+    let coverage = options.coverage || options.lcov.is_some();
+    let hooks = [options.debug, options.trace, options.profile, coverage];
+    let hooks = hooks.iter().filter(|&&hook| hook).count();
+    // The debugger takes its commands from stdin.
+    if options.debug && options.script.as_deref() == Some(STDIN) {
+        usage();
+    }
+    if hooks > 1
+        || hooks == 1 && (options.script.is_none() || options.check || options.emit.is_some())
+    {
+        usage();
+    }
+
+    // Directories given on the command line are searched before LOX_PATH.
+    if let Some(paths) = env::var_os("LOX_PATH") {
+        options.search_path.extend(env::split_paths(&paths));
+    }
+
+    let stack_size = stack_size(options.max_call_depth).unwrap_or_else(|| usage());
+    thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || run(options))?
+        .join()
+        .unwrap_or_else(|_| process::exit(101))
+}
+
+/// Deep recursion should end in Lox's own stack overflow error rather than
+/// the Rust one, so the interpreter runs on a thread with room for the
+/// deepest allowed call and the deepest allowed nesting. That's this much
+/// stack, or `None` if it's more than [`MAX_STACK_SIZE`].
+fn stack_size(max_call_depth: usize) -> Option<usize> {
+    max_call_depth
+        .checked_add(1)?
+        .checked_mul(STACK_PER_CALL)?
+        .checked_add(MAX_NESTING * STACK_PER_NESTING)
+        .filter(|&size| size <= MAX_STACK_SIZE)
+}
+
+fn run(options: Options) -> Result<()> {
+    let lox = Lox::new();
+    lox.interpreter().set_max_call_depth(options.max_call_depth);
+    lox.interpreter().set_optimize(options.optimize);
+    if let Some(seed) = options.seed {
+        lox.interpreter().set_seed(seed);
+    }
+    lox.interpreter()
+        .set_diagnostic_format(options.diagnostic_format);
+    for directory in &options.search_path {
+        lox.interpreter().add_search_path(directory);
+    }
+
+    match (&options.script, options.emit) {
+        (Some(path), None) if options.check => {
+            check_file(path, options.print_ast, options.diagnostic_format)
         }
+        (Some(path), Some(emit)) => emit_file(path, emit, options.diagnostic_format),
+        (Some(path), None) => run_file(lox, path, &options),
+        (None, Some(_)) => usage(),
+        (None, None) if options.check => usage(),
+        (None, None) => run_prompt(lox, options.diagnostic_format),
     }
+}
 
-    Ok(())
+fn usage() -> ! {
+    println!("Usage: jlox [--path dirs] [--max-depth n] [--optimize] [--seed n] [--time] [--trace] [--profile] [--coverage] [--lcov file] [--diagnostics=human|json] [--check] [--ast] [--tokens] [--emit=ast-json|dot] [script | - | --stdin] [args...]");
+    println!("       jlox fmt [--check] files...");
+    println!("       jlox debug [--path dirs] [--max-depth n] script [args...]");
+    println!("       jlox test [--coverage] dir");
+    println!("       jlox lsp");
+    process::exit(64);
 }
 
-fn run_file(path: &str) -> Result<()> {
-    let s = fs::read_to_string(path).context("couldn't read input file")?;
-    run(&s)
+/// Reads the script at `path`, or from stdin if the path is [`STDIN`].
+fn read_script(path: &str) -> Result<String> {
+    if path == STDIN {
+        let mut source = String::new();
+        io::stdin()
+            .read_to_string(&mut source)
+            .context("couldn't read the script from stdin")?;
+        return Ok(source);
+    }
+
+    fs::read_to_string(path).context("couldn't read input file")
 }
 
-fn run(source: &str) -> Result<()> {
-    let mut scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens()?;
+fn run_file(lox: Lox<'static>, path: &str, options: &Options) -> Result<()> {
+    // Leaked so that it outlives `lox`, which borrows from it.
+    let s: &'static str = Box::leak(read_script(path)?.into_boxed_str());
+    // A script from stdin imports modules relative to the working directory.
+    if path != STDIN {
+        lox.interpreter().set_script_path(Path::new(path));
+    }
+    lox.interpreter().set_args(&options.script_args);
+    if options.debug {
+        lox.interpreter().set_hook(Rc::new(Debugger::new(s)));
+    } else if options.trace {
+        lox.interpreter().set_hook(Rc::new(Tracer::new(s)));
+    }
+    let profiler = Rc::new(Profiler::new());
+    if options.profile {
+        lox.interpreter().set_hook(profiler.clone());
+    }
+    let coverage = if options.coverage || options.lcov.is_some() {
+        // A script that doesn't parse has its errors reported by running it.
+        Coverage::new(s).ok().map(Rc::new)
+    } else {
+        None
+    };
+    if let Some(coverage) = &coverage {
+        lox.interpreter().set_hook(coverage.clone());
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let result = lox.run(s);
+    if options.time {
+        print_timings(
+            &lox,
+            ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+            ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes,
+        );
+    }
+    if options.profile {
+        eprint!("{}", profiler.report());
+    }
+    if let Some(coverage) = &coverage {
+        if options.coverage {
+            eprint!("{}", coverage.report(path));
+        }
+        if let Some(lcov) = &options.lcov {
+            fs::write(lcov, coverage.lcov(path))
+                .with_context(|| format!("couldn't write {}", lcov.display()))?;
+        }
+    }
+
+    if let Err(e) = result {
+        if let Some(code) = exit_code(&e) {
+            process::exit(code);
+        }
+        if diagnostic::from_error(&e).is_none() {
+            return Err(e);
+        }
 
-    let parser = Parser::new(tokens);
-    let expr = parser.parse()?;
-    Interpreter.interpret(&expr)?;
+        // Like jlox, 70 for a failure while running and 65 for mistakes
+        // found before it started.
+        diagnostic::report(&e, s, options.diagnostic_format);
+        if e.downcast_ref::<RuntimeError>().is_some() {
+            process::exit(70);
+        }
+        process::exit(65);
+    }
 
     Ok(())
 }
 
-fn run_prompt() -> Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-    let mut line = String::new();
+/// The exit code a program asked for, if `error` is it calling `exit`.
+fn exit_code(error: &anyhow::Error) -> Option<i32> {
+    error.downcast_ref::<RuntimeError>().and_then(|e| e.exit)
+}
 
-    loop {
-        line.clear();
+/// The line a statement starting at `token` is on, shown with its text if
+/// it's from `source`, the script being run.
+fn describe_line(source: &str, token: &Token) -> String {
+    match source.lines().nth(token.line.wrapping_sub(1)) {
+        Some(text) if token.is_from(source) => format!("[line {}] {}", token.line, text.trim()),
+        _ => format!("[line {}]", token.line),
+    }
+}
 
-        print!("> ");
-        stdout.flush()?;
+/// Prints how long each phase of running a script took, and how much it
+/// allocated, to stderr.
+fn print_timings(lox: &Lox, allocations: usize, allocated_bytes: usize) {
+    let timings = lox.timings();
+    for (phase, duration) in [
+        ("scan", timings.scan),
+        ("parse", timings.parse),
+        ("optimize", timings.optimize),
+        ("resolve", timings.resolve),
+        ("interpret", timings.interpret),
+        ("total", timings.total()),
+    ] {
+        eprintln!("{:<10} {:>12.3?}", phase, duration);
+    }
+    eprintln!(
+        "{:<10} {:>12} ({} bytes)",
+        "allocs", allocations, allocated_bytes
+    );
+}
 
-        stdin.lock().read_line(&mut line)?;
-        if let Err(e) = run(&line) {
-            eprint!("{}", e);
+/// Scans and parses a script as far as `emit` needs and prints it, rather
+/// than running the script.
+fn emit_file(path: &str, emit: Emit, format: Format) -> Result<()> {
+    let source = read_script(path)?;
+    let mut scanner = Scanner::new(&source);
+    let emitted = scanner.scan_tokens().and_then(|tokens| {
+        let parse = || {
+            let parser = Parser::new(tokens);
+            let statements = parser.parse()?;
+            Ok::<_, anyhow::Error>((parser.into_ast(), statements))
         };
+
+        Ok(match emit {
+            Emit::Tokens => tokens.iter().map(|t| format!("{}\n", t)).collect(),
+            Emit::AstJson => {
+                let (ast, statements) = parse()?;
+                format!("{}\n", AstJsonPrinter::new(&ast).print(&statements))
+            }
+            Emit::Dot => {
+                let (ast, statements) = parse()?;
+                AstDotPrinter::new(&ast).print(&statements)
+            }
+        })
+    });
+
+    match emitted {
+        Ok(emitted) => print!("{}", emitted),
+        Err(e) if diagnostic::from_error(&e).is_some() => {
+            diagnostic::report(&e, &source, format);
+            process::exit(65);
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Scans, parses and resolves a script without running it, reporting any
+/// errors and warnings, and exits with 65 if there were errors.
+fn check_file(path: &str, print_ast: bool, format: Format) -> Result<()> {
+    let source = read_script(path)?;
+    let mut scanner = Scanner::new(&source);
+    // Each pass runs on whatever the one before could make sense of, so
+    // every error is reported at once.
+    let mut errors = StaticErrors::default();
+    errors.0.extend(scanner.scan_tokens().err());
+    let parser = Parser::new(scanner.tokens());
+    let (statements, parse_errors) = parser.parse_all();
+    errors.0.extend(parse_errors.map(anyhow::Error::from));
+    let ast = parser.into_ast();
+    if print_ast {
+        print!("{}", AstPrinter::new(&ast).print_program(&statements));
+    }
+
+    let mut resolver = Resolver::new(&ast);
+    errors.0.extend(resolver.resolve_all(&statements).err());
+    for warning in resolver.take_warnings() {
+        diagnostic::emit(&warning.diagnostic(), &source, format);
+    }
+    if !errors.0.is_empty() {
+        diagnostic::report(&errors.into(), &source, format);
+        process::exit(65);
     }
+    Ok(())
 }
 
-fn error(line: usize, message: &str) {
-    report(line, "", message);
+/// `jlox fmt`: rewrites each file in the canonical layout or, with
+/// `--check`, prints how each would change and exits with 1 if any would.
+fn format_files(args: Vec<String>) -> Result<()> {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() || paths.iter().any(|path| path.starts_with("--")) {
+        usage();
+    }
+
+    let mut unformatted = false;
+    for path in paths {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read input file {}", path))?;
+        let formatted = match formatter::format(&source) {
+            Ok(formatted) => formatted,
+            Err(e) if diagnostic::from_error(&e).is_some() => {
+                diagnostic::report(&e, &source, Format::Human);
+                process::exit(65);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if formatted == source {
+            continue;
+        }
+        if check {
+            unformatted = true;
+            print!("--- {}\n{}", path, formatter::diff(&source, &formatted));
+        } else {
+            fs::write(path, formatted)
+                .with_context(|| format!("couldn't write output file {}", path))?;
+        }
+    }
+
+    if unformatted {
+        process::exit(1);
+    }
+    Ok(())
 }
 
-fn report(line: usize, at: &str, message: &str) {
-    eprintln!("[line {}] Error {}: {}", line, at, message);
+fn run_prompt(lox: Lox<'static>, format: Format) -> Result<()> {
+    // Only what's typed at a terminal is worth remembering, not input piped
+    // in from somewhere else.
+    let history_path = if io::stdin().is_terminal() {
+        LineEditor::default_history_path()
+    } else {
+        None
+    };
+    let mut editor = LineEditor::new(history_path)?;
+    if io::stdout().is_terminal() {
+        editor.set_highlighter(highlight::highlight);
+    }
+    let mut pending = String::new();
+
+    loop {
+        let prompt = if pending.is_empty() { "> " } else { ".. " };
+        let line = match editor.readline(prompt)? {
+            Some(line) => line,
+            None => break,
+        };
+        editor.add_history_entry(&line);
+
+        // A blank continuation line gives up waiting for the rest of the
+        // input and reports whatever is wrong with it.
+        let force = !pending.is_empty() && line.trim().is_empty();
+        pending.push_str(&line);
+        pending.push('\n');
+
+        if !force && is_incomplete(&pending) {
+            continue;
+        }
+
+        // Functions and classes declared here point back into the source
+        // text, so it has to live for as long as the interpreter does. Only
+        // whole inputs get here, so each line is kept once. That's already
+        // been checked, so it's run as is rather than checked again.
+        let source: &'static str = Box::leak(mem::take(&mut pending).into_boxed_str());
+        match lox.run_line(source, true) {
+            Ok(LineResult::Value(value)) => println!("{}", value),
+            Ok(_) => {}
+            Err(e) => {
+                if let Some(code) = exit_code(&e) {
+                    editor.save_history()?;
+                    process::exit(code);
+                }
+                lox.report(&e, format);
+            }
+        }
+    }
+
+    editor.save_history()?;
+    Ok(())
 }
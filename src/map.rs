@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+use crate::interpreter::Types;
+
+/// A key in a Lox map. Only strings and numbers can be keys; numbers are
+/// compared by their bits, with `-0` folded into `0`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Number(u64),
+    String(Rc<String>),
+}
+
+impl MapKey {
+    /// Turns a value into a key, or returns `None` if it can't be one.
+    pub fn from_value(value: &Types) -> Option<MapKey> {
+        match value {
+            Types::Number(n) if *n == 0.0 => Some(MapKey::Number(0f64.to_bits())),
+            Types::Number(n) => Some(MapKey::Number(n.to_bits())),
+            Types::ReturnString(s) => Some(MapKey::String(s.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_value<'a>(&self) -> Types<'a> {
+        match self {
+            MapKey::Number(bits) => Types::Number(f64::from_bits(*bits)),
+            MapKey::String(s) => Types::ReturnString(s.clone()),
+        }
+    }
+}
+
+impl Display for MapKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_value())
+    }
+}
+
+/// The contents of a Lox map, which iterates in insertion order.
+#[derive(Debug, Default)]
+pub struct LoxMap<'a> {
+    entries: Vec<(MapKey, Types<'a>)>,
+    /// Position of each key in `entries`.
+    positions: HashMap<MapKey, usize>,
+}
+
+impl<'a> LoxMap<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&Types<'a>> {
+        self.positions.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: Types<'a>) {
+        match self.positions.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// A snapshot of the keys, so the map can change while they're used.
+    pub fn keys(&self) -> Vec<MapKey> {
+        self.entries.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &Types<'a>)> + '_ {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
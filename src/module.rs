@@ -0,0 +1,47 @@
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+use crate::diagnostic::codes;
+use crate::environment::Environment;
+use crate::interpreter::{RuntimeError, RuntimeResult, Types};
+use crate::scanner::Token;
+
+/// A loaded module bound to a name with `import "..." as name;`. Its members
+/// are the module's top-level declarations.
+pub struct LoxModule<'a> {
+    name: String,
+    environment: Rc<RefCell<Environment<'a>>>,
+}
+
+impl<'a> LoxModule<'a> {
+    pub fn new(name: &str, environment: Rc<RefCell<Environment<'a>>>) -> Self {
+        LoxModule {
+            name: name.to_string(),
+            environment,
+        }
+    }
+
+    pub(crate) fn environment(&self) -> &Rc<RefCell<Environment<'a>>> {
+        &self.environment
+    }
+
+    pub fn get(&self, name: &Token) -> RuntimeResult<Types<'a>> {
+        self.environment
+            .borrow()
+            .get_own(name.symbol)
+            .ok_or_else(|| {
+                RuntimeError::new(
+                    name,
+                    codes::UNDEFINED_MEMBER,
+                    &format!("Module '{}' has no member '{}'.", self.name, name.lexeme),
+                )
+            })
+    }
+}
+
+impl Debug for LoxModule<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
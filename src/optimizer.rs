@@ -0,0 +1,117 @@
+use std::borrow::Cow;
+
+use crate::ast::{Ast, ExprId, Expression};
+use crate::interpreter::{Interpreter, Types};
+use crate::scanner::{Token, TokenType};
+
+/// Replaces each expression built only out of literals, like `1 + 2 * 3` or
+/// `!true`, with the literal it evaluates to. Operators are applied by the
+/// interpreter's own rules, and an expression that would fail at runtime is
+/// left for the runtime to report.
+pub fn fold_constants(ast: &mut Ast) {
+    // Operands come before the expressions using them, so they've already
+    // been folded by the time those expressions are reached.
+    for id in ast.ids() {
+        if let Some(token) = fold(ast, &ast[id]) {
+            ast.replace(id, Expression::Literal { token });
+        }
+    }
+}
+
+/// The literal `expr` folds to, if it's made of literals.
+fn fold<'a>(ast: &Ast<'a>, expr: &Expression<'a>) -> Option<Token<'a>> {
+    match expr {
+        Expression::Grouping { expr } => match &ast[*expr] {
+            Expression::Literal { token } => Some(token.clone()),
+            _ => None,
+        },
+        Expression::Unary { operator, r_expr } => {
+            let right = literal_value(ast, *r_expr)?;
+            literal_token(operator, Interpreter::unary(operator, right).ok()?)
+        }
+        Expression::Binary {
+            l_expr,
+            operator,
+            r_expr,
+        } => {
+            let left = literal_value(ast, *l_expr)?;
+            let right = literal_value(ast, *r_expr)?;
+            literal_token(operator, Interpreter::binary(operator, left, right).ok()?)
+        }
+        _ => None,
+    }
+}
+
+fn literal_value<'a>(ast: &Ast<'a>, id: ExprId) -> Option<Types<'a>> {
+    match &ast[id] {
+        Expression::Literal { token } => Interpreter::literal(token).ok(),
+        _ => None,
+    }
+}
+
+/// A literal token for `value`, placed at the operator that produced it.
+fn literal_token<'a>(operator: &Token<'a>, value: Types<'a>) -> Option<Token<'a>> {
+    let token_type = match value {
+        Types::Number(number) => TokenType::Number { number },
+        Types::ReturnString(s) => TokenType::StringLiteral {
+            literal: Cow::Owned(s.to_string()),
+        },
+        Types::Boolean(true) => TokenType::True,
+        Types::Boolean(false) => TokenType::False,
+        Types::Nil => TokenType::Nil,
+        _ => return None,
+    };
+
+    Some(Token {
+        token_type,
+        ..operator.clone()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ast::AstPrinter;
+    use crate::parser::{Parser, ReplInput};
+    use crate::scanner::Scanner;
+
+    /// Parses `source` as an expression, folds it and prints the result.
+    fn folded(source: &str) -> String {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let parser = Parser::new(tokens);
+        let expr = match parser.parse_repl() {
+            Ok(ReplInput::Expression(expr)) => expr,
+            _ => panic!("not an expression: {}", source),
+        };
+
+        let mut ast = parser.into_ast();
+        fold_constants(&mut ast);
+        match &ast[expr] {
+            Expression::Literal { token } => match &token.token_type {
+                TokenType::Number { number } => number.to_string(),
+                TokenType::StringLiteral { literal } => format!("{:?}", literal),
+                token_type => format!("{:?}", token_type),
+            },
+            _ => AstPrinter::new(&ast).print(expr),
+        }
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        assert_eq!(folded("1 + 2 * 3"), "7");
+        assert_eq!(folded("(1 + 2) * 3"), "9");
+        assert_eq!(folded("-(2 ** 3)"), "-8");
+        assert_eq!(folded("!true"), "False");
+        assert_eq!(folded("\"a\" + \"b\" + \"c\""), "\"abc\"");
+        assert_eq!(folded("1 < 2"), "True");
+
+        // Only the literal parts of an expression are folded.
+        assert_eq!(folded("x + 2 * 3"), "(Binary + (Variable x) (Literal 6))");
+        // Errors are left for the interpreter to report.
+        assert_eq!(
+            folded("1 + \"a\""),
+            "(Binary + (Literal 1) (Literal \"a\"))"
+        );
+    }
+}
@@ -1,68 +1,1080 @@
-use crate::ast::Expression;
+use crate::ast::{Ast, ExprId, Expression, FunctionDeclaration, Span, Stmt};
+use crate::diagnostic::{codes, Diagnostic, Severity};
 use crate::scanner::{Token, TokenType};
-use anyhow::anyhow;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::fmt::{Display, Formatter};
+use std::rc::Rc;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub struct ParserError {
+    code: &'static str,
     message: String,
     line: usize,
+    column: usize,
     lexeme: String,
+    start: usize,
+    end: usize,
+    /// Whether the parser ran out of tokens, meaning more input could fix it.
+    at_end: bool,
+    /// Whether this is at or just after text the scanner already reported,
+    /// and so is most likely a knock-on effect of that error.
+    after_scan_error: bool,
+}
+
+impl ParserError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            code: self.code,
+            severity: Severity::Error,
+            message: self.message.clone(),
+            line: self.line,
+            column: self.column,
+            start: self.start,
+            end: self.end,
+            notes: vec![],
+            input: None,
+        }
+    }
 }
 
 impl Display for ParserError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         writeln!(
             f,
-            "[line {}] Error {}: {}",
-            self.line, self.lexeme, self.message
+            "[line {}, column {}] Error {}: {}",
+            self.line, self.column, self.lexeme, self.message
         )
     }
 }
 
-pub struct Parser<'a> {
-    tokens: &'a [Token<'a>],
+/// Every error found while parsing a program, in source order.
+#[derive(Error, Debug)]
+pub struct ParserErrors(pub Vec<ParserError>);
+
+impl ParserErrors {
+    /// True when every error is from input ending too early, as with an
+    /// unclosed brace, so the source is an unfinished prefix of a valid one.
+    pub fn is_incomplete(&self) -> bool {
+        self.0.iter().all(|e| e.at_end)
+    }
+}
+
+impl Display for ParserErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        for error in &self.0 {
+            write!(f, "{}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+type ParseResult<T> = Result<T, ParserError>;
+
+/// How deeply statements and expressions can nest. Every pass over the tree
+/// recurses once per level, so this keeps deeply nested input from
+/// overflowing a stack with room for this many levels: a few KiB each in an
+/// optimized build, but tens of KiB in a debug one.
+pub const MAX_NESTING: usize = 256;
+
+/// Counts levels of nesting for as long as it's alive.
+struct Nesting<'p> {
+    depth: &'p Cell<usize>,
+    levels: usize,
+}
+
+impl Drop for Nesting<'_> {
+    fn drop(&mut self) {
+        self.depth.set(self.depth.get() - self.levels);
+    }
+}
+
+/// What a line typed at the REPL turned out to be.
+pub enum ReplInput<'a> {
+    /// A lone expression without a trailing semicolon, whose value should be
+    /// echoed back.
+    Expression(ExprId),
+    Statements(Vec<Stmt<'a>>),
+}
+
+pub struct Parser<'t, 'a> {
+    tokens: &'t [Token<'a>],
     current: Cell<usize>,
+    errors: RefCell<Vec<ParserError>>,
+    ast: RefCell<Ast<'a>>,
+    /// How many statements and expressions enclose the current token.
+    nesting: Cell<usize>,
+    /// Statements starting before this token are what's left of the body of
+    /// a declaration that failed, so they're parsed for the errors in them
+    /// but not handed back for later passes to find errors in.
+    orphaned_until: Cell<usize>,
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a [Token<'a>]) -> Self {
+impl<'t, 'a> Parser<'t, 'a> {
+    pub fn new(tokens: &'t [Token<'a>]) -> Self {
         Parser {
             tokens,
             current: Cell::new(0),
+            errors: RefCell::new(vec![]),
+            ast: RefCell::new(Ast::new()),
+            nesting: Cell::new(0),
+            orphaned_until: Cell::new(0),
+        }
+    }
+
+    /// Takes the arena holding every expression parsed so far.
+    pub fn into_ast(self) -> Ast<'a> {
+        self.ast.into_inner()
+    }
+
+    pub fn parse(&self) -> Result<Vec<Stmt<'a>>, ParserErrors> {
+        match self.parse_all() {
+            (statements, None) => Ok(statements),
+            (_, Some(errors)) => Err(errors),
+        }
+    }
+
+    /// Parses a program like [`Parser::parse`], but also hands back the
+    /// statements parsed around any errors, which later passes can still
+    /// look for errors in.
+    pub fn parse_all(&self) -> (Vec<Stmt<'a>>, Option<ParserErrors>) {
+        let mut statements = vec![];
+        while !self.is_at_end() {
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
+        }
+
+        let errors = self.errors.take();
+        if errors.is_empty() {
+            (statements, None)
+        } else {
+            (statements, Some(ParserErrors(errors)))
+        }
+    }
+
+    /// Parses REPL input, which may be a bare expression as well as the
+    /// usual list of statements.
+    pub fn parse_repl(&self) -> Result<ReplInput<'a>, ParserErrors> {
+        match self.parse_repl_all() {
+            (input, None) => Ok(input),
+            (_, Some(errors)) => Err(errors),
+        }
+    }
+
+    /// Parses REPL input like [`Parser::parse_repl`], but like
+    /// [`Parser::parse_all`] also hands back the statements parsed around
+    /// any errors.
+    pub fn parse_repl_all(&self) -> (ReplInput<'a>, Option<ParserErrors>) {
+        if let Ok(expr) = self.expression() {
+            if self.is_at_end() && self.errors.borrow().is_empty() {
+                return (ReplInput::Expression(expr), None);
+            }
+        }
+
+        // Not a lone expression, so start again from the top.
+        self.current.set(0);
+        self.errors.take();
+        self.ast.take();
+        self.orphaned_until.set(0);
+        let (statements, errors) = self.parse_all();
+        (ReplInput::Statements(statements), errors)
+    }
+
+    /// Parses a lone expression, with nothing after it. Like
+    /// [`Parser::parse_repl_all`], hands back the errors rather than failing,
+    /// but with no expression to hand back there's nothing else either.
+    pub fn parse_expression_all(&self) -> (ReplInput<'a>, Option<ParserErrors>) {
+        let input = match self.expression() {
+            Ok(expr) if self.is_at_end() => ReplInput::Expression(expr),
+            Ok(_) => {
+                self.report(
+                    self.error_at_current(codes::EXPECTED_TOKEN, "Expect end of expression."),
+                );
+                ReplInput::Statements(vec![])
+            }
+            Err(error) => {
+                self.report(error);
+                ReplInput::Statements(vec![])
+            }
+        };
+
+        let errors = self.errors.take();
+        if errors.is_empty() {
+            (input, None)
+        } else {
+            (input, Some(ParserErrors(errors)))
+        }
+    }
+
+    /// Parses a declaration, recording any error and skipping ahead to the
+    /// next statement boundary so that parsing can carry on.
+    fn declaration(&self) -> Option<Stmt<'a>> {
+        let start = self.current.get();
+        match self.parse_declaration() {
+            Ok(_) if start < self.orphaned_until.get() => None,
+            Ok(statement) => Some(statement),
+            Err(error) => {
+                self.report(error);
+                self.orphan_rest_of(start);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    /// Marks what's left of the blocks a declaration starting at `start` was
+    /// in the middle of as orphaned. Recovery carries on from inside them, so
+    /// a `return` there would otherwise look like it's at the top level.
+    fn orphan_rest_of(&self, start: usize) {
+        let mut depth = 0usize;
+        let mut end = start;
+        while end < self.current.get() || (depth > 0 && end < self.tokens.len()) {
+            match self.tokens[end].token_type {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            end += 1;
+        }
+
+        if end > self.current.get() {
+            self.orphaned_until.set(self.orphaned_until.get().max(end));
+        }
+    }
+
+    fn parse_declaration(&self) -> ParseResult<Stmt<'a>> {
+        if self.matches(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+
+        // `fun` followed by a name; otherwise it's an anonymous function
+        // at the start of an expression statement.
+        if self.check(&TokenType::Fun) && self.check_next(&TokenType::Identifier) {
+            let start = self.current.get();
+            self.advance();
+            return Ok(Stmt::Function {
+                declaration: Rc::new(self.function("function")?),
+                span: self.span_from(start),
+            });
+        }
+
+        if self.matches(&[TokenType::Var]) {
+            return self.var_declaration();
+        }
+
+        if self.matches(&[TokenType::Const]) {
+            return self.const_declaration();
+        }
+
+        if self.matches(&[TokenType::Import]) {
+            return self.import_declaration();
+        }
+
+        self.statement()
+    }
+
+    fn class_declaration(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        let name = self.consume(&TokenType::Identifier, "Expect class name.")?;
+
+        let superclass = if self.matches(&[TokenType::Less]) {
+            let name = self.consume(&TokenType::Identifier, "Expect superclass name.")?;
+            Some(self.add(Expression::Variable {
+                name: name.clone(),
+                slot: Cell::new(None),
+            }))
+        } else {
+            None
+        };
+
+        let mut mixins = vec![];
+        if self.matches(&[TokenType::With]) {
+            loop {
+                let name = self.consume(&TokenType::Identifier, "Expect mixin name.")?;
+                mixins.push(self.add(Expression::Variable {
+                    name: name.clone(),
+                    slot: Cell::new(None),
+                }));
+
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = vec![];
+        let mut class_methods = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if self.matches(&[TokenType::Class]) {
+                class_methods.push(Rc::new(self.method()?));
+            } else {
+                methods.push(Rc::new(self.method()?));
+            }
+        }
+
+        self.consume(&TokenType::RightBrace, "Expect '}' after class body.")?;
+
+        Ok(Stmt::Class {
+            name: name.clone(),
+            superclass,
+            mixins,
+            methods,
+            class_methods,
+            span: self.span_from(start),
+        })
+    }
+
+    fn function(&self, kind: &str) -> ParseResult<FunctionDeclaration<'a>> {
+        let name = self.consume(&TokenType::Identifier, &format!("Expect {} name.", kind))?;
+        self.consume(
+            &TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        self.function_body(name, kind)
+    }
+
+    /// Parses a method, which is a getter if it has no parameter list.
+    fn method(&self) -> ParseResult<FunctionDeclaration<'a>> {
+        let name = self.consume(&TokenType::Identifier, "Expect method name.")?;
+        if self.matches(&[TokenType::LeftBrace]) {
+            return Ok(FunctionDeclaration {
+                name: name.clone(),
+                params: vec![],
+                variadic: false,
+                body: self.block()?,
+                is_getter: true,
+            });
+        }
+
+        self.consume(&TokenType::LeftParen, "Expect '(' after method name.")?;
+        self.function_body(name, "method")
+    }
+
+    /// Parses the parameters and body of a function, after its `(`.
+    fn function_body(&self, name: &Token<'a>, kind: &str) -> ParseResult<FunctionDeclaration<'a>> {
+        let (params, variadic) = self.parameters()?;
+
+        self.consume(
+            &TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        let body = self.block()?;
+
+        Ok(FunctionDeclaration {
+            name: name.clone(),
+            params,
+            variadic,
+            body,
+            is_getter: false,
+        })
+    }
+
+    /// Parses an anonymous function after its `fun`. The body is either a
+    /// block or, after `=>`, a single expression that's returned.
+    fn anonymous_function(&self, keyword: &Token<'a>) -> ParseResult<ExprId> {
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'fun'.")?;
+        let (params, variadic) = self.parameters()?;
+
+        let body = if let Some(arrow) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::Arrow) => self.advance(),
+            _ => None,
+        } {
+            let value = self.assignment()?;
+            vec![Stmt::Return {
+                keyword: arrow.clone(),
+                value: Some(value),
+                span: self.ast.borrow().span(value),
+            }]
+        } else {
+            self.consume(
+                &TokenType::LeftBrace,
+                "Expect '{' or '=>' after parameters.",
+            )?;
+            self.block()?
+        };
+
+        Ok(self.add(Expression::Function {
+            declaration: Rc::new(FunctionDeclaration {
+                name: keyword.clone(),
+                params,
+                variadic,
+                body,
+                is_getter: false,
+            }),
+        }))
+    }
+
+    /// Parses a parameter list up to and including its `)`, and whether
+    /// it ends with a rest parameter.
+    fn parameters(&self) -> ParseResult<(Vec<Token<'a>>, bool)> {
+        let mut params = vec![];
+        let mut variadic = false;
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    self.report(self.error_at_current(
+                        codes::TOO_MANY_PARAMETERS,
+                        "Can't have more than 255 parameters.",
+                    ));
+                }
+
+                variadic = self.matches(&[TokenType::Ellipsis]);
+                params.push(
+                    self.consume(&TokenType::Identifier, "Expect parameter name.")?
+                        .clone(),
+                );
+
+                // A rest parameter has to come last.
+                if variadic || !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        Ok((params, variadic))
+    }
+
+    fn var_declaration(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        if self.check(&TokenType::LeftParen) {
+            let paren = self.consume(&TokenType::LeftParen, "Expect '('.")?;
+            return self.destructure(start, paren, false);
+        }
+
+        let name = self.consume(&TokenType::Identifier, "Expect variable name.")?;
+
+        let initializer = if self.matches(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            &TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Var {
+            name: name.clone(),
+            initializer,
+            is_const: false,
+            span: self.span_from(start),
+        })
+    }
+
+    fn const_declaration(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        if self.check(&TokenType::LeftParen) {
+            let paren = self.consume(&TokenType::LeftParen, "Expect '('.")?;
+            return self.destructure(start, paren, true);
+        }
+
+        let name = self.consume(&TokenType::Identifier, "Expect constant name.")?;
+        self.consume(&TokenType::Equal, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+
+        self.consume(
+            &TokenType::Semicolon,
+            "Expect ';' after constant declaration.",
+        )?;
+        Ok(Stmt::Var {
+            name: name.clone(),
+            initializer: Some(initializer),
+            is_const: true,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses the rest of `var (a, b) = tuple;` after its `(`. `start` is
+    /// the index of the `var` or `const`.
+    fn destructure(
+        &self,
+        start: usize,
+        paren: &Token<'a>,
+        is_const: bool,
+    ) -> ParseResult<Stmt<'a>> {
+        let mut names = vec![];
+        loop {
+            names.push(
+                self.consume(&TokenType::Identifier, "Expect variable name.")?
+                    .clone(),
+            );
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after variable names.")?;
+        self.consume(&TokenType::Equal, "Expect '=' after variable names.")?;
+        let initializer = self.expression()?;
+
+        self.consume(
+            &TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Destructure {
+            paren: paren.clone(),
+            names,
+            initializer,
+            is_const,
+            span: self.span_from(start),
+        })
+    }
+
+    fn import_declaration(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        let keyword = self.previous().ok_or_else(|| {
+            self.error_at_current(codes::EXPECTED_TOKEN, "Expect 'import' keyword.")
+        })?;
+
+        let path = match self.peek() {
+            Some(
+                token @ Token {
+                    token_type: TokenType::StringLiteral { .. },
+                    ..
+                },
+            ) => {
+                self.advance();
+                token
+            }
+            _ => {
+                return Err(self
+                    .error_at_current(codes::EXPECTED_TOKEN, "Expect module path after 'import'."))
+            }
+        };
+
+        let alias = if self.matches(&[TokenType::As]) {
+            Some(
+                self.consume(&TokenType::Identifier, "Expect module name after 'as'.")?
+                    .clone(),
+            )
+        } else {
+            None
+        };
+
+        self.consume(&TokenType::Semicolon, "Expect ';' after import.")?;
+        Ok(Stmt::Import {
+            keyword: keyword.clone(),
+            path: path.clone(),
+            alias,
+            span: self.span_from(start),
+        })
+    }
+
+    fn statement(&self) -> ParseResult<Stmt<'a>> {
+        let _nesting = self.nest()?;
+        if self.matches(&[TokenType::Break]) {
+            return self.break_statement();
+        }
+
+        if self.matches(&[TokenType::Continue]) {
+            return self.continue_statement();
+        }
+
+        if self.matches(&[TokenType::For]) {
+            return self.for_statement();
+        }
+
+        if self.matches(&[TokenType::If]) {
+            return self.if_statement();
+        }
+
+        if self.matches(&[TokenType::Print]) {
+            return self.print_statement();
+        }
+
+        if self.matches(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+
+        if self.matches(&[TokenType::Throw]) {
+            return self.throw_statement();
+        }
+
+        if self.matches(&[TokenType::Try]) {
+            return self.try_statement();
+        }
+
+        if self.matches(&[TokenType::While]) {
+            return self.while_statement();
+        }
+
+        if self.matches(&[TokenType::LeftBrace]) {
+            let start = self.previous_index();
+            return Ok(Stmt::Block {
+                statements: self.block()?,
+                span: self.span_from(start),
+            });
+        }
+
+        self.expression_statement()
+    }
+
+    /// There's no runtime node for `for`; it's desugared into a while loop
+    /// that carries the increment, wrapped in a block for the initializer.
+    fn for_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'for'.")?;
+
+        // `for (x in xs)`, optionally written `for (var x in xs)`.
+        let name_distance = if self.check(&TokenType::Var) { 1 } else { 0 };
+        if self.check_ahead(name_distance, &TokenType::Identifier)
+            && self.check_ahead(name_distance + 1, &TokenType::In)
+        {
+            return self.for_in_statement(start);
+        }
+
+        let initializer = if self.matches(&[TokenType::Semicolon]) {
+            None
+        } else if self.matches(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if !self.check(&TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(&TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if !self.check(&TokenType::RightParen) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(&TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.statement()?;
+
+        let condition = condition.unwrap_or_else(|| {
+            self.add(Expression::Literal {
+                token: Token::new(
+                    TokenType::True,
+                    "true",
+                    self.previous().map_or(0, |t| t.line),
+                ),
+            })
+        });
+        let mut body = Stmt::While {
+            condition,
+            body: Box::new(body),
+            increment,
+            span: self.span_from(start),
+        };
+
+        if let Some(initializer) = initializer {
+            body = Stmt::Block {
+                statements: vec![initializer, body],
+                span: self.span_from(start),
+            };
+        }
+
+        Ok(body)
+    }
+
+    /// Parses a `for`-in loop after its `(`. `start` is the index of the
+    /// `for`.
+    fn for_in_statement(&self, start: usize) -> ParseResult<Stmt<'a>> {
+        self.matches(&[TokenType::Var]);
+        let name = self.consume(&TokenType::Identifier, "Expect loop variable name.")?;
+        let keyword = self.consume(&TokenType::In, "Expect 'in' after loop variable.")?;
+        let iterable = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after for clauses.")?;
+        let body = Box::new(self.statement()?);
+
+        Ok(Stmt::ForIn {
+            name: name.clone(),
+            keyword: keyword.clone(),
+            iterable,
+            body,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses an `if` and any `else if`s after it in a loop, so a long chain
+    /// of them doesn't count as nesting.
+    fn if_statement(&self) -> ParseResult<Stmt<'a>> {
+        let mut branches = vec![];
+        let else_branch = loop {
+            let start = self.previous_index();
+            self.consume(&TokenType::LeftParen, "Expect '(' after 'if'.")?;
+            let condition = self.expression()?;
+            self.consume(&TokenType::RightParen, "Expect ')' after if condition.")?;
+            let then_branch = Box::new(self.statement()?);
+            branches.push((start, condition, then_branch));
+
+            // An else binds to the nearest if that precedes it.
+            if !self.matches(&[TokenType::Else]) {
+                break None;
+            }
+            if !self.matches(&[TokenType::If]) {
+                break Some(Box::new(self.statement()?));
+            }
+        };
+
+        // Each `if` in the chain ends where the whole chain does, which is
+        // the token just consumed.
+        let chain = branches.into_iter().rev().fold(
+            else_branch,
+            |else_branch, (start, condition, then_branch)| {
+                Some(Box::new(Stmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    span: self.span_from(start),
+                }))
+            },
+        );
+        Ok(*chain.expect("an if statement has at least one branch"))
+    }
+
+    fn break_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        let keyword = self.previous().ok_or_else(|| {
+            self.error_at_current(codes::EXPECTED_TOKEN, "Expect 'break' keyword.")
+        })?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after 'break'.")?;
+        Ok(Stmt::Break {
+            keyword: keyword.clone(),
+            span: self.span_from(start),
+        })
+    }
+
+    fn continue_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        let keyword = self.previous().ok_or_else(|| {
+            self.error_at_current(codes::EXPECTED_TOKEN, "Expect 'continue' keyword.")
+        })?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+        Ok(Stmt::Continue {
+            keyword: keyword.clone(),
+            span: self.span_from(start),
+        })
+    }
+
+    fn print_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        let expr = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(Stmt::Print {
+            expr,
+            span: self.span_from(start),
+        })
+    }
+
+    fn return_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        let keyword = self.previous().ok_or_else(|| {
+            self.error_at_current(codes::EXPECTED_TOKEN, "Expect 'return' keyword.")
+        })?;
+
+        let value = if !self.check(&TokenType::Semicolon) {
+            // `return a, b;` returns a tuple.
+            let value = self.assignment()?;
+            if self.check(&TokenType::Comma) {
+                let mut elements = vec![value];
+                while self.matches(&[TokenType::Comma]) {
+                    elements.push(self.assignment()?);
+                }
+                Some(self.add(Expression::Tuple {
+                    paren: keyword.clone(),
+                    elements,
+                }))
+            } else {
+                Some(value)
+            }
+        } else {
+            None
+        };
+
+        self.consume(&TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return {
+            keyword: keyword.clone(),
+            value,
+            span: self.span_from(start),
+        })
+    }
+
+    fn throw_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        let keyword = self.previous().ok_or_else(|| {
+            self.error_at_current(codes::EXPECTED_TOKEN, "Expect 'throw' keyword.")
+        })?;
+        let value = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after thrown value.")?;
+
+        Ok(Stmt::Throw {
+            keyword: keyword.clone(),
+            value,
+            span: self.span_from(start),
+        })
+    }
+
+    fn try_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        self.consume(&TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        self.consume(&TokenType::Catch, "Expect 'catch' after try block.")?;
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+        let name = self.consume(&TokenType::Identifier, "Expect exception variable name.")?;
+        self.consume(
+            &TokenType::RightParen,
+            "Expect ')' after exception variable.",
+        )?;
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' before catch block.")?;
+        let handler = self.block()?;
+
+        Ok(Stmt::Try {
+            body,
+            name: name.clone(),
+            handler,
+            span: self.span_from(start),
+        })
+    }
+
+    fn while_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.previous_index();
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.statement()?);
+
+        Ok(Stmt::While {
+            condition,
+            body,
+            increment: None,
+            span: self.span_from(start),
+        })
+    }
+
+    fn block(&self) -> ParseResult<Vec<Stmt<'a>>> {
+        let mut statements = vec![];
+
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
+        }
+
+        self.consume(&TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
+    fn expression_statement(&self) -> ParseResult<Stmt<'a>> {
+        let start = self.current.get();
+        let expr = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after expression.")?;
+        Ok(Stmt::Expression {
+            expr,
+            span: self.span_from(start),
+        })
+    }
+
+    fn expression(&self) -> ParseResult<ExprId> {
+        self.comma()
+    }
+
+    /// `a, b` evaluates `a`, throws its value away and yields `b`.
+    fn comma(&self) -> ParseResult<ExprId> {
+        let mut expr = self.assignment()?;
+
+        while let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::Comma) => self.advance(),
+            _ => None,
+        } {
+            let right = self.assignment()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            });
         }
+
+        Ok(expr)
     }
 
-    pub fn parse(&self) -> anyhow::Result<Expression> {
-        self.expression()
+    fn assignment(&self) -> ParseResult<ExprId> {
+        let _nesting = self.nest()?;
+        let expr = self.or()?;
+
+        if let Some(equals) = match self.peek().map(|t| &t.token_type) {
+            Some(
+                &TokenType::Equal
+                | &TokenType::PlusEqual
+                | &TokenType::MinusEqual
+                | &TokenType::StarEqual
+                | &TokenType::SlashEqual,
+            ) => self.advance(),
+            _ => None,
+        } {
+            let mut value = self.assignment()?;
+            let operator = Self::compound_operator(equals);
+
+            let target = self.ast.borrow()[expr].clone();
+            return match target {
+                Expression::Variable { name, .. } => {
+                    // Reading a variable again has no side effects, so
+                    // `a += b` is sugar for `a = a + b`.
+                    if let Some(operator) = operator {
+                        value = self.add(Expression::Binary {
+                            l_expr: expr,
+                            operator,
+                            r_expr: value,
+                        });
+                    }
+                    Ok(self.add(Expression::Assign {
+                        name,
+                        value,
+                        slot: Cell::new(None),
+                    }))
+                }
+                Expression::Get {
+                    object,
+                    name,
+                    optional: false,
+                } => Ok(self.add(Expression::Set {
+                    object,
+                    name,
+                    value,
+                    operator,
+                })),
+                Expression::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Ok(self.add(Expression::SetIndex {
+                    object,
+                    bracket,
+                    index,
+                    value,
+                    operator,
+                })),
+                // The parser isn't confused about where it is, so report the
+                // error without unwinding.
+                _ => {
+                    self.report(self.error(
+                        equals,
+                        codes::INVALID_ASSIGNMENT_TARGET,
+                        "Invalid assignment target.",
+                    ));
+                    Ok(expr)
+                }
+            };
+        }
+
+        Ok(expr)
     }
 
-    fn expression(&self) -> anyhow::Result<Expression> {
-        self.equality()
+    /// The binary operator a compound assignment like `+=` applies, as a
+    /// token covering just the operator's first character.
+    fn compound_operator(equals: &Token<'a>) -> Option<Token<'a>> {
+        let token_type = match equals.token_type {
+            TokenType::PlusEqual => TokenType::Plus,
+            TokenType::MinusEqual => TokenType::Minus,
+            TokenType::StarEqual => TokenType::Star,
+            TokenType::SlashEqual => TokenType::Slash,
+            _ => return None,
+        };
+
+        Some(Token {
+            token_type,
+            lexeme: &equals.lexeme[..1],
+            end: equals.start + 1,
+            ..equals.clone()
+        })
     }
 
-    fn equality(&self) -> anyhow::Result<Expression> {
+    fn or(&self) -> ParseResult<ExprId> {
+        let mut expr = self.coalesce()?;
+        let mut nesting = self.nesting();
+
+        while let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::Or) => self.advance(),
+            _ => None,
+        } {
+            self.deepen(&mut nesting)?;
+            let right = self.coalesce()?;
+            expr = self.add(Expression::Logical {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    /// `a ?? b`, which is `a` unless that's nil.
+    fn coalesce(&self) -> ParseResult<ExprId> {
+        let mut expr = self.and()?;
+        let mut nesting = self.nesting();
+
+        while let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::QuestionQuestion) => self.advance(),
+            _ => None,
+        } {
+            self.deepen(&mut nesting)?;
+            let right = self.and()?;
+            expr = self.add(Expression::Logical {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&self) -> ParseResult<ExprId> {
+        let mut expr = self.equality()?;
+        let mut nesting = self.nesting();
+
+        while let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::And) => self.advance(),
+            _ => None,
+        } {
+            self.deepen(&mut nesting)?;
+            let right = self.equality()?;
+            expr = self.add(Expression::Logical {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn equality(&self) -> ParseResult<ExprId> {
         let mut expr = self.comparison()?;
+        let mut nesting = self.nesting();
 
         while let Some(t) = match self.peek().map(|t| &t.token_type) {
             Some(&TokenType::BangEqual | &TokenType::EqualEqual) => self.advance(),
             _ => None,
         } {
-            let right = Box::new(self.comparison()?);
-            expr = Expression::Binary {
-                l_expr: Box::new(expr),
+            self.deepen(&mut nesting)?;
+            let right = self.comparison()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
                 operator: t.clone(),
                 r_expr: right,
-            };
+            });
         }
 
         Ok(expr)
     }
 
-    fn comparison(&self) -> anyhow::Result<Expression> {
-        let mut expr = self.term()?;
+    fn comparison(&self) -> ParseResult<ExprId> {
+        let mut expr = self.bitwise_or()?;
+        let mut nesting = self.nesting();
 
         while let Some(t) = match self.peek().map(|t| &t.token_type) {
             Some(
@@ -73,69 +1085,270 @@ impl<'a> Parser<'a> {
             ) => self.advance(),
             _ => None,
         } {
-            let right = Box::new(self.term()?);
-            expr = Expression::Binary {
-                l_expr: Box::new(expr),
+            self.deepen(&mut nesting)?;
+            let right = self.bitwise_or()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
                 operator: t.clone(),
                 r_expr: right,
-            };
+            });
+        }
+
+        Ok(expr)
+    }
+
+    // The bitwise operators sit between comparison and term, as in Python,
+    // so that `a & mask == 0` compares the masked value.
+    fn bitwise_or(&self) -> ParseResult<ExprId> {
+        let mut expr = self.bitwise_xor()?;
+        let mut nesting = self.nesting();
+
+        while let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::Pipe) => self.advance(),
+            _ => None,
+        } {
+            self.deepen(&mut nesting)?;
+            let right = self.bitwise_xor()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn bitwise_xor(&self) -> ParseResult<ExprId> {
+        let mut expr = self.bitwise_and()?;
+        let mut nesting = self.nesting();
+
+        while let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::Caret) => self.advance(),
+            _ => None,
+        } {
+            self.deepen(&mut nesting)?;
+            let right = self.bitwise_and()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn bitwise_and(&self) -> ParseResult<ExprId> {
+        let mut expr = self.shift()?;
+        let mut nesting = self.nesting();
+
+        while let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::Ampersand) => self.advance(),
+            _ => None,
+        } {
+            self.deepen(&mut nesting)?;
+            let right = self.shift()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn shift(&self) -> ParseResult<ExprId> {
+        let mut expr = self.term()?;
+        let mut nesting = self.nesting();
+
+        while let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::LessLess | &TokenType::GreaterGreater) => self.advance(),
+            _ => None,
+        } {
+            self.deepen(&mut nesting)?;
+            let right = self.term()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            });
         }
 
         Ok(expr)
     }
 
-    fn term(&self) -> anyhow::Result<Expression> {
+    fn term(&self) -> ParseResult<ExprId> {
         let mut expr = self.factor()?;
+        let mut nesting = self.nesting();
 
         while let Some(t) = match self.peek().map(|t| &t.token_type) {
             Some(&TokenType::Plus | &TokenType::Minus) => self.advance(),
             _ => None,
         } {
-            let right = Box::new(self.factor()?);
-            expr = Expression::Binary {
-                l_expr: Box::new(expr),
+            self.deepen(&mut nesting)?;
+            let right = self.factor()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
                 operator: t.clone(),
                 r_expr: right,
-            }
+            })
         }
 
         Ok(expr)
     }
 
-    fn factor(&self) -> anyhow::Result<Expression> {
-        let mut expr = self.unary()?;
+    fn factor(&self) -> ParseResult<ExprId> {
+        let mut expr = self.exponent()?;
+        let mut nesting = self.nesting();
 
         while let Some(t) = match self.peek().map(|t| &t.token_type) {
             Some(&TokenType::Slash | &TokenType::Star) => self.advance(),
             _ => None,
         } {
-            let right = Box::new(self.unary()?);
-            expr = Expression::Binary {
-                l_expr: Box::new(expr),
+            self.deepen(&mut nesting)?;
+            let right = self.exponent()?;
+            expr = self.add(Expression::Binary {
+                l_expr: expr,
                 operator: t.clone(),
                 r_expr: right,
-            }
+            })
         }
 
         Ok(expr)
     }
 
-    fn unary(&self) -> anyhow::Result<Expression> {
+    /// `**` is right-associative, so `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+    fn exponent(&self) -> ParseResult<ExprId> {
+        let expr = self.unary()?;
+
+        if let Some(t) = match self.peek().map(|t| &t.token_type) {
+            Some(&TokenType::StarStar) => self.advance(),
+            _ => None,
+        } {
+            let _nesting = self.nest()?;
+            let right = self.exponent()?;
+            return Ok(self.add(Expression::Binary {
+                l_expr: expr,
+                operator: t.clone(),
+                r_expr: right,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&self) -> ParseResult<ExprId> {
         if let Some(t) = match self.peek().map(|t| &t.token_type) {
             Some(&TokenType::Bang | &TokenType::Minus) => self.advance(),
             _ => None,
         } {
-            let right = Box::new(self.unary()?);
-            return Ok(Expression::Unary {
+            let _nesting = self.nest()?;
+            let right = self.unary()?;
+            return Ok(self.add(Expression::Unary {
                 operator: t.clone(),
                 r_expr: right,
-            });
+            }));
+        }
+
+        self.call()
+    }
+
+    fn call(&self) -> ParseResult<ExprId> {
+        let mut expr = self.primary()?;
+        let mut nesting = self.nesting();
+
+        loop {
+            if self.matches(&[TokenType::LeftParen]) {
+                self.deepen(&mut nesting)?;
+                expr = self.finish_call(expr)?;
+            } else if self.matches(&[TokenType::LeftBracket]) {
+                self.deepen(&mut nesting)?;
+                let index = self.expression()?;
+                let bracket = self.consume(&TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = self.add(Expression::Index {
+                    object: expr,
+                    bracket: bracket.clone(),
+                    index,
+                });
+            } else if self.matches(&[TokenType::Dot, TokenType::QuestionDot]) {
+                self.deepen(&mut nesting)?;
+                let optional =
+                    self.previous().map(|t| &t.token_type) == Some(&TokenType::QuestionDot);
+                let name =
+                    self.consume(&TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = self.add(Expression::Get {
+                    object: expr,
+                    name: name.clone(),
+                    optional,
+                });
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn finish_call(&self, callee: ExprId) -> ParseResult<ExprId> {
+        let mut arguments = vec![];
+        // Whether a named argument has been seen, after which every argument
+        // has to be named.
+        let mut named = false;
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    self.report(self.error_at_current(
+                        codes::TOO_MANY_ARGUMENTS,
+                        "Can't have more than 255 arguments.",
+                    ));
+                }
+
+                // Commas here separate arguments rather than sequencing.
+                let argument =
+                    if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Colon) {
+                        let name = self.consume(&TokenType::Identifier, "Expect argument name.")?;
+                        self.consume(&TokenType::Colon, "Expect ':' after argument name.")?;
+                        let value = self.assignment()?;
+                        named = true;
+                        self.add(Expression::Named {
+                            name: name.clone(),
+                            value,
+                        })
+                    } else if named {
+                        return Err(self.error_at_current(
+                            codes::POSITIONAL_AFTER_NAMED,
+                            "Expect named argument after a named argument.",
+                        ));
+                    } else if self.check(&TokenType::Ellipsis) {
+                        let ellipsis = self.consume(&TokenType::Ellipsis, "Expect '...'.")?;
+                        let expr = self.assignment()?;
+                        self.add(Expression::Spread {
+                            ellipsis: ellipsis.clone(),
+                            expr,
+                        })
+                    } else {
+                        self.assignment()?
+                    };
+                arguments.push(argument);
+
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
 
-        self.primary()
+        let paren = self.consume(&TokenType::RightParen, "Expect ')' after arguments.")?;
+
+        Ok(self.add(Expression::Call {
+            callee,
+            paren: paren.clone(),
+            arguments,
+        }))
     }
 
-    fn primary(&self) -> anyhow::Result<Expression> {
+    fn primary(&self) -> ParseResult<ExprId> {
         let next = self.peek();
 
         match next {
@@ -144,37 +1357,283 @@ impl<'a> Parser<'a> {
                 | TokenType::True
                 | TokenType::Nil
                 | TokenType::Number { .. }
-                | TokenType::StringLiteral { .. } => {
+                | TokenType::StringLiteral { .. }
+                // Stands in for whatever the scanner couldn't read, which
+                // was most likely meant to be an expression.
+                | TokenType::Error => {
                     self.advance();
-                    Ok(Expression::Literal { token: t.clone() })
+                    Ok(self.add(Expression::Literal { token: t.clone() }))
+                }
+                TokenType::Super => {
+                    self.advance();
+                    self.consume(&TokenType::Dot, "Expect '.' after 'super'.")?;
+                    let method =
+                        self.consume(&TokenType::Identifier, "Expect superclass method name.")?;
+                    Ok(self.add(Expression::Super {
+                        keyword: t.clone(),
+                        method: method.clone(),
+                        slot: Cell::new(None),
+                    }))
+                }
+                TokenType::This => {
+                    self.advance();
+                    Ok(self.add(Expression::This {
+                        keyword: t.clone(),
+                        slot: Cell::new(None),
+                    }))
+                }
+                TokenType::Identifier => {
+                    self.advance();
+                    Ok(self.add(Expression::Variable {
+                        name: t.clone(),
+                        slot: Cell::new(None),
+                    }))
+                }
+                TokenType::Fun => {
+                    self.advance();
+                    self.anonymous_function(t)
+                }
+                TokenType::LeftBracket => {
+                    self.advance();
+                    self.list(t)
+                }
+                TokenType::LeftBrace => {
+                    self.advance();
+                    self.map(t)
                 }
                 TokenType::LeftParen => {
                     self.advance();
-                    let expr = Box::new(self.expression()?);
-                    if let Some(t) = self.peek() {
-                        if t.token_type == TokenType::RightParen {
-                            self.advance();
-                            Ok(Expression::Grouping { expr })
-                        } else {
-                            Err(ParserError {
-                                message: "expect ')' after expression".to_string(),
-                                lexeme: t.lexeme.to_string(),
-                                line: t.line,
-                            }
-                            .into())
-                        }
-                    } else {
-                        Err(anyhow!("expect ')' after expression"))
+                    let expr = self.assignment()?;
+                    if self.matches(&[TokenType::Comma]) {
+                        return self.tuple(t, expr);
                     }
+                    self.consume(&TokenType::RightParen, "Expect ')' after expression.")?;
+                    Ok(self.add_from(t, Expression::Grouping { expr }))
+                }
+                _ => Err(self.error(t, codes::EXPECTED_EXPRESSION, "Expect expression.")),
+            },
+            _ => Err(self.error_at_current(codes::EXPECTED_EXPRESSION, "Expect expression.")),
+        }
+    }
+
+    /// Parses a list literal after its `[`. A trailing comma is allowed.
+    fn list(&self, bracket: &Token<'a>) -> ParseResult<ExprId> {
+        let mut elements = vec![];
+        while !self.check(&TokenType::RightBracket) && !self.is_at_end() {
+            elements.push(self.assignment()?);
+
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(&TokenType::RightBracket, "Expect ']' after list elements.")?;
+        Ok(self.add(Expression::List {
+            bracket: bracket.clone(),
+            elements,
+        }))
+    }
+
+    /// Parses the rest of a tuple after its `(`, first element and comma.
+    /// A trailing comma is allowed, and is needed for a tuple of one.
+    fn tuple(&self, paren: &Token<'a>, first: ExprId) -> ParseResult<ExprId> {
+        let mut elements = vec![first];
+        while !self.check(&TokenType::RightParen) && !self.is_at_end() {
+            elements.push(self.assignment()?);
+
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(&TokenType::RightParen, "Expect ')' after tuple elements.")?;
+        Ok(self.add_from(
+            paren,
+            Expression::Tuple {
+                paren: paren.clone(),
+                elements,
+            },
+        ))
+    }
+
+    /// Parses a map literal after its `{`. A trailing comma is allowed.
+    fn map(&self, brace: &Token<'a>) -> ParseResult<ExprId> {
+        let mut entries = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let key = self.assignment()?;
+            self.consume(&TokenType::Colon, "Expect ':' after map key.")?;
+            let value = self.assignment()?;
+            entries.push((key, value));
+
+            if !self.matches(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(&TokenType::RightBrace, "Expect '}' after map entries.")?;
+        Ok(self.add(Expression::Map {
+            brace: brace.clone(),
+            entries,
+        }))
+    }
+
+    /// Adds an expression that ends at the token just consumed.
+    fn add(&self, expr: Expression<'a>) -> ExprId {
+        let start = {
+            let ast = self.ast.borrow();
+            match &expr {
+                Expression::Binary { l_expr: left, .. }
+                | Expression::Logical { l_expr: left, .. }
+                | Expression::Call { callee: left, .. }
+                | Expression::Get { object: left, .. }
+                | Expression::Index { object: left, .. }
+                | Expression::Set { object: left, .. }
+                | Expression::SetIndex { object: left, .. }
+                | Expression::Grouping { expr: left } => ast.span(*left),
+                Expression::Tuple { paren, elements } => match elements.first() {
+                    Some(first) => ast.span(*first),
+                    None => Span::between(paren, paren),
+                },
+                Expression::Assign { name: token, .. }
+                | Expression::Variable { name: token, .. }
+                | Expression::Named { name: token, .. }
+                | Expression::List { bracket: token, .. }
+                | Expression::Spread {
+                    ellipsis: token, ..
                 }
-                _ => Err(ParserError {
-                    message: format!("unrecognized primary: {:?}", t),
-                    lexeme: t.lexeme.to_string(),
-                    line: t.line,
+                | Expression::Map { brace: token, .. }
+                | Expression::Literal { token }
+                | Expression::Super { keyword: token, .. }
+                | Expression::This { keyword: token, .. }
+                | Expression::Unary {
+                    operator: token, ..
+                } => Span::between(token, token),
+                Expression::Function { declaration } => {
+                    Span::between(&declaration.name, &declaration.name)
                 }
-                .into()),
+            }
+        };
+        let end = self.previous().map_or(start.end, |t| t.end).max(start.end);
+        self.ast.borrow_mut().add(expr, Span { end, ..start })
+    }
+
+    /// Adds an expression that starts at `first`, like a grouping whose `(`
+    /// no subexpression includes.
+    fn add_from(&self, first: &Token<'a>, expr: Expression<'a>) -> ExprId {
+        let span = match self.previous() {
+            Some(last) => Span::between(first, last),
+            None => Span::between(first, first),
+        };
+        self.ast.borrow_mut().add(expr, span)
+    }
+
+    /// The span of a statement from the token at `first` to the one just
+    /// consumed.
+    fn span_from(&self, first: usize) -> Span {
+        match (self.tokens.get(first), self.previous()) {
+            (Some(first), Some(last)) => Span::between(first, last),
+            _ => Span::default(),
+        }
+    }
+
+    /// The index of the token just consumed, which a statement whose
+    /// keyword was just matched starts at.
+    fn previous_index(&self) -> usize {
+        self.current.get().saturating_sub(1)
+    }
+
+    fn matches(&self, types: &[TokenType]) -> bool {
+        for t in types {
+            if self.check(t) {
+                self.advance();
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn consume(&self, t: &TokenType, message: &str) -> ParseResult<&'t Token<'a>> {
+        if self.check(t) {
+            if let Some(token) = self.advance() {
+                return Ok(token);
+            }
+        }
+
+        Err(self.error_at_current(codes::EXPECTED_TOKEN, message))
+    }
+
+    fn report(&self, error: ParserError) {
+        if !error.after_scan_error {
+            self.errors.borrow_mut().push(error);
+        }
+    }
+
+    /// Enters one more level of nesting, or fails if that's too many.
+    fn nest(&self) -> ParseResult<Nesting<'_>> {
+        let mut nesting = self.nesting();
+        self.deepen(&mut nesting)?;
+        Ok(nesting)
+    }
+
+    /// A guard that holds no levels yet, for a loop that builds a
+    /// left-nested tree to add one to per step.
+    fn nesting(&self) -> Nesting<'_> {
+        Nesting {
+            depth: &self.nesting,
+            levels: 0,
+        }
+    }
+
+    /// Adds one more level of nesting to `nesting`, or fails if that's too
+    /// many.
+    fn deepen(&self, nesting: &mut Nesting<'_>) -> ParseResult<()> {
+        if self.nesting.get() >= MAX_NESTING {
+            return Err(self.error_at_current(codes::TOO_DEEPLY_NESTED, "Too much nesting."));
+        }
+        self.nesting.set(self.nesting.get() + 1);
+        nesting.levels += 1;
+        Ok(())
+    }
+
+    fn error_at_current(&self, code: &'static str, message: &str) -> ParserError {
+        match self.peek().or_else(|| self.tokens.last()) {
+            Some(token) => self.error(token, code, message),
+            None => ParserError {
+                code,
+                message: message.to_string(),
+                lexeme: "at end".to_string(),
+                line: 0,
+                column: 0,
+                start: 0,
+                end: 0,
+                at_end: true,
+                after_scan_error: false,
             },
-            _ => Err(anyhow!("expected expression")),
+        }
+    }
+
+    fn error(&self, token: &Token, code: &'static str, message: &str) -> ParserError {
+        let at_end = token.token_type == TokenType::Eof;
+        let lexeme = if at_end {
+            "at end".to_string()
+        } else {
+            format!("at '{}'", token.lexeme)
+        };
+
+        ParserError {
+            code,
+            message: message.to_string(),
+            lexeme,
+            line: token.line,
+            column: token.column,
+            start: token.start,
+            end: token.end,
+            at_end,
+            after_scan_error: token.token_type == TokenType::Error
+                || self
+                    .previous()
+                    .is_some_and(|previous| previous.token_type == TokenType::Error),
         }
     }
 
@@ -186,7 +1645,7 @@ impl<'a> Parser<'a> {
         self.peek().map(|t| &t.token_type) == Some(t)
     }
 
-    fn advance(&self) -> Option<&'a Token<'a>> {
+    fn advance(&self) -> Option<&'t Token<'a>> {
         if !self.is_at_end() {
             self.current.set(self.current.get() + 1)
         }
@@ -194,16 +1653,29 @@ impl<'a> Parser<'a> {
         self.previous()
     }
 
+    fn check_next(&self, t: &TokenType) -> bool {
+        self.check_ahead(1, t)
+    }
+
+    /// Whether the token `distance` places after the current one is a `t`.
+    fn check_ahead(&self, distance: usize, t: &TokenType) -> bool {
+        self.tokens
+            .get(self.current.get() + distance)
+            .map(|t| &t.token_type)
+            == Some(t)
+    }
+
     fn is_at_end(&self) -> bool {
         self.peek().map(|t| &t.token_type) == Some(&TokenType::Eof)
     }
 
-    fn peek(&self) -> Option<&'a Token<'a>> {
+    fn peek(&self) -> Option<&'t Token<'a>> {
         self.tokens.get(self.current.get())
     }
 
-    fn previous(&self) -> Option<&'a Token<'a>> {
-        self.tokens.get(self.current.get() - 1)
+    fn previous(&self) -> Option<&'t Token<'a>> {
+        let current = self.current.get();
+        current.checked_sub(1).and_then(|i| self.tokens.get(i))
     }
 
     fn synchronize(&self) {
@@ -215,21 +1687,55 @@ impl<'a> Parser<'a> {
                 }
             }
 
-            match self.peek().map(|t| &t.token_type) {
-                Some(
-                    TokenType::Class
-                    | TokenType::Fun
-                    | TokenType::Var
-                    | TokenType::For
-                    | TokenType::If
-                    | TokenType::While
-                    | TokenType::Print
-                    | TokenType::Return,
-                ) => return,
-                _ => (),
+            if let Some(
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::Const
+                | TokenType::Import
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Try,
+            ) = self.peek().map(|t| &t.token_type)
+            {
+                return;
             }
 
             self.advance();
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_reports_every_statement_error() {
+        let mut scanner = Scanner::new("print 1 +;\nvar = 3;\nprint \"ok\";\n");
+        let tokens = scanner.scan_tokens().unwrap();
+
+        let errors = Parser::new(tokens).parse().err().unwrap();
+        let lines = errors.0.iter().map(|e| e.line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        // A chain of `else if`s is as deep as one `if`, however long it is.
+        let branches: Vec<String> = (0..200)
+            .map(|i| format!("if (x == {}) print {};", i, i))
+            .collect();
+        let source = format!("{} else print -1;", branches.join(" else "));
+        let mut scanner = Scanner::new(&source);
+        let tokens = scanner.scan_tokens().unwrap();
+
+        let statements = Parser::new(tokens).parse().unwrap();
+        assert_eq!(statements.len(), 1);
+    }
+}
@@ -0,0 +1,92 @@
+//! Running programs somewhere without a terminal, like a web page with the
+//! interpreter compiled to WebAssembly. Nothing here touches stdout, stderr
+//! or the clock. With the `wasm-bindgen` feature, [`run`] is exported to
+//! JavaScript, returning an object with `output` and `errors` fields:
+//!
+//! ```text
+//! cargo build --lib --release --target wasm32-unknown-unknown --features wasm-bindgen
+//! ```
+
+use std::rc::Rc;
+
+use crate::diagnostic;
+use crate::host::CaptureHost;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::json::{self, Object};
+use crate::Lox;
+
+#[cfg(feature = "wasm-bindgen")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// What running a program produced.
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(getter_with_clone))]
+#[derive(Debug, Default, PartialEq)]
+pub struct RunOutput {
+    /// Everything the program printed.
+    pub output: String,
+    /// Each error that stopped it, rendered as it would be in a terminal
+    /// but without color.
+    pub errors: Vec<String>,
+}
+
+impl RunOutput {
+    /// `{"output": ..., "errors": [...]}`, for handing to JavaScript.
+    pub fn to_json(&self) -> String {
+        Object::new()
+            .field("output", json::string(&self.output))
+            .field(
+                "errors",
+                json::array(self.errors.iter().map(|e| json::string(e))),
+            )
+            .build()
+    }
+}
+
+/// Runs `source` in a fresh session, capturing what it prints and any
+/// errors instead of writing them out.
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+pub fn run(source: &str) -> RunOutput {
+    let host = Rc::new(CaptureHost::new());
+    let lox = Lox::with_interpreter(Interpreter::with_host(host.clone()));
+    let errors = match lox.run(source) {
+        Ok(_) => vec![],
+        // Calling `exit` just stops the program.
+        Err(e)
+            if e.downcast_ref::<RuntimeError>()
+                .is_some_and(|e| e.exit.is_some()) =>
+        {
+            vec![]
+        }
+        Err(e) => match diagnostic::from_error(&e) {
+            Some(diagnostics) => diagnostics
+                .iter()
+                .map(|d| d.render(source, false))
+                .collect(),
+            None => vec![e.to_string()],
+        },
+    };
+
+    RunOutput {
+        output: host.take_stdout(),
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let result = run("print 1 + 2;\nprint \"a\" + nil;");
+        assert_eq!(result.output, "3\n");
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].starts_with("error[E0301]: "));
+        assert_eq!(run("print 1; exit(3); print 2;").output, "1\n");
+        assert!(run("exit(3);").errors.is_empty());
+        assert_eq!(
+            run("print \"hi\";").to_json(),
+            r#"{"output":"hi\n","errors":[]}"#
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Counts how often each function is called and how long it runs for.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::clock::{Clock, MonotonicClock};
+use crate::interpreter::{ExecutionHook, Interpreter, Types};
+
+/// What's been measured about one function.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub calls: usize,
+    /// Time spent in calls to the function, including the functions it
+    /// called. Time in a recursive call is only counted once.
+    pub total: Duration,
+    /// Time spent running the function's own code.
+    pub own: Duration,
+}
+
+/// A call that's in progress.
+struct Active {
+    name: String,
+    start: Duration,
+    /// Time spent in the functions it's called so far.
+    children: Duration,
+}
+
+/// An [`ExecutionHook`] which profiles the Lox functions and natives called
+/// while it's installed.
+pub struct Profiler<C = MonotonicClock> {
+    clock: C,
+    stack: RefCell<Vec<Active>>,
+    profiles: RefCell<HashMap<String, Profile>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::with_clock(MonotonicClock::new())
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock> Profiler<C> {
+    pub fn with_clock(clock: C) -> Self {
+        Profiler {
+            clock,
+            stack: RefCell::new(Vec::new()),
+            profiles: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Each function that's been called with its profile, the one that spent
+    /// the most time running its own code first.
+    pub fn profiles(&self) -> Vec<(String, Profile)> {
+        let mut profiles: Vec<_> = self
+            .profiles
+            .borrow()
+            .iter()
+            .map(|(name, profile)| (name.clone(), *profile))
+            .collect();
+        profiles
+            .sort_by(|(a_name, a), (b_name, b)| b.own.cmp(&a.own).then_with(|| a_name.cmp(b_name)));
+        profiles
+    }
+
+    /// The profiles as a table, one function per line.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{:<20} {:>8} {:>12} {:>12}\n",
+            "function", "calls", "total", "self"
+        );
+        for (name, profile) in self.profiles() {
+            let _ = writeln!(
+                report,
+                "{:<20} {:>8} {:>12.3?} {:>12.3?}",
+                name, profile.calls, profile.total, profile.own
+            );
+        }
+        report
+    }
+}
+
+impl<'a, C: Clock> ExecutionHook<'a> for Profiler<C> {
+    fn on_call(&self, _interpreter: &Interpreter<'a>, name: &str, _arguments: &[Types<'a>]) {
+        self.stack.borrow_mut().push(Active {
+            name: name.to_string(),
+            start: self.clock.elapsed(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn on_return(&self, _interpreter: &Interpreter<'a>, _name: &str, _value: Option<&Types<'a>>) {
+        let mut stack = self.stack.borrow_mut();
+        let call = match stack.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let elapsed = self.clock.elapsed().saturating_sub(call.start);
+        let recursive = stack.iter().any(|caller| caller.name == call.name);
+        if let Some(caller) = stack.last_mut() {
+            caller.children += elapsed;
+        }
+
+        let mut profiles = self.profiles.borrow_mut();
+        let profile = profiles.entry(call.name).or_default();
+        profile.calls += 1;
+        profile.own += elapsed.saturating_sub(call.children);
+        if !recursive {
+            profile.total += elapsed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Lox;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A clock that moves on by a millisecond each time it's read.
+    #[derive(Default)]
+    struct Ticker(Cell<u64>);
+
+    impl Clock for Ticker {
+        fn elapsed(&self) -> Duration {
+            self.0.set(self.0.get() + 1);
+            Duration::from_millis(self.0.get())
+        }
+    }
+
+    #[test]
+    fn test_profiler() {
+        let profiler = Rc::new(Profiler::with_clock(Ticker::default()));
+        let lox = Lox::new();
+        lox.interpreter().set_hook(profiler.clone());
+        lox.run("fun f(n) { if (n > 0) f(n - 1); clock(); } f(1);")
+            .unwrap();
+
+        let ms = Duration::from_millis;
+        // f(1) [1 f(0) [2 clock [3 4] 5] clock [6 7] 8]
+        assert_eq!(
+            profiler.profiles(),
+            vec![
+                (
+                    "f".to_string(),
+                    Profile {
+                        calls: 2,
+                        total: ms(7),
+                        own: ms(5),
+                    }
+                ),
+                (
+                    "clock".to_string(),
+                    Profile {
+                        calls: 2,
+                        total: ms(2),
+                        own: ms(2),
+                    }
+                ),
+            ]
+        );
+        assert!(profiler.report().starts_with("function"));
+    }
+}
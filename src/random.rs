@@ -0,0 +1,67 @@
+//! Pseudo-random numbers for the `random` and `randomInt` natives.
+
+use std::cell::Cell;
+
+/// A small, fast generator (SplitMix64). It's nowhere near good enough for
+/// cryptography, but plenty for games and simulations, and the same seed
+/// always gives the same numbers.
+pub struct Rng {
+    state: Cell<u64>,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng {
+            state: Cell::new(seed),
+        }
+    }
+
+    pub fn seed(&self, seed: u64) {
+        self.state.set(seed);
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        let state = self.state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.state.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in [0, 1).
+    pub fn next_f64(&self) -> f64 {
+        // The top 53 bits fill a double's mantissa exactly.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer from `low` to `high`, including both.
+    pub fn between(&self, low: i64, high: i64) -> i64 {
+        let span = high.wrapping_sub(low) as u64 as u128 + 1;
+        // Multiplying rather than taking a remainder keeps the bias tiny
+        // without looping.
+        let offset = (self.next_u64() as u128 * span) >> 64;
+        low.wrapping_add(offset as i64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let (a, b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+            let x = a.next_f64();
+            assert!((0.0..1.0).contains(&x));
+            b.next_f64();
+            let n = a.between(-2, 3);
+            assert!((-2..=3).contains(&n));
+            b.between(-2, 3);
+        }
+        assert_eq!(a.between(7, 7), 7);
+        a.between(i64::MIN, i64::MAX);
+    }
+}
@@ -0,0 +1,850 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use thiserror::Error;
+
+use crate::ast::{
+    walk_expr, walk_stmt, Ast, ExprId, Expression, FunctionDeclaration, Slot, Stmt, VisitorMut,
+};
+use crate::diagnostic::{codes, Diagnostic, Severity};
+use crate::scanner::Token;
+
+#[derive(Error, Debug)]
+pub struct ResolverError {
+    code: &'static str,
+    message: String,
+    line: usize,
+    column: usize,
+    lexeme: String,
+    start: usize,
+    end: usize,
+}
+
+impl ResolverError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            code: self.code,
+            severity: Severity::Error,
+            message: self.message.clone(),
+            line: self.line,
+            column: self.column,
+            start: self.start,
+            end: self.end,
+            notes: vec![],
+            input: None,
+        }
+    }
+}
+
+impl Display for ResolverError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        writeln!(
+            f,
+            "[line {}, column {}] Error {}: {}",
+            self.line, self.column, self.lexeme, self.message
+        )
+    }
+}
+
+/// Every error found by [`Resolver::resolve_all`], in source order.
+#[derive(Error, Debug)]
+pub struct ResolverErrors(pub Vec<ResolverError>);
+
+impl Display for ResolverErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        for error in &self.0 {
+            write!(f, "{}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Something suspicious but legal found while resolving, which is reported
+/// without stopping the program from running.
+#[derive(Debug)]
+pub struct Warning {
+    pub code: &'static str,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Warning {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            code: self.code,
+            severity: Severity::Warning,
+            message: self.message.clone(),
+            line: self.line,
+            column: self.column,
+            start: self.start,
+            end: self.end,
+            notes: vec![],
+            input: None,
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        writeln!(
+            f,
+            "[line {}, column {}] Warning: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// A local variable or function, remembered until its scope ends so it can be
+/// reported if nothing ever reads it.
+struct Local {
+    name: String,
+    kind: &'static str,
+    line: usize,
+    column: usize,
+    start: usize,
+    end: usize,
+    used: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+/// A local variable declared in one of the resolver's scopes.
+struct Variable {
+    /// Whether its initializer has finished resolving.
+    defined: bool,
+    /// Its position among the variables declared in its scope, which is
+    /// where the interpreter stores it.
+    index: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Walks the syntax tree once before it's run, recording how many scopes
+/// away each local variable reference is from its declaration and rejecting
+/// programs that are statically invalid.
+pub struct Resolver<'r, 'a> {
+    /// Where the expressions in the statements being resolved live.
+    ast: &'r Ast<'a>,
+    /// One map per block scope, from variable name to what's known about
+    /// the variable. Globals aren't tracked.
+    scopes: Vec<HashMap<String, Variable>>,
+    /// The names declared with `const` at the top level, followed by those
+    /// in each scope of `scopes`.
+    constants: Vec<HashSet<String>>,
+    /// The locals declared in each scope of `scopes` that should be warned
+    /// about if they're never read.
+    locals: Vec<Vec<Local>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    /// How many loops enclose the code being resolved, within the current
+    /// function.
+    loop_depth: usize,
+    warnings: Vec<Warning>,
+    /// The first error in the top-level statement being resolved. Once
+    /// there is one, the rest of the statement is skipped.
+    error: Option<ResolverError>,
+}
+
+impl<'r, 'a> Resolver<'r, 'a> {
+    pub fn new(ast: &'r Ast<'a>) -> Self {
+        Resolver {
+            ast,
+            scopes: vec![],
+            constants: vec![HashSet::new()],
+            locals: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loop_depth: 0,
+            warnings: vec![],
+            error: None,
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt<'a>]) -> anyhow::Result<()> {
+        let ast = self.ast;
+        self.resolve_statements(ast, statements);
+        self.take_error()
+    }
+
+    /// Resolves a program like [`Resolver::resolve`], but carries on after a
+    /// top-level statement with an error so that the errors in the ones after
+    /// it are found too. Fails with [`ResolverErrors`].
+    pub fn resolve_all(&mut self, statements: &[Stmt<'a>]) -> anyhow::Result<()> {
+        let ast = self.ast;
+        let mut errors = vec![];
+        for statement in statements {
+            self.visit_stmt(ast, statement);
+            errors.extend(self.error.take());
+        }
+
+        if !errors.is_empty() {
+            return Err(ResolverErrors(errors).into());
+        }
+        self.check_reachable(statements);
+        Ok(())
+    }
+
+    /// Takes the warnings found so far.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Resolves an imported module. Its top-level declarations are locals of
+    /// a scope wrapping the whole module rather than globals.
+    pub fn resolve_module(&mut self, statements: &[Stmt<'a>]) -> anyhow::Result<()> {
+        let ast = self.ast;
+        self.begin_scope();
+        self.resolve_statements(ast, statements);
+        // The module's declarations are read by whoever imports it.
+        if let Some(locals) = self.locals.last_mut() {
+            locals.clear();
+        }
+        self.end_scope();
+        self.take_error()
+    }
+
+    pub fn resolve_expression(&mut self, expr: ExprId) -> anyhow::Result<()> {
+        let ast = self.ast;
+        self.visit_expr(ast, expr);
+        self.take_error()
+    }
+
+    /// Fails with the error found while resolving, if there was one.
+    fn take_error(&mut self) -> anyhow::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves a list of statements, like a block's, in the current scope.
+    fn resolve_statements(&mut self, ast: &Ast<'a>, statements: &[Stmt<'a>]) {
+        for statement in statements {
+            self.visit_stmt(ast, statement);
+        }
+        if self.error.is_none() {
+            self.check_reachable(statements);
+        }
+    }
+
+    /// Warns if any of `statements` comes after one that always jumps away.
+    fn check_reachable(&mut self, statements: &[Stmt]) {
+        let mut exits =
+            statements
+                .iter()
+                .enumerate()
+                .filter_map(|(i, statement)| match statement {
+                    Stmt::Break { keyword, .. }
+                    | Stmt::Continue { keyword, .. }
+                    | Stmt::Return { keyword, .. }
+                    | Stmt::Throw { keyword, .. } => Some((i, keyword)),
+                    _ => None,
+                });
+
+        if let Some((position, keyword)) = exits.next() {
+            if position + 1 < statements.len() {
+                self.warn(
+                    keyword,
+                    codes::UNREACHABLE_CODE,
+                    &format!("Code after '{}' is unreachable.", keyword.lexeme),
+                );
+            }
+        }
+    }
+
+    fn resolve_function(
+        &mut self,
+        ast: &Ast<'a>,
+        function: &FunctionDeclaration<'a>,
+        function_type: FunctionType,
+    ) {
+        let enclosing_function = std::mem::replace(&mut self.current_function, function_type);
+        // A loop outside the function can't be broken out of from inside it.
+        let enclosing_loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+
+        self.begin_scope();
+        for param in &function.params {
+            if let Err(error) = self.declare(param) {
+                self.fail(error);
+                break;
+            }
+            self.define(param);
+        }
+        self.resolve_statements(ast, &function.body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loop_depth;
+    }
+
+    fn resolve_local(&self, name: &Token, slot: &Cell<Option<Slot>>) {
+        slot.set(
+            self.scopes
+                .iter()
+                .rev()
+                .enumerate()
+                .find_map(|(depth, scope)| {
+                    let variable = scope.get(name.lexeme)?;
+                    Some(Slot {
+                        depth,
+                        index: variable.index,
+                    })
+                }),
+        );
+    }
+
+    /// Remembers a local declaration so it's reported if it's never read.
+    /// Names starting with an underscore are meant to go unused.
+    fn track(&mut self, name: &Token, kind: &'static str) {
+        if name.lexeme.starts_with('_') {
+            return;
+        }
+
+        if let Some(locals) = self.locals.last_mut() {
+            locals.push(Local {
+                name: name.lexeme.to_string(),
+                kind,
+                line: name.line,
+                column: name.column,
+                start: name.start,
+                end: name.end,
+                used: false,
+            });
+        }
+    }
+
+    /// Marks the local `name` resolved to as read.
+    fn mark_used(&mut self, name: &Token, slot: Option<Slot>) {
+        if let Some(Slot {
+            depth: distance, ..
+        }) = slot
+        {
+            let scope = self.locals.len() - 1 - distance;
+            if let Some(local) = self.locals[scope]
+                .iter_mut()
+                .find(|local| local.name == name.lexeme)
+            {
+                local.used = true;
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+        self.constants.push(HashSet::new());
+        self.locals.push(vec![]);
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+        self.constants.pop();
+
+        let locals = self.locals.pop().unwrap_or_default();
+        self.warnings.extend(
+            locals
+                .into_iter()
+                .filter(|local| !local.used)
+                .map(|local| Warning {
+                    code: codes::UNUSED_LOCAL,
+                    message: format!("local {} '{}' is never used.", local.kind, local.name),
+                    line: local.line,
+                    column: local.column,
+                    start: local.start,
+                    end: local.end,
+                }),
+        );
+    }
+
+    fn declare(&mut self, name: &Token) -> Result<(), ResolverError> {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(name.lexeme) {
+                return Err(Self::error(
+                    name,
+                    codes::ALREADY_DECLARED,
+                    "Already a variable with this name in this scope.",
+                ));
+            }
+
+            let index = scope.len();
+            scope.insert(
+                name.lexeme.to_string(),
+                Variable {
+                    defined: false,
+                    index,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(variable) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(name.lexeme))
+        {
+            variable.defined = true;
+        }
+    }
+
+    /// Declares `name` and defines it straight away, or fails if it can't be
+    /// declared.
+    fn declare_and_define(&mut self, name: &Token) -> bool {
+        match self.declare(name) {
+            Ok(()) => {
+                self.define(name);
+                true
+            }
+            Err(error) => {
+                self.fail(error);
+                false
+            }
+        }
+    }
+
+    /// Declares and defines a variable the interpreter adds to a scope on its
+    /// own, like `this`.
+    fn declare_implicit(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            let index = scope.len();
+            scope.insert(
+                name.to_string(),
+                Variable {
+                    defined: true,
+                    index,
+                },
+            );
+        }
+    }
+
+    /// Records whether the variable just declared as `name` is a constant,
+    /// which a variable declared later with the same name may change.
+    fn set_constant(&mut self, name: &Token, is_const: bool) {
+        if let Some(scope_constants) = self.constants.last_mut() {
+            if is_const {
+                scope_constants.insert(name.lexeme.to_string());
+            } else {
+                scope_constants.remove(name.lexeme);
+            }
+        }
+    }
+
+    fn warn(&mut self, token: &Token, code: &'static str, message: &str) {
+        self.warnings.push(Warning {
+            code,
+            message: message.to_string(),
+            line: token.line,
+            column: token.column,
+            start: token.start,
+            end: token.end,
+        });
+    }
+
+    /// Records `error`, unless the statement being resolved already has one.
+    fn fail(&mut self, error: ResolverError) {
+        self.error.get_or_insert(error);
+    }
+
+    fn error(token: &Token, code: &'static str, message: &str) -> ResolverError {
+        ResolverError {
+            code,
+            message: message.to_string(),
+            line: token.line,
+            column: token.column,
+            lexeme: format!("at '{}'", token.lexeme),
+            start: token.start,
+            end: token.end,
+        }
+    }
+}
+
+impl<'a> VisitorMut<'a> for Resolver<'_, 'a> {
+    fn visit_expr(&mut self, ast: &Ast<'a>, e: ExprId) {
+        if self.error.is_some() {
+            return;
+        }
+
+        match &ast[e] {
+            Expression::Assign { name, value, slot } => {
+                self.visit_expr(ast, *value);
+                self.resolve_local(name, slot);
+
+                let scope = match slot.get() {
+                    Some(slot) => self.constants.len() - 1 - slot.depth,
+                    None => 0,
+                };
+                if self.constants[scope].contains(name.lexeme) {
+                    self.fail(Self::error(
+                        name,
+                        codes::ASSIGN_TO_CONSTANT,
+                        "Can't assign to a constant.",
+                    ));
+                }
+            }
+            Expression::Function { declaration } => {
+                self.resolve_function(ast, declaration, FunctionType::Function)
+            }
+            Expression::Super { keyword, slot, .. } => match self.current_class {
+                ClassType::None => self.fail(Self::error(
+                    keyword,
+                    codes::SUPER_OUTSIDE_CLASS,
+                    "Can't use 'super' outside of a class.",
+                )),
+                ClassType::Class => self.fail(Self::error(
+                    keyword,
+                    codes::SUPER_WITHOUT_SUPERCLASS,
+                    "Can't use 'super' in a class with no superclass.",
+                )),
+                ClassType::Subclass => self.resolve_local(keyword, slot),
+            },
+            Expression::This { keyword, slot } => {
+                if self.current_class == ClassType::None {
+                    return self.fail(Self::error(
+                        keyword,
+                        codes::THIS_OUTSIDE_CLASS,
+                        "Can't use 'this' outside of a class.",
+                    ));
+                }
+
+                self.resolve_local(keyword, slot);
+            }
+            Expression::Variable { name, slot } => {
+                if let Some(false) = self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.get(name.lexeme))
+                    .map(|variable| variable.defined)
+                {
+                    return self.fail(Self::error(
+                        name,
+                        codes::READ_IN_OWN_INITIALIZER,
+                        "Can't read local variable in its own initializer.",
+                    ));
+                }
+
+                self.resolve_local(name, slot);
+                self.mark_used(name, slot.get());
+            }
+            _ => walk_expr(self, ast, e),
+        }
+    }
+
+    fn visit_stmt(&mut self, ast: &Ast<'a>, stmt: &Stmt<'a>) {
+        if self.error.is_some() {
+            return;
+        }
+
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                self.begin_scope();
+                self.resolve_statements(ast, statements);
+                self.end_scope();
+            }
+            Stmt::Break { keyword, .. } => {
+                if self.loop_depth == 0 {
+                    self.fail(Self::error(
+                        keyword,
+                        codes::BREAK_OUTSIDE_LOOP,
+                        "Can't use 'break' outside of a loop.",
+                    ));
+                }
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                mixins,
+                methods,
+                class_methods,
+                ..
+            } => {
+                if !self.declare_and_define(name) {
+                    return;
+                }
+
+                let mut seen = vec![];
+                for mixin in mixins {
+                    if let Expression::Variable {
+                        name: mixin_name, ..
+                    } = &ast[*mixin]
+                    {
+                        if mixin_name.lexeme == name.lexeme {
+                            return self.fail(Self::error(
+                                mixin_name,
+                                codes::MIX_IN_SELF,
+                                "A class can't mix in itself.",
+                            ));
+                        }
+                        if seen.contains(&mixin_name.lexeme) {
+                            return self.fail(Self::error(
+                                mixin_name,
+                                codes::DUPLICATE_MIXIN,
+                                "A mixin can only be listed once.",
+                            ));
+                        }
+                        seen.push(mixin_name.lexeme);
+                    }
+
+                    self.visit_expr(ast, *mixin);
+                }
+
+                if let Some(superclass) = superclass {
+                    if let Expression::Variable {
+                        name: superclass_name,
+                        ..
+                    } = &ast[*superclass]
+                    {
+                        if superclass_name.lexeme == name.lexeme {
+                            return self.fail(Self::error(
+                                superclass_name,
+                                codes::INHERIT_FROM_SELF,
+                                "A class can't inherit from itself.",
+                            ));
+                        }
+                    }
+                }
+
+                let enclosing_class = std::mem::replace(&mut self.current_class, ClassType::Class);
+                if let Some(superclass) = superclass {
+                    self.current_class = ClassType::Subclass;
+                    self.visit_expr(ast, *superclass);
+
+                    self.begin_scope();
+                    self.declare_implicit("super");
+                }
+
+                self.begin_scope();
+                self.declare_implicit("this");
+
+                for method in methods {
+                    let function_type = if method.name.lexeme == "init" {
+                        FunctionType::Initializer
+                    } else {
+                        FunctionType::Method
+                    };
+                    self.resolve_function(ast, method, function_type);
+                }
+                // In a class method `this` is the class itself.
+                for method in class_methods {
+                    self.resolve_function(ast, method, FunctionType::Method);
+                }
+
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.current_class = enclosing_class;
+            }
+            Stmt::Continue { keyword, .. } => {
+                if self.loop_depth == 0 {
+                    self.fail(Self::error(
+                        keyword,
+                        codes::CONTINUE_OUTSIDE_LOOP,
+                        "Can't use 'continue' outside of a loop.",
+                    ));
+                }
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.visit_expr(ast, *iterable);
+
+                // The loop variable gets a fresh scope for each iteration.
+                self.begin_scope();
+                self.loop_depth += 1;
+                if self.declare_and_define(name) {
+                    self.visit_stmt(ast, body);
+                }
+                self.loop_depth -= 1;
+                self.end_scope();
+            }
+            Stmt::Function { declaration, .. } => {
+                if self.declare_and_define(&declaration.name) {
+                    self.track(&declaration.name, "function");
+                    self.resolve_function(ast, declaration, FunctionType::Function);
+                }
+            }
+            Stmt::Return { keyword, value, .. } => {
+                if self.current_function == FunctionType::None {
+                    return self.fail(Self::error(
+                        keyword,
+                        codes::TOP_LEVEL_RETURN,
+                        "Can't return from top-level code.",
+                    ));
+                }
+
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        return self.fail(Self::error(
+                            keyword,
+                            codes::RETURN_FROM_INITIALIZER,
+                            "Can't return a value from an initializer.",
+                        ));
+                    }
+
+                    self.visit_expr(ast, *value);
+                }
+            }
+            // A plain import defines globals, which aren't tracked.
+            Stmt::Import { alias, .. } => {
+                if let Some(alias) = alias {
+                    self.declare_and_define(alias);
+                }
+            }
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                self.begin_scope();
+                self.resolve_statements(ast, body);
+                self.end_scope();
+
+                self.begin_scope();
+                if self.declare_and_define(name) {
+                    self.resolve_statements(ast, handler);
+                }
+                self.end_scope();
+            }
+            Stmt::Var {
+                name,
+                initializer,
+                is_const,
+                ..
+            } => {
+                if let Err(error) = self.declare(name) {
+                    return self.fail(error);
+                }
+                if let Some(initializer) = initializer {
+                    self.visit_expr(ast, *initializer);
+                }
+                self.define(name);
+                self.track(name, "variable");
+                self.set_constant(name, *is_const);
+            }
+            Stmt::Destructure {
+                names,
+                initializer,
+                is_const,
+                ..
+            } => {
+                for name in names {
+                    if let Err(error) = self.declare(name) {
+                        return self.fail(error);
+                    }
+                }
+                self.visit_expr(ast, *initializer);
+                for name in names {
+                    self.define(name);
+                    self.track(name, "variable");
+                    self.set_constant(name, *is_const);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                self.visit_expr(ast, *condition);
+                self.loop_depth += 1;
+                self.visit_stmt(ast, body);
+                self.loop_depth -= 1;
+
+                if let Some(increment) = increment {
+                    self.visit_expr(ast, *increment);
+                }
+            }
+            Stmt::Expression { .. } | Stmt::If { .. } | Stmt::Print { .. } | Stmt::Throw { .. } => {
+                walk_stmt(self, ast, stmt)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_slots_per_expression() {
+        // The two `a`s are the same expression in different scopes, which
+        // have to get different slots.
+        let source = "{ var a = 1; { var b = 2; var a = 3; print a; } print a; }";
+        let mut scanner = Scanner::new(source);
+        let parser = Parser::new(scanner.scan_tokens().unwrap());
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        Resolver::new(&ast).resolve(&statements).unwrap();
+
+        let slots: Vec<Option<Slot>> = ast
+            .ids()
+            .filter_map(|id| match &ast[id] {
+                Expression::Variable { name, slot } if name.lexeme == "a" => Some(slot.get()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            slots,
+            [
+                Some(Slot { depth: 0, index: 1 }),
+                Some(Slot { depth: 0, index: 0 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors_leave_no_state_behind() {
+        // Each error is in the middle of a function, loop or class, which
+        // must all be left behind for the statements after it.
+        let source = "fun f() { while (true) { var a; var a; } }\nbreak;\n\
+            class A { m() { return super.m(); } }\nprint this;\nreturn 1;";
+        let mut scanner = Scanner::new(source);
+        let parser = Parser::new(scanner.scan_tokens().unwrap());
+        let statements = parser.parse().unwrap();
+        let ast = parser.into_ast();
+        let mut resolver = Resolver::new(&ast);
+        let errors = resolver.resolve_all(&statements).unwrap_err();
+
+        let codes: Vec<&str> = errors
+            .downcast_ref::<ResolverErrors>()
+            .unwrap()
+            .0
+            .iter()
+            .map(|error| error.code)
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                codes::ALREADY_DECLARED,
+                codes::BREAK_OUTSIDE_LOOP,
+                codes::SUPER_WITHOUT_SUPERCLASS,
+                codes::THIS_OUTSIDE_CLASS,
+                codes::TOP_LEVEL_RETURN,
+            ]
+        );
+    }
+}
@@ -1,44 +1,156 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::iter::FromIterator;
 
-use crate::error;
+use crate::diagnostic::{codes, Diagnostic, Severity};
+use crate::symbol::Symbol;
 use anyhow::Result;
 use once_cell::unsync::Lazy;
+use thiserror::Error;
+use unicode_xid::UnicodeXID;
 use TokenType::*;
 
+#[derive(Error, Debug)]
+pub struct ScannerError {
+    code: &'static str,
+    message: String,
+    line: usize,
+    column: usize,
+    start: usize,
+    end: usize,
+}
+
+impl ScannerError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            code: self.code,
+            severity: Severity::Error,
+            message: self.message.clone(),
+            line: self.line,
+            column: self.column,
+            start: self.start,
+            end: self.end,
+            notes: vec![],
+            input: None,
+        }
+    }
+}
+
+impl Display for ScannerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        writeln!(
+            f,
+            "[line {}, column {}] Error: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// Every error found while scanning a program, in source order.
+#[derive(Error, Debug)]
+pub struct ScannerErrors(pub Vec<ScannerError>);
+
+impl Display for ScannerErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        for error in &self.0 {
+            write!(f, "{}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Scanner<'a> {
     source: &'a str,
     tokens: Vec<Token<'a>>,
+    /// Byte offset of the first character of the token being scanned.
     start: usize,
+    /// Byte offset of the next character to be consumed.
     current: usize,
     line: usize,
-    has_error: bool,
+    /// Byte offset of the first character of the current line.
+    line_start: usize,
+    /// 1-based column of the token currently being scanned.
+    column: usize,
+    errors: Vec<ScannerError>,
+    comments: Vec<Comment<'a>>,
+    /// Added to every byte offset recorded, for source that's one part of a
+    /// larger buffer, like one REPL input among many.
+    offset: usize,
+}
+
+/// A comment, which the parser never sees but tools that rewrite source,
+/// like the formatter, need to keep.
+#[derive(Debug, Clone)]
+pub struct Comment<'a> {
+    /// The whole comment, including its `//` or `/* */`.
+    pub text: &'a str,
+    /// The line the comment starts on.
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
 impl<'a> Scanner<'a> {
-    pub fn new(source: &'a str) -> Scanner {
+    pub fn new(source: &'a str) -> Scanner<'a> {
         Scanner {
             source,
             tokens: vec![],
             start: 0,
             current: 0,
             line: 1,
-            has_error: false,
+            line_start: 0,
+            column: 1,
+            errors: vec![],
+            comments: vec![],
+            offset: 0,
         }
     }
 
+    /// A scanner for `source` as if it started `offset` bytes into a larger
+    /// buffer. Lines and columns still count from the start of `source`.
+    pub fn with_offset(source: &'a str, offset: usize) -> Scanner<'a> {
+        Scanner {
+            offset,
+            ..Scanner::new(source)
+        }
+    }
+
+    /// The tokens scanned so far. After [`Scanner::scan_tokens`] fails,
+    /// these are every token that could be scanned, still ending with
+    /// [`TokenType::Eof`], so a parser can look for more errors in them.
+    pub fn tokens(&self) -> &[Token<'a>] {
+        &self.tokens
+    }
+
+    /// The comments skipped over by [`Scanner::scan_tokens`], in source order.
+    pub fn comments(&self) -> &[Comment<'a>] {
+        &self.comments
+    }
+
     pub fn scan_tokens(&mut self) -> Result<&[Token<'a>]> {
-        while !self.is_at_end() && !self.has_error {
+        // Keep going after an error, so every mistake is reported at once.
+        while !self.is_at_end() {
             self.start = self.current;
+            self.column = self.current_column();
             self.scan_token()
         }
 
-        if self.has_error {
-            return Err(anyhow::anyhow!("error while scanning"));
+        // The end is just after the last text, so that anything missing
+        // there is reported where it was left off, not on a blank line.
+        let end = self.source.trim_end().len();
+        self.line -= self.source[end..].matches('\n').count();
+        self.line_start = self.source[..end].rfind('\n').map_or(0, |i| i + 1);
+        self.start = end;
+        self.current = end;
+        self.column = self.current_column();
+        self.add_token(Eof);
+
+        if !self.errors.is_empty() {
+            return Err(ScannerErrors(std::mem::take(&mut self.errors)).into());
         }
-
-        self.tokens.push(Token::new(Eof, "", self.line));
         Ok(&self.tokens)
     }
 
@@ -49,12 +161,40 @@ impl<'a> Scanner<'a> {
             ')' => self.add_token(RightParen),
             '{' => self.add_token(LeftBrace),
             '}' => self.add_token(RightBrace),
+            '[' => self.add_token(LeftBracket),
+            ']' => self.add_token(RightBracket),
             ',' => self.add_token(Comma),
+            '.' if self.peek() == '.' && self.peek_next() == '.' => {
+                self.advance();
+                self.advance();
+                self.add_token(Ellipsis)
+            }
             '.' => self.add_token(Dot),
-            '-' => self.add_token(Minus),
-            '+' => self.add_token(Plus),
+            '-' => {
+                if self.matching('=') {
+                    self.add_token(MinusEqual)
+                } else {
+                    self.add_token(Minus)
+                }
+            }
+            '+' => {
+                if self.matching('=') {
+                    self.add_token(PlusEqual)
+                } else {
+                    self.add_token(Plus)
+                }
+            }
             ';' => self.add_token(Semicolon),
-            '*' => self.add_token(Star),
+            ':' => self.add_token(Colon),
+            '*' => {
+                if self.matching('*') {
+                    self.add_token(StarStar)
+                } else if self.matching('=') {
+                    self.add_token(StarEqual)
+                } else {
+                    self.add_token(Star)
+                }
+            }
             '!' => {
                 if self.matching('=') {
                     self.add_token(BangEqual)
@@ -65,6 +205,8 @@ impl<'a> Scanner<'a> {
             '=' => {
                 if self.matching('=') {
                     self.add_token(EqualEqual)
+                } else if self.matching('>') {
+                    self.add_token(Arrow)
                 } else {
                     self.add_token(Equal)
                 }
@@ -72,6 +214,8 @@ impl<'a> Scanner<'a> {
             '<' => {
                 if self.matching('=') {
                     self.add_token(LessEqual)
+                } else if self.matching('<') {
+                    self.add_token(LessLess)
                 } else {
                     self.add_token(Less)
                 }
@@ -79,63 +223,121 @@ impl<'a> Scanner<'a> {
             '>' => {
                 if self.matching('=') {
                     self.add_token(GreaterEqual)
+                } else if self.matching('>') {
+                    self.add_token(GreaterGreater)
                 } else {
                     self.add_token(Greater)
                 }
             }
+            '?' if self.matching('?') => self.add_token(QuestionQuestion),
+            '?' if self.matching('.') => self.add_token(QuestionDot),
+            '&' => self.add_token(Ampersand),
+            '|' => self.add_token(Pipe),
+            '^' => self.add_token(Caret),
             '/' => {
                 if self.matching('/') {
                     // A comment goes until the end of the line.
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.add_comment(self.line);
+                } else if self.matching('*') {
+                    let line = self.line;
+                    if self.block_comment() {
+                        self.add_comment(line);
+                    }
+                } else if self.matching('=') {
+                    self.add_token(SlashEqual);
                 } else {
                     self.add_token(Slash);
                 }
             }
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
             '"' => self.string(),
             _ if Self::is_digit(c) => self.number(),
             _ if Self::is_alpha(c) => self.identifier(),
             _ => {
-                error(self.line, "Unexpected character.");
-                self.has_error = true;
+                self.error(
+                    self.start,
+                    self.current,
+                    codes::UNEXPECTED_CHARACTER,
+                    "Unexpected character.",
+                );
+                self.add_token(Error);
             }
         }
     }
 
+    /// Skips a `/* ... */` comment whose opening `/*` has been consumed.
+    /// Block comments nest, so each `/*` inside needs its own `*/`.
+    /// Skips the rest of a block comment, returning whether it was closed.
+    fn block_comment(&mut self) -> bool {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.error(
+                    self.start,
+                    self.start + 2,
+                    codes::UNTERMINATED_BLOCK_COMMENT,
+                    "Unterminated block comment.",
+                );
+                return false;
+            }
+
+            match self.advance() {
+                '/' if self.matching('*') => depth += 1,
+                '*' if self.matching('/') => depth -= 1,
+                '\n' => self.new_line(),
+                _ => {}
+            }
+        }
+        true
+    }
+
     fn is_digit(c: char) -> bool {
-        ('0'..='9').contains(&c)
+        c.is_ascii_digit()
     }
 
+    /// Whether `c` can start an identifier: `_` or any Unicode `XID_Start`
+    /// character.
     fn is_alpha(c: char) -> bool {
-        ('a'..='z').contains(&c) || ('A'..='Z').contains(&c) || c == '_'
+        c == '_' || c.is_xid_start()
     }
 
     fn is_alpha_numeric(c: char) -> bool {
-        Self::is_alpha(c) || Self::is_digit(c)
+        c.is_xid_continue()
     }
 
     fn identifier(&mut self) {
         let keywords: Lazy<HashMap<&'static str, TokenType>> = Lazy::new(|| {
             HashMap::<_, _>::from_iter([
                 ("and", And),
+                ("as", As),
+                ("break", Break),
+                ("catch", Catch),
                 ("class", Class),
+                ("const", Const),
+                ("continue", Continue),
                 ("else", Else),
                 ("false", False),
                 ("for", For),
                 ("fun", Fun),
                 ("if", If),
+                ("import", Import),
+                ("in", In),
                 ("nil", Nil),
                 ("or", Or),
                 ("print", Print),
                 ("return", Return),
                 ("super", Super),
                 ("this", This),
+                ("throw", Throw),
                 ("true", True),
+                ("try", Try),
                 ("var", Var),
                 ("while", While),
+                ("with", With),
             ])
         });
 
@@ -165,70 +367,203 @@ impl<'a> Scanner<'a> {
             }
         }
 
-        self.add_token(Number {
-            number: self.source[self.start..self.current].parse().unwrap(),
-        })
+        match self.source[self.start..self.current].parse() {
+            Ok(number) => self.add_token(Number { number }),
+            Err(_) => {
+                self.error(
+                    self.start,
+                    self.current,
+                    codes::INVALID_NUMBER,
+                    "Invalid number.",
+                );
+                self.add_token(Error);
+            }
+        }
     }
 
     fn string(&mut self) {
+        // Only allocated once an escape sequence turns up; until then the
+        // literal can borrow straight from the source.
+        let mut unescaped: Option<String> = None;
+        let mut invalid_escape = false;
+
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            let offset = self.current;
+            let c = match self.advance() {
+                '\\' => {
+                    let start = self.start + 1;
+                    let buffer =
+                        unescaped.get_or_insert_with(|| self.source[start..offset].to_string());
+                    match self.escape() {
+                        Some(c) => buffer.push(c),
+                        None if !invalid_escape => {
+                            let end = self.current;
+                            self.error(
+                                offset,
+                                end,
+                                codes::INVALID_ESCAPE,
+                                "Invalid escape sequence.",
+                            );
+                            invalid_escape = true;
+                        }
+                        None => {}
+                    }
+                    continue;
+                }
+                '\n' => {
+                    self.new_line();
+                    '\n'
+                }
+                c => c,
+            };
+
+            if let Some(buffer) = &mut unescaped {
+                buffer.push(c);
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            error(self.line, "Unterminated string.");
-            self.has_error = true;
+            self.error(
+                self.start,
+                self.start + 1,
+                codes::UNTERMINATED_STRING,
+                "Unterminated string.",
+            );
+            self.add_token(Error);
             return;
         }
 
         // The closing "
         self.advance();
 
+        // A string with a bad escape in it is still a string, with the
+        // escape left out.
+
         // Trim the surrounding quotes.
-        let value = &self.source[self.start + 1..self.current - 1];
-        self.add_token(StringLiteral { literal: value });
+        let literal = match unescaped {
+            Some(value) => Cow::Owned(value),
+            None => Cow::Borrowed(&self.source[self.start + 1..self.current - 1]),
+        };
+        self.add_token(StringLiteral { literal });
     }
 
-    fn matching(&mut self, expected: char) -> bool {
+    /// Reads the rest of an escape sequence whose backslash has just been
+    /// consumed, returning the character it stands for.
+    fn escape(&mut self) -> Option<char> {
         if self.is_at_end() {
-            return false;
+            return None;
+        }
+
+        match self.advance() {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            'u' => {
+                // \u{...} with one to six hex digits.
+                if !self.matching('{') {
+                    return None;
+                }
+
+                let digits_start = self.current;
+                while self.peek().is_ascii_hexdigit() {
+                    self.advance();
+                }
+                let digits = &self.source[digits_start..self.current];
+
+                if !self.matching('}') || digits.is_empty() || digits.len() > 6 {
+                    return None;
+                }
+
+                u32::from_str_radix(digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+            }
+            _ => None,
         }
-        if self.source.chars().nth(self.current) != Some(expected) {
+    }
+
+    fn matching(&mut self, expected: char) -> bool {
+        if self.peek() != expected || self.is_at_end() {
             return false;
         }
 
-        self.current += 1;
+        self.current += expected.len_utf8();
         true
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-
-        return self.source.chars().nth(self.current).unwrap();
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-
-        self.source.chars().nth(self.current + 1).unwrap()
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+        let c = self.peek();
+        self.current += c.len_utf8();
         c
     }
 
+    /// The column `start` is at, counted in characters rather than bytes.
+    fn current_column(&self) -> usize {
+        self.column_at(self.start)
+    }
+
+    /// The column of the character at byte `offset` on the current line.
+    fn column_at(&self, offset: usize) -> usize {
+        self.source[self.line_start..offset].chars().count() + 1
+    }
+
+    /// Called just after consuming a newline.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
     fn add_token(&mut self, token_type: TokenType<'a>) {
         let text = &self.source[self.start..self.current];
-        self.tokens.push(Token::new(token_type, text, self.line));
+        let symbol = match token_type {
+            Identifier => Symbol::intern(text),
+            _ => Symbol::default(),
+        };
+        self.tokens.push(Token {
+            token_type,
+            lexeme: text,
+            symbol,
+            line: self.line,
+            column: self.column,
+            start: self.offset + self.start,
+            end: self.offset + self.current,
+        });
+    }
+
+    fn add_comment(&mut self, line: usize) {
+        self.comments.push(Comment {
+            text: &self.source[self.start..self.current],
+            line,
+            start: self.offset + self.start,
+            end: self.offset + self.current,
+        });
+    }
+
+    /// Records an error about the source between byte offsets `start` and
+    /// `end`. The position reported is where `start` is, which may be on an
+    /// earlier line for a string or comment that never ends.
+    fn error(&mut self, start: usize, end: usize, code: &'static str, message: &str) {
+        let start_line = self.source[..start].matches('\n').count() + 1;
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        self.errors.push(ScannerError {
+            code,
+            message: message.to_string(),
+            line: start_line,
+            column: self.source[line_start..start].chars().count() + 1,
+            start: self.offset + start,
+            end: self.offset + end,
+        });
     }
 
     fn is_at_end(&self) -> bool {
@@ -243,13 +578,19 @@ pub enum TokenType<'a> {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
     Plus,
     Semicolon,
+    Colon,
     Slash,
     Star,
+    Ampersand,
+    Pipe,
+    Caret,
 
     // One or two character tokens.
     Bang,
@@ -260,30 +601,58 @@ pub enum TokenType<'a> {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    StarStar,
+    Arrow,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    QuestionQuestion,
+    QuestionDot,
+    Ellipsis,
 
     // Literals
     Identifier,
-    StringLiteral { literal: &'a str },
-    Number { number: f64 },
+    StringLiteral {
+        literal: Cow<'a, str>,
+    },
+    Number {
+        number: f64,
+    },
 
     // Keywords.
     And,
+    As,
+    Break,
+    Catch,
     Class,
+    Const,
+    Continue,
     Else,
     False,
     Fun,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
+    With,
 
+    /// Text the scanner reported an error about, kept in place so that the
+    /// parser can step over it without reporting it again.
+    Error,
     Eof,
 }
 
@@ -291,15 +660,202 @@ pub enum TokenType<'a> {
 pub struct Token<'a> {
     pub token_type: TokenType<'a>,
     pub lexeme: &'a str,
+    /// The interned lexeme of an identifier, which globals are looked up by.
+    /// Other tokens have the default symbol.
+    pub symbol: Symbol,
     pub line: usize,
+    /// 1-based column of the token's first character within its line,
+    /// counted in characters.
+    pub column: usize,
+    /// Byte offset of the token's first character in the source.
+    pub start: usize,
+    /// Byte offset just past the token's last character in the source.
+    pub end: usize,
 }
 
 impl<'a> Token<'a> {
+    /// Creates a token that doesn't come from the source text, so it only
+    /// knows which line it belongs to.
     pub fn new(token_type: TokenType<'a>, lexeme: &'a str, line: usize) -> Token<'a> {
         Token {
             token_type,
             lexeme,
+            symbol: Symbol::intern(lexeme),
             line,
+            column: 0,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Whether the token was scanned from `source`, rather than from some
+    /// other text or made up with [`Token::new`].
+    pub fn is_from(&self, source: &str) -> bool {
+        let range = source.as_bytes().as_ptr_range();
+        let lexeme = self.lexeme.as_ptr();
+        range.contains(&lexeme) || (self.lexeme.is_empty() && lexeme == range.end)
+    }
+}
+
+/// One line describing the token, for dumping a token stream: its position,
+/// type, and source text.
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}:{}\t{:?}\t{:?}",
+            self.line, self.column, self.token_type, self.lexeme
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unicode_source() {
+        let mut scanner = Scanner::new("var café = \"日本語\";\n  print café;");
+        let tokens = scanner.scan_tokens().unwrap();
+
+        let summary = tokens
+            .iter()
+            .map(|t| (t.lexeme, t.line, t.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("var", 1, 1),
+                ("café", 1, 5),
+                ("=", 1, 10),
+                ("\"日本語\"", 1, 12),
+                (";", 1, 17),
+                ("print", 2, 3),
+                ("café", 2, 9),
+                (";", 2, 13),
+                ("", 2, 14),
+            ]
+        );
+        assert_eq!(
+            tokens[3].token_type,
+            StringLiteral {
+                literal: Cow::Borrowed("日本語")
+            }
+        );
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let source = "var a = 1;\n  print a >= 10;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+
+        let summary = tokens
+            .iter()
+            .map(|t| (t.lexeme, t.line, t.column, t.start))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("var", 1, 1, 0),
+                ("a", 1, 5, 4),
+                ("=", 1, 7, 6),
+                ("1", 1, 9, 8),
+                (";", 1, 10, 9),
+                ("print", 2, 3, 13),
+                ("a", 2, 9, 19),
+                (">=", 2, 11, 21),
+                ("10", 2, 14, 24),
+                (";", 2, 16, 26),
+                ("", 2, 17, 27),
+            ]
+        );
+        for token in tokens {
+            assert_eq!(&source[token.start..token.end], token.lexeme);
         }
     }
+
+    #[test]
+    fn test_multibyte_lexemes() {
+        let source = "// ünïcödé\nvar s = \"😀\" + \"é\"; /* 😀 */ print s;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+
+        let lexemes = tokens.iter().map(|t| t.lexeme).collect::<Vec<_>>();
+        assert_eq!(
+            lexemes,
+            ["var", "s", "=", "\"😀\"", "+", "\"é\"", ";", "print", "s", ";", ""]
+        );
+        for token in tokens {
+            assert_eq!(&source[token.start..token.end], token.lexeme);
+        }
+        assert_eq!(
+            tokens[5].token_type,
+            StringLiteral {
+                literal: Cow::Borrowed("é")
+            }
+        );
+        assert!(Scanner::new("\"😀").scan_tokens().is_err());
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let mut scanner = Scanner::new(r#""a\tb\n\"q\" \\ \u{1F600}\u{e9}""#);
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(
+            tokens[0].token_type,
+            StringLiteral {
+                literal: Cow::Owned("a\tb\n\"q\" \\ \u{1F600}\u{e9}".to_string())
+            }
+        );
+
+        for source in [r#""\q""#, r#""\u{}""#, r#""\u{110000}""#, r#""\u12""#] {
+            assert!(Scanner::new(source).scan_tokens().is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_nested_block_comments() {
+        let mut scanner = Scanner::new("1 /* a /* b\n */ c\n */ 2 /**/ 3");
+        let tokens = scanner.scan_tokens().unwrap();
+        let summary = tokens
+            .iter()
+            .map(|t| (t.lexeme, t.line))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![("1", 1), ("2", 3), ("3", 3), ("", 3)]);
+
+        assert!(Scanner::new("/* /* */").scan_tokens().is_err());
+    }
+
+    #[test]
+    fn test_display_token() {
+        let mut scanner = Scanner::new("x = \"a\\\"b\"\n 2;");
+        let tokens = scanner.scan_tokens().unwrap();
+        let lines: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "1:1\tIdentifier\t\"x\"",
+                "1:3\tEqual\t\"=\"",
+                "1:5\tStringLiteral { literal: \"a\\\"b\" }\t\"\\\"a\\\\\\\"b\\\"\"",
+                "2:2\tNumber { number: 2.0 }\t\"2\"",
+                "2:3\tSemicolon\t\";\"",
+                "2:4\tEof\t\"\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_every_error() {
+        let error = Scanner::new("var a = @;\nprint \"\\q\" # 1;\n\"open")
+            .scan_tokens()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1, column 9] Error: Unexpected character.\n\
+             [line 2, column 8] Error: Invalid escape sequence.\n\
+             [line 2, column 12] Error: Unexpected character.\n\
+             [line 3, column 1] Error: Unterminated string.\n"
+        );
+    }
 }
@@ -0,0 +1,131 @@
+//! Natives for working with strings. Positions in a string count
+//! characters, as `for (c in s)` does, rather than bytes.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::callable::NativeFailure;
+use crate::diagnostic::codes;
+use crate::interpreter::{Interpreter, Types};
+
+type StringResult<'a> = Result<Types<'a>, NativeFailure>;
+
+pub(crate) fn define(interpreter: &Interpreter) {
+    interpreter.define_builtin("len", 1, len);
+    interpreter.define_builtin("substring", 3, substring);
+    interpreter.define_builtin("toUpper", 1, |arguments| {
+        Ok(Types::from(string(&arguments[0])?.to_uppercase()))
+    });
+    interpreter.define_builtin("toLower", 1, |arguments| {
+        Ok(Types::from(string(&arguments[0])?.to_lowercase()))
+    });
+    interpreter.define_builtin("indexOf", 2, index_of);
+    interpreter.define_builtin("split", 2, split);
+    interpreter.define_builtin("trim", 1, |arguments| {
+        Ok(Types::from(string(&arguments[0])?.trim()))
+    });
+    interpreter.define_builtin("str", 1, |arguments| {
+        Ok(Types::from(arguments[0].to_string()))
+    });
+    interpreter.define_builtin("num", 1, num);
+}
+
+fn string<'v>(value: &'v Types) -> Result<&'v str, NativeFailure> {
+    match value {
+        Types::ReturnString(s) => Ok(s),
+        _ => Err(NativeFailure::message("Expected a string.")),
+    }
+}
+
+/// `len(x)`, the number of characters in a string or elements in a list.
+fn len<'a>(arguments: &[Types<'a>]) -> StringResult<'a> {
+    let len = match &arguments[0] {
+        Types::ReturnString(s) => s.chars().count(),
+        Types::List(elements) => elements.borrow().len(),
+        Types::Map(map) => map.borrow().len(),
+        _ => {
+            return Err(NativeFailure::message(
+                "Can only take the length of a string, list or map.",
+            ))
+        }
+    };
+    Ok(Types::Number(len as f64))
+}
+
+/// `substring(s, start, end)`, the characters from `start` up to but not
+/// including `end`.
+fn substring<'a>(arguments: &[Types<'a>]) -> StringResult<'a> {
+    let s = string(&arguments[0])?;
+    let (start, end) = match (&arguments[1], &arguments[2]) {
+        (Types::Number(start), Types::Number(end))
+            if start.fract() == 0.0 && end.fract() == 0.0 =>
+        {
+            (*start, *end)
+        }
+        _ => {
+            return Err(NativeFailure::Error(
+                codes::INVALID_INDEX,
+                "Substring bounds must be whole numbers.".to_string(),
+            ))
+        }
+    };
+
+    let len = s.chars().count();
+    if start < 0.0 || end < start || end > len as f64 {
+        return Err(NativeFailure::Error(
+            codes::INDEX_OUT_OF_BOUNDS,
+            format!(
+                "Substring from {} to {} is out of bounds for a string of length {}.",
+                start, end, len
+            ),
+        ));
+    }
+
+    let substring: String = s
+        .chars()
+        .skip(start as usize)
+        .take((end - start) as usize)
+        .collect();
+    Ok(Types::from(substring))
+}
+
+/// `indexOf(s, needle)`, where `needle` first appears in `s`, or -1 if it
+/// doesn't.
+fn index_of<'a>(arguments: &[Types<'a>]) -> StringResult<'a> {
+    let (s, needle) = (string(&arguments[0])?, string(&arguments[1])?);
+    let index = match s.find(needle) {
+        Some(at) => s[..at].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(Types::Number(index))
+}
+
+/// `num(s)`, the number written in `s`, or nil if it isn't one. A number is
+/// handed back as it is.
+fn num<'a>(arguments: &[Types<'a>]) -> StringResult<'a> {
+    let s = match &arguments[0] {
+        Types::Number(n) => return Ok(Types::Number(*n)),
+        Types::ReturnString(s) => s.trim(),
+        _ => return Ok(Types::Nil),
+    };
+
+    // Rust would also read words like "inf" and "NaN", which Lox can't
+    // write as numbers.
+    let numeric = |c: char| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E');
+    if !s.chars().any(|c| c.is_ascii_digit()) || !s.chars().all(numeric) {
+        return Ok(Types::Nil);
+    }
+    Ok(s.parse().map_or(Types::Nil, Types::Number))
+}
+
+/// `split(s, separator)`, the list of the parts of `s` between each
+/// `separator`. An empty separator splits it into characters.
+fn split<'a>(arguments: &[Types<'a>]) -> StringResult<'a> {
+    let (s, separator) = (string(&arguments[0])?, string(&arguments[1])?);
+    let parts = if separator.is_empty() {
+        s.chars().map(|c| Types::from(c.to_string())).collect()
+    } else {
+        s.split(separator).map(Types::from).collect()
+    };
+    Ok(Types::List(Rc::new(RefCell::new(parts))))
+}
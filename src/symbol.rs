@@ -0,0 +1,129 @@
+//! Interned identifiers, so variables can be looked up by a small number
+//! rather than by hashing or comparing their names.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Formatter};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::{Mutex, PoisonError};
+
+use once_cell::sync::Lazy;
+
+/// An identifier the scanner has seen. Two symbols are equal exactly when
+/// their names are, and the default symbol is the empty name, which no
+/// variable has.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// Every name interned so far. Names are never freed, since programs only
+/// use so many distinct identifiers.
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+/// The names interned before any others, so that the symbols for them are
+/// known without asking the interner.
+const PREINTERNED: [&str; 3] = ["", "this", "super"];
+
+static INTERNER: Lazy<Mutex<Interner>> = Lazy::new(|| {
+    Mutex::new(Interner {
+        ids: PREINTERNED
+            .iter()
+            .enumerate()
+            .map(|(i, &name)| (name, Symbol(i as u32)))
+            .collect(),
+        names: PREINTERNED.to_vec(),
+    })
+});
+
+impl Symbol {
+    /// The name of the instance a method is bound to.
+    pub const THIS: Symbol = Symbol(1);
+    /// The name of the superclass inside its subclass's methods.
+    pub const SUPER: Symbol = Symbol(2);
+
+    pub fn intern(name: &str) -> Symbol {
+        let mut interner = INTERNER.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&symbol) = interner.ids.get(name) {
+            return symbol;
+        }
+
+        let name: &'static str = Box::leak(name.into());
+        let symbol = Symbol(interner.names.len() as u32);
+        interner.names.push(name);
+        interner.ids.insert(name, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        let interner = INTERNER.lock().unwrap_or_else(PoisonError::into_inner);
+        interner.names[self.0 as usize]
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// The hash function rustc uses for its own tables (FxHash). It's much
+/// faster than the default for small keys like symbols, but isn't resistant
+/// to keys chosen to collide, which doesn't matter for names in a program.
+#[derive(Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.add(i.into());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+pub type FxHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FxHasher>>;
+pub type FxHashSet<K> = HashSet<K, BuildHasherDefault<FxHasher>>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let a = Symbol::intern("alpha");
+        assert_eq!(a, Symbol::intern("alpha"));
+        assert_ne!(a, Symbol::intern("beta"));
+        assert_eq!(a.as_str(), "alpha");
+        assert_eq!(Symbol::default().as_str(), "");
+        assert_eq!(Symbol::intern("this"), Symbol::THIS);
+        assert_eq!(Symbol::SUPER.as_str(), "super");
+    }
+}
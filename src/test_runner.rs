@@ -0,0 +1,361 @@
+//! `jlox test`: runs test scripts written in the style of the Crafting
+//! Interpreters test suite and checks what they did against what their
+//! comments expect.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+/// Starts a compile error expected on the comment's own line.
+const EXPECT_ERROR: &str = "// Error";
+/// Starts a compile error expected on another line, like
+/// `// [line 3] Error at 'x': message`. The suite's `[java line 3]` is for
+/// jlox, which this is.
+const EXPECT_LINE_ERRORS: [&str; 2] = ["// [line ", "// [java line "];
+
+/// The exit code of a script that stopped with a runtime error.
+const RUNTIME_ERROR_EXIT: i32 = 70;
+/// The exit code of a script that didn't compile.
+const COMPILE_ERROR_EXIT: i32 = 65;
+
+/// What a test script says it should do, from comments in the style of the
+/// Crafting Interpreters test suite.
+#[derive(Debug, Default, PartialEq)]
+struct Expectations {
+    /// Lines printed, from `// expect: line`.
+    output: Vec<String>,
+    /// The line number and message of `// expect runtime error: message`.
+    runtime_error: Option<(usize, String)>,
+    /// The line number and message of each `// Error at 'x': message` or
+    /// `// [line n] Error at 'x': message`. Where on the line doesn't have
+    /// to match, since diagnostics point at it with a caret instead.
+    compile_errors: Vec<(usize, String)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+        for (i, line) in source.lines().enumerate() {
+            if let Some(at) = line.find(EXPECT) {
+                expectations
+                    .output
+                    .push(line[at + EXPECT.len()..].to_string());
+            } else if let Some(at) = line.find(EXPECT_RUNTIME_ERROR) {
+                let message = line[at + EXPECT_RUNTIME_ERROR.len()..].to_string();
+                expectations.runtime_error = Some((i + 1, message));
+            } else if let Some(error) = Self::compile_error(line, i + 1) {
+                expectations.compile_errors.push(error);
+            }
+        }
+        expectations
+    }
+
+    /// The line and message of a compile error expected by `line`, which is
+    /// line `number` of the script.
+    fn compile_error(line: &str, number: usize) -> Option<(usize, String)> {
+        let (number, error) = match EXPECT_LINE_ERRORS
+            .iter()
+            .find_map(|prefix| Some(&line[line.find(prefix)? + prefix.len()..]))
+        {
+            Some(rest) => {
+                let (number, error) = rest.split_once("] ")?;
+                (number.parse().ok()?, error.strip_prefix("Error")?)
+            }
+            None => (
+                number,
+                &line[line.find(EXPECT_ERROR)? + EXPECT_ERROR.len()..],
+            ),
+        };
+        // What follows is `: message`, or ` at 'x': message`, or
+        // ` at end: message`.
+        let message = match error.strip_prefix(" at '") {
+            Some(rest) => rest.split_once("': ")?.1,
+            None => error.split_once(": ")?.1,
+        };
+        Some((number, message.to_string()))
+    }
+
+    /// Everything about a run that doesn't match, one problem per line.
+    fn check(&self, stdout: &str, stderr: &str, exit_code: Option<i32>) -> Vec<String> {
+        let mut failures = vec![];
+
+        let output: Vec<&str> = stdout.lines().collect();
+        for (i, expected) in self.output.iter().enumerate() {
+            match output.get(i) {
+                Some(&actual) if actual == expected => {}
+                Some(actual) => failures.push(format!(
+                    "Expected output '{}' but got '{}'.",
+                    expected, actual
+                )),
+                None => failures.push(format!("Missing expected output '{}'.", expected)),
+            }
+        }
+        for extra in output.iter().skip(self.output.len()) {
+            failures.push(format!("Got output '{}' when none was expected.", extra));
+        }
+
+        if !self.compile_errors.is_empty() {
+            if exit_code != Some(COMPILE_ERROR_EXIT) {
+                failures.push(format!(
+                    "Expected exit code {} but got {}.",
+                    COMPILE_ERROR_EXIT,
+                    describe(exit_code)
+                ));
+            }
+            let errors = compile_errors(stderr);
+            for (line, message) in &self.compile_errors {
+                if !errors.contains(&(*line, message.as_str())) {
+                    failures.push(format!("Expected error '{}' on line {}.", message, line));
+                }
+            }
+            for (line, message) in errors {
+                if !self
+                    .compile_errors
+                    .iter()
+                    .any(|(l, m)| *l == line && m == message)
+                {
+                    failures.push(format!("Unexpected error '{}' on line {}.", message, line));
+                }
+            }
+            return failures;
+        }
+
+        match &self.runtime_error {
+            Some((line, message)) => {
+                if exit_code != Some(RUNTIME_ERROR_EXIT) {
+                    failures.push(format!(
+                        "Expected exit code {} but got {}.",
+                        RUNTIME_ERROR_EXIT,
+                        describe(exit_code)
+                    ));
+                }
+                if !stderr.contains(&format!(": {}", message)) {
+                    failures.push(format!("Expected runtime error '{}'.", message));
+                } else if !stderr.contains(&format!("[line {},", line)) {
+                    failures.push(format!("Expected runtime error on line {}.", line));
+                }
+            }
+            None if exit_code != Some(0) => {
+                failures.push(format!(
+                    "Expected exit code 0 but got {}.",
+                    describe(exit_code)
+                ));
+                failures.extend(stderr.lines().map(str::to_string));
+            }
+            None => {}
+        }
+
+        failures
+    }
+}
+
+/// The line and message of each error diagnostic in `stderr`, leaving out
+/// warnings.
+fn compile_errors(stderr: &str) -> Vec<(usize, &str)> {
+    let mut errors = vec![];
+    let mut lines = stderr.lines();
+    while let Some(line) = lines.next() {
+        let message = match line.strip_prefix("error[") {
+            Some(rest) => match rest.split_once("]: ") {
+                Some((_, message)) => message,
+                None => continue,
+            },
+            None => continue,
+        };
+        // The location comes next, as ` --> [line 3, column 4]`.
+        let number = lines
+            .next()
+            .and_then(|location| location.trim_start().strip_prefix("--> [line "))
+            .and_then(|location| location.split_once(',')?.0.parse().ok());
+        if let Some(number) = number {
+            errors.push((number, message));
+        }
+    }
+    errors
+}
+
+fn describe(exit_code: Option<i32>) -> String {
+    exit_code.map_or_else(|| "a signal".to_string(), |code| code.to_string())
+}
+
+/// The lines of a script with statements on them, and whether each ran,
+/// from the lcov tracefile written by `--lcov`.
+fn parse_lcov(lcov: &str) -> Vec<(usize, bool)> {
+    lcov.lines()
+        .filter_map(|line| line.strip_prefix("DA:"))
+        .filter_map(|data| {
+            let (line, hits) = data.split_once(',')?;
+            Some((line.parse().ok()?, hits.parse::<u64>().ok()? > 0))
+        })
+        .collect()
+}
+
+/// Every `.lox` file under `dir`, in a stable order.
+pub fn scripts(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("couldn't read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            found.extend(scripts(&path)?);
+        } else if path.extension().is_some_and(|e| e == "lox") {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Runs every test script under `dir` with the running interpreter, which
+/// has to be the `jlox` binary, printing each failure and a summary. With
+/// `coverage`, also reports the lines of the scripts that never ran. Returns
+/// whether they all passed.
+pub fn run(dir: &Path, coverage: bool) -> Result<bool> {
+    let interpreter = std::env::current_exe().context("couldn't find the interpreter")?;
+    let (mut passed, mut failed) = (0, 0);
+    let lcov = std::env::temp_dir().join(format!("lox-test-{}.info", std::process::id()));
+    let (mut lines, mut covered) = (0, 0);
+    let mut uncovered = vec![];
+
+    for script in scripts(dir)? {
+        let source = fs::read_to_string(&script)
+            .with_context(|| format!("couldn't read {}", script.display()))?;
+        let mut command = Command::new(&interpreter);
+        if coverage {
+            command.arg("--lcov").arg(&lcov);
+        }
+        let output = command
+            .arg(&script)
+            .output()
+            .with_context(|| format!("couldn't run {}", script.display()))?;
+
+        let failures = Expectations::parse(&source).check(
+            &String::from_utf8_lossy(&output.stdout),
+            &String::from_utf8_lossy(&output.stderr),
+            output.status.code(),
+        );
+        if failures.is_empty() {
+            passed += 1;
+        } else {
+            failed += 1;
+            println!("FAIL {}", script.display());
+            for failure in failures {
+                println!("     {}", failure);
+            }
+        }
+
+        // A script that didn't parse has no coverage to report.
+        if let Ok(report) = fs::read_to_string(&lcov) {
+            let _ = fs::remove_file(&lcov);
+            let report = parse_lcov(&report);
+            let missed: Vec<String> = report
+                .iter()
+                .filter(|(_, ran)| !ran)
+                .map(|(line, _)| line.to_string())
+                .collect();
+            lines += report.len();
+            covered += report.len() - missed.len();
+            if !missed.is_empty() {
+                uncovered.push(format!("{}: {}", script.display(), missed.join(", ")));
+            }
+        }
+    }
+
+    println!("{} passed, {} failed.", passed, failed);
+    if coverage {
+        let percentage = if lines == 0 {
+            100.0
+        } else {
+            covered as f64 * 100.0 / lines as f64
+        };
+        println!(
+            "{} of {} lines covered ({:.1}%).",
+            covered, lines, percentage
+        );
+        if !uncovered.is_empty() {
+            println!("Lines not run:");
+            for script in uncovered {
+                println!("     {}", script);
+            }
+        }
+    }
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expectations() {
+        let expectations = Expectations::parse(
+            "print 1; // expect: 1\nprint \"a\"; // expect: a\nnil(); // expect runtime error: Can only call functions and classes.\n",
+        );
+        assert_eq!(expectations.output, vec!["1", "a"]);
+        assert_eq!(
+            expectations.runtime_error,
+            Some((3, "Can only call functions and classes.".to_string()))
+        );
+
+        let stderr =
+            "error[E0303]: Can only call functions and classes.\n --> [line 3, column 4]\n";
+        assert!(expectations.check("1\na\n", stderr, Some(70)).is_empty());
+        assert_eq!(
+            expectations.check("1\nb\n", stderr, Some(70)),
+            vec!["Expected output 'a' but got 'b'."]
+        );
+        assert_eq!(
+            expectations.check("1\na\n", "", Some(0)),
+            vec![
+                "Expected exit code 70 but got 0.",
+                "Expected runtime error 'Can only call functions and classes.'."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_error_expectations() {
+        let expectations = Expectations::parse(
+            "print 1 +; // Error at ';': Expect expression.\n\
+             var 1 = 2; // [line 2] Error at '1': Expect variable name.\n\
+             // [java line 3] Error at end: Expect '}' after block.\n\
+             // [c line 3] Error: Not this one.\n",
+        );
+        assert_eq!(
+            expectations.compile_errors,
+            vec![
+                (1, "Expect expression.".to_string()),
+                (2, "Expect variable name.".to_string()),
+                (3, "Expect '}' after block.".to_string()),
+            ]
+        );
+
+        let stderr = "error[E0100]: Expect expression.\n --> [line 1, column 10]\n  |\n\
+                      error[E0101]: Expect variable name.\n --> [line 2, column 5]\n\
+                      warning[W0001]: local variable 'a' is never used.\n --> [line 3, column 1]\n\
+                      error[E0102]: Expect '}' after block.\n --> [line 3, column 1]\n";
+        assert!(expectations.check("", stderr, Some(65)).is_empty());
+        assert_eq!(
+            expectations.check(
+                "",
+                "error[E0100]: Expect expression.\n --> [line 4, column 1]\n",
+                Some(0)
+            ),
+            vec![
+                "Expected exit code 65 but got 0.",
+                "Expected error 'Expect expression.' on line 1.",
+                "Expected error 'Expect variable name.' on line 2.",
+                "Expected error 'Expect '}' after block.' on line 3.",
+                "Unexpected error 'Expect expression.' on line 4.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_lcov() {
+        let lcov = "SF:a.lox\nDA:1,1\nDA:3,0\nDA:4,12\nLH:2\nLF:3\nend_of_record\n";
+        assert_eq!(parse_lcov(lcov), vec![(1, true), (3, false), (4, true)]);
+    }
+}
@@ -0,0 +1,67 @@
+use rlox_treewalk::interpreter::{ExecutionHook, Interpreter, Types};
+use rlox_treewalk::scanner::Token;
+
+/// Prints each statement, call and return to stderr as a script runs,
+/// indented by how many calls are in progress.
+pub struct Tracer {
+    source: &'static str,
+}
+
+impl Tracer {
+    pub fn new(source: &'static str) -> Self {
+        Tracer { source }
+    }
+}
+
+impl ExecutionHook<'static> for Tracer {
+    fn before_statement(&self, interpreter: &Interpreter<'static>, token: &Token<'static>) {
+        eprintln!(
+            "{}{}",
+            indent(interpreter.call_depth()),
+            crate::describe_line(self.source, token)
+        );
+    }
+
+    fn on_call(&self, interpreter: &Interpreter<'static>, name: &str, arguments: &[Types]) {
+        let arguments: Vec<String> = arguments.iter().map(describe).collect();
+        eprintln!(
+            "{}call {}({})",
+            indent(interpreter.call_depth().saturating_sub(1)),
+            name,
+            arguments.join(", ")
+        );
+    }
+
+    fn on_return(&self, interpreter: &Interpreter<'static>, name: &str, value: Option<&Types>) {
+        let indent = indent(interpreter.call_depth().saturating_sub(1));
+        match value {
+            Some(value) => eprintln!("{}return {} from {}", indent, describe(value), name),
+            None => eprintln!("{}exit {}", indent, name),
+        }
+    }
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+/// A value as it would be written in Lox, so strings stand out from the
+/// names and numbers around them.
+fn describe(value: &Types) -> String {
+    match value {
+        Types::ReturnString(s) => format!("{:?}", s),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe(&Types::from("a\"b")), "\"a\\\"b\"");
+        assert_eq!(describe(&Types::Number(1.5)), "1.5");
+        assert_eq!(describe(&Types::Nil), "nil");
+    }
+}
@@ -0,0 +1,114 @@
+//! Runs the built interpreter the way a shell would, to check what only the
+//! command line does: exit codes and where the script comes from.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Runs the interpreter with `args`, feeding it `stdin`. It gets a home
+/// directory of its own, so nothing it keeps there, like REPL history, ends
+/// up in the real one.
+fn jlox(args: &[&str], stdin: &str) -> Output {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let home = std::env::temp_dir().join(format!(
+        "lox-cli-{}-home-{}",
+        process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&home).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox_treewalk"))
+        .args(args)
+        .env("HOME", &home)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("couldn't run the interpreter");
+    // It may well have exited without reading any of it.
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    let output = child.wait_with_output().unwrap();
+    // Not even the REPL keeps history of input that wasn't typed.
+    let kept: Vec<_> = fs::read_dir(&home).unwrap().collect();
+    assert!(kept.is_empty(), "{:?} wrote {:?}", args, kept);
+    fs::remove_dir_all(&home).unwrap();
+    output
+}
+
+/// Writes `source` to a script of its own in the temporary directory.
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lox-cli-{}-{}.lox", process::id(), name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn static_error_exit_code() {
+    let path = script("static", "print \"ran\";\nprint 1 +;\n");
+    let path = path.to_str().unwrap();
+    for args in [
+        vec![path],
+        vec!["--check", path],
+        // Tokens are only scanned, so this reads a scan error from stdin.
+        vec!["--emit=tokens", "-"],
+        vec!["--emit=ast-json", path],
+        vec!["--emit=dot", path],
+    ] {
+        let output = jlox(&args, "var a = @;");
+        assert_eq!(output.status.code(), Some(65), "{:?}", args);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+    }
+}
+
+#[test]
+fn runtime_error_exit_code() {
+    let path = script(
+        "runtime",
+        "print \"ran\";\nprint -nil;\nprint \"not ran\";\n",
+    );
+    let output = jlox(&[path.to_str().unwrap()], "");
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ran\n");
+}
+
+#[test]
+fn repl_keeps_going_after_errors() {
+    let output = jlox(&[], "print 1 +;\nprint -nil;\nprint \"still here\";\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("still here"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E0100"), "{}", stderr);
+    assert!(stderr.contains("Operand must be a number."), "{}", stderr);
+}
+
+#[test]
+fn script_from_stdin() {
+    let source = "var a = \"from\";\nprint a + \" stdin\";\n";
+    for args in [vec!["-"], vec!["--stdin"], vec!["-", "arg"]] {
+        let output = jlox(&args, source);
+        assert_eq!(output.status.code(), Some(0), "{:?}", args);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "from stdin\n",
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn exit_code() {
+    let output = jlox(&["-"], "print \"bye\";\nexit(3);\nprint \"not ran\";\n");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bye\n");
+
+    // The OS only keeps the low byte, so these would look like other codes.
+    for code in ["256", "300", "-1"] {
+        let output = jlox(&["-"], &format!("exit({});", code));
+        assert_eq!(output.status.code(), Some(70), "exit({})", code);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("from 0 to 255"), "{}", stderr);
+    }
+}
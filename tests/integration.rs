@@ -0,0 +1,77 @@
+//! Runs every `.lox` file under `testdata` and compares what it printed,
+//! warned about and failed with against the `.expected` file next to it.
+//!
+//! Run with `UPDATE_EXPECT=1` to write the `.expected` files from the
+//! current behavior instead, then review the changes to them.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use rlox_treewalk::diagnostic;
+use rlox_treewalk::host::CaptureHost;
+use rlox_treewalk::interpreter::Interpreter;
+use rlox_treewalk::test_runner::scripts;
+use rlox_treewalk::Lox;
+
+/// Everything running `path` wrote, followed by the error it stopped with.
+fn run(path: &Path) -> String {
+    let source = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("couldn't read {}: {}", path.display(), e));
+    let host = Rc::new(CaptureHost::new());
+    let interpreter = Interpreter::with_host(host.clone());
+    interpreter.set_script_path(path);
+    let lox = Lox::with_interpreter(interpreter);
+
+    let result = lox.run(&source);
+    let mut output = host.take_stdout() + &host.take_stderr();
+    if let Err(error) = result {
+        match diagnostic::from_error(&error) {
+            Some(diagnostics) => {
+                for diagnostic in diagnostics {
+                    output += &diagnostic.render(&source, false);
+                }
+            }
+            None => output += &format!("{}\n", error),
+        }
+    }
+    output
+}
+
+#[test]
+fn testdata() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+    let update = env::var_os("UPDATE_EXPECT").is_some_and(|value| value == "1");
+
+    let mut failures = vec![];
+    let scripts = scripts(&root).unwrap();
+    assert!(!scripts.is_empty(), "no scripts in {}", root.display());
+    for script in scripts {
+        let actual = run(&script);
+        let expected_path = script.with_extension("expected");
+        if update {
+            fs::write(&expected_path, &actual)
+                .unwrap_or_else(|e| panic!("couldn't write {}: {}", expected_path.display(), e));
+            continue;
+        }
+
+        let expected = fs::read_to_string(&expected_path)
+            .unwrap_or_else(|_| panic!("missing {}", expected_path.display()));
+        if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}--- actual\n{}",
+                script.strip_prefix(&root).unwrap_or(&script).display(),
+                expected,
+                actual
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} scripts didn't match (rerun with UPDATE_EXPECT=1 to accept):\n\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}